@@ -1,4 +1,11 @@
-use crate::{line, str_utils};
+use std::sync::Arc;
+#[cfg(feature = "lines")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "lines")]
+use crate::line;
+use crate::piece::Piece;
+use crate::str_utils;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum BufferType {
@@ -6,57 +13,342 @@ pub(crate) enum BufferType {
     Add,
 }
 
-#[derive(Debug)]
+/// The text of the `original` buffer, which is either borrowed or owned by the
+/// table.
+#[derive(Debug, Clone)]
+pub(crate) enum Original<'b> {
+    Borrowed(&'b str),
+    Owned(Arc<str>),
+    /// A memory mapped file, which is valid UTF-8 (see
+    /// [`Buffers::from_mapped`]).
+    #[cfg(feature = "mmap")]
+    Mapped(Arc<memmap2::Mmap>),
+}
+
+impl std::ops::Deref for Original<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Borrowed(text) => text,
+            Self::Owned(text) => text,
+            // SAFETY: the map was checked to be valid UTF-8 when the buffers
+            // were created (and it's immutable).
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => unsafe { std::str::from_utf8_unchecked(map) },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct Buffer<T> {
     pub(crate) content: T,
     #[cfg(feature = "lines")]
     pub(crate) line_breaks: Vec<(usize, line::Break)>,
 }
 
-#[derive(Debug)]
+/// The `original` buffer, split into the regions which were its pieces when
+/// the table was created.
+///
+/// No piece references more than one region, so the line breaks of the buffer
+/// are kept per region (like per segment of the `add` buffer), and each region
+/// is scanned for them only the first time they are needed.
+#[derive(Debug, Clone)]
+pub(crate) struct OriginalBuffer<'b> {
+    pub(crate) content: Original<'b>,
+    pub(crate) regions: Vec<Region>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Region {
+    pub(crate) range: std::ops::Range<usize>,
+    pub(crate) len_chars: usize,
+    #[cfg(feature = "lines")]
+    line_breaks: OnceLock<Vec<(usize, line::Break)>>,
+}
+
+impl Region {
+    fn new(range: std::ops::Range<usize>, text: &str) -> Self {
+        Self {
+            range,
+            len_chars: str_utils::count_chars(text),
+            #[cfg(feature = "lines")]
+            line_breaks: OnceLock::new(),
+        }
+    }
+}
+
+/// The buffers of a table. Cloning them is cheap, as the contents are shared
+/// between the clones.
+///
+/// The `add` buffer is split into segments, each occupying `SEGMENT_LEN` bytes
+/// of the buffer's index space (so the text at `idx` is in the
+/// `idx / SEGMENT_LEN`-th segment). Only the last segment is ever appended to,
+/// thus appending to a buffer which is shared with a clone copies at most one
+/// segment, and text is never moved once it was appended.
+#[derive(Debug, Clone)]
 pub(crate) struct Buffers<'b> {
-    pub(crate) original: Buffer<&'b str>,
-    pub(crate) add: Buffer<String>,
+    pub(crate) original: Arc<OriginalBuffer<'b>>,
+    pub(crate) add: Vec<Arc<Buffer<String>>>,
+    /// Which line breaks the buffers are scanned for.
+    #[cfg(feature = "lines")]
+    pub(crate) line_break_config: line::LineBreakConfig,
 }
 
 impl<'b> Buffers<'b> {
-    pub(crate) fn from_initial(initial: &'b str) -> Self {
-        let mut line_breaks = vec![];
-        str_utils::line_breaks(initial, &mut line_breaks, 0);
+    /// The amount of bytes in the index space of an `add` buffer segment.
+    const SEGMENT_LEN: usize = 1 << 16;
+
+    /// Create buffers with `original` as the `original` buffer, and split it
+    /// into regions (see [`OriginalBuffer`]).
+    ///
+    /// Runs in `O(N)` where `N` is the length of `original`, to count its
+    /// chars, but its line breaks are not scanned yet.
+    pub(crate) fn from_original(original: Original<'b>) -> Self {
+        let regions =
+            str_utils::chunk_ranges(original.as_bytes(), Piece::MAX_LEN_BYTES)
+                .map(|range| Region::new(range.clone(), &original[range]))
+                .collect();
+        Self::from_regions(original, regions)
+    }
+
+    /// Create buffers with a memory mapped file as the `original` buffer,
+    /// after checking that it is valid UTF-8.
+    ///
+    /// Every region is checked right before its chars are counted, so each
+    /// page of the file is read once.
+    ///
+    /// # Errors
+    ///
+    /// Returns the index of the first byte which is not valid UTF-8.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn from_mapped(
+        map: memmap2::Mmap,
+    ) -> Result<Buffers<'static>, usize> {
+        let mut regions = vec![];
+        for range in str_utils::chunk_ranges(&map, Piece::MAX_LEN_BYTES) {
+            let text = std::str::from_utf8(&map[range.clone()])
+                .map_err(|e| range.start + e.valid_up_to())?;
+            regions.push(Region::new(range, text));
+        }
+        Ok(Buffers::from_regions(
+            Original::Mapped(Arc::new(map)),
+            regions,
+        ))
+    }
 
+    fn from_regions(original: Original<'b>, regions: Vec<Region>) -> Self {
         Self {
-            original: Buffer {
-                content: initial,
-                #[cfg(feature = "lines")]
-                line_breaks,
-            },
-            add: Buffer {
-                content: String::new(),
-                #[cfg(feature = "lines")]
-                line_breaks: vec![],
-            },
+            original: Arc::new(OriginalBuffer { content: original, regions }),
+            add: vec![],
+            #[cfg(feature = "lines")]
+            line_break_config: line::LineBreakConfig::default(),
+        }
+    }
+
+    /// Scan the line breaks of all the buffers again, according to `config`.
+    /// Buffers which are shared with clones are copied.
+    ///
+    /// Only the `add` buffer is scanned right away: the regions of the
+    /// `original` buffer are scanned again the next time they are needed.
+    /// Scanning a whole segment finds the same line breaks as scanning it in
+    /// the parts it was appended in, as no part ends between the CR and the LF
+    /// of a CRLF sequence (see [`Buffers::append`]).
+    #[cfg(feature = "lines")]
+    pub(crate) fn rescan(&mut self, config: line::LineBreakConfig) {
+        self.line_break_config = config;
+
+        let original = Arc::make_mut(&mut self.original);
+        for region in &mut original.regions {
+            region.line_breaks = OnceLock::new();
+        }
+
+        for (idx, segment) in self.add.iter_mut().enumerate() {
+            let segment = Arc::make_mut(segment);
+            segment.line_breaks.clear();
+            str_utils::line_breaks(
+                &segment.content,
+                &mut segment.line_breaks,
+                idx * Self::SEGMENT_LEN,
+                config,
+            );
         }
     }
 
+    /// Make the buffers own the `original` buffer, by copying it if it is
+    /// borrowed. The indexes in the buffers stay the same.
+    pub(crate) fn into_owned(self) -> Buffers<'static> {
+        let original = Arc::unwrap_or_clone(self.original);
+        let content = match original.content {
+            Original::Borrowed(text) => Original::Owned(text.into()),
+            Original::Owned(text) => Original::Owned(text),
+            #[cfg(feature = "mmap")]
+            Original::Mapped(map) => Original::Mapped(map),
+        };
+
+        Buffers {
+            original: Arc::new(OriginalBuffer {
+                content,
+                regions: original.regions,
+            }),
+            add: self.add,
+            #[cfg(feature = "lines")]
+            line_break_config: self.line_break_config,
+        }
+    }
+
+    /// Append `text` to the end of the `add` buffer, split into chunks of at
+    /// most `max_len` bytes (see [`str_utils::chunk_ranges`]), and return the
+    /// ranges they occupy in it. Every chunk is contained in a single segment.
+    ///
+    /// If the last segment ends with a CR and `text` starts with an LF, a
+    /// padding byte (which no piece references) is appended between them, so
+    /// that they are never scanned as a CRLF sequence when rescanning the
+    /// segment (see [`Buffers::rescan`]).
+    pub(crate) fn append(
+        &mut self,
+        text: &str,
+        max_len: usize,
+    ) -> Vec<std::ops::Range<usize>> {
+        debug_assert!(max_len <= Self::SEGMENT_LEN);
+
+        let mut ranges = vec![];
+        for range in str_utils::chunk_ranges(text.as_bytes(), max_len) {
+            let chunk = &text[range];
+            #[cfg(feature = "lines")]
+            let pad = self.add.last().is_some_and(|s| {
+                s.content.ends_with('\r') && chunk.starts_with('\n')
+            });
+            #[cfg(not(feature = "lines"))]
+            let pad = false;
+
+            if !self.fits_last_segment(usize::from(pad) + chunk.len()) {
+                self.add.push(Arc::new(Buffer {
+                    content: String::with_capacity(Self::SEGMENT_LEN),
+                    #[cfg(feature = "lines")]
+                    line_breaks: vec![],
+                }));
+            }
+
+            let Some(segment) = self.add.last_mut() else {
+                unreachable!("a segment was pushed if there was none")
+            };
+            if pad && !segment.content.is_empty() {
+                Arc::make_mut(segment).content.push('\0');
+            }
+
+            let start = self.add_len();
+            let Some(segment) = self.add.last_mut() else {
+                unreachable!("a segment was pushed if there was none")
+            };
+            let segment = Arc::make_mut(segment);
+            #[cfg(feature = "lines")]
+            str_utils::line_breaks(
+                chunk,
+                &mut segment.line_breaks,
+                start,
+                self.line_break_config,
+            );
+            segment.content.push_str(chunk);
+
+            ranges.push(start..start + chunk.len());
+        }
+        ranges
+    }
+
+    /// Whether `len` bytes can be appended to the last segment of the `add`
+    /// buffer, i.e., whether appending them will make them contiguous to the
+    /// current end of the buffer.
+    pub(crate) fn fits_last_segment(&self, len: usize) -> bool {
+        self.add
+            .last()
+            .is_some_and(|s| s.content.len() + len <= Self::SEGMENT_LEN)
+    }
+
+    /// The index of the end of the `add` buffer.
+    pub(crate) fn add_len(&self) -> usize {
+        match self.add.last() {
+            Some(segment) => {
+                (self.add.len() - 1) * Self::SEGMENT_LEN + segment.content.len()
+            }
+            None => 0,
+        }
+    }
+
+    /// The text in `byte_range` of the `ty` buffer. For the `add` buffer, the
+    /// range must be contained in a single segment.
+    pub(crate) fn text(
+        &self,
+        ty: BufferType,
+        byte_range: std::ops::Range<usize>,
+    ) -> &str {
+        match ty {
+            BufferType::Original => &self.original.content[byte_range],
+            BufferType::Add if byte_range.is_empty() => "",
+            BufferType::Add => {
+                let (segment, offset) = self.segment(byte_range.start);
+                &segment.content[byte_range.start - offset..]
+                    [..byte_range.len()]
+            }
+        }
+    }
+
+    /// The line breaks of the region of the `original` buffer (or of the
+    /// segment of the `add` buffer) which contains `byte_idx`, sorted by their
+    /// index. A region is scanned for them the first time they are needed.
     #[cfg(feature = "lines")]
     pub(crate) fn line_breaks(
         &self,
         ty: BufferType,
+        byte_idx: usize,
     ) -> &[(usize, line::Break)] {
         match ty {
-            BufferType::Original => &self.original.line_breaks,
-            BufferType::Add => &self.add.line_breaks,
+            BufferType::Original => {
+                let regions = &self.original.regions;
+                let region = &regions
+                    [regions.partition_point(|r| r.range.end <= byte_idx)];
+                region.line_breaks.get_or_init(|| {
+                    let mut line_breaks = vec![];
+                    str_utils::line_breaks(
+                        &self.original.content[region.range.clone()],
+                        &mut line_breaks,
+                        region.range.start,
+                        self.line_break_config,
+                    );
+                    line_breaks
+                })
+            }
+            BufferType::Add => &self.segment(byte_idx).0.line_breaks,
         }
     }
-}
 
-impl<'b> std::ops::Index<BufferType> for Buffers<'b> {
-    type Output = str;
-
-    fn index(&self, index: BufferType) -> &Self::Output {
-        match index {
-            BufferType::Original => self.original.content,
-            BufferType::Add => &self.add.content,
+    /// The range of indexes in the line breaks of `ty` (see
+    /// [`Buffers::line_breaks`]), of the line breaks that start inside
+    /// `byte_range`.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of line breaks in the region
+    /// (or the segment), once it was scanned.
+    #[cfg(feature = "lines")]
+    pub(crate) fn line_breaks_in(
+        &self,
+        ty: BufferType,
+        byte_range: std::ops::Range<usize>,
+    ) -> std::ops::Range<usize> {
+        if byte_range.is_empty() {
+            return 0..0;
         }
+
+        let lbs = self.line_breaks(ty, byte_range.start);
+        let start = lbs.partition_point(|(idx, _ty)| *idx < byte_range.start);
+        let len =
+            lbs[start..].partition_point(|(idx, _ty)| *idx < byte_range.end);
+        start..start + len
+    }
+
+    /// The segment of the `add` buffer which contains `byte_idx`, and the index
+    /// of its start.
+    fn segment(&self, byte_idx: usize) -> (&Buffer<String>, usize) {
+        let idx = byte_idx / Self::SEGMENT_LEN;
+        (&self.add[idx], idx * Self::SEGMENT_LEN)
     }
 }
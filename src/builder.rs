@@ -0,0 +1,182 @@
+//! Building tables from text which arrives in parts, and extending them.
+
+#[cfg(feature = "lines")]
+use crate::LineBreakConfig;
+use crate::PieceTable;
+use crate::buffer::BufferType;
+use crate::piece::Piece;
+
+/// Builds a table by appending text to its end, without recording the text in
+/// the history of the table.
+///
+/// The text is gathered into pieces of about [`Piece::MAX_LEN_BYTES`] bytes,
+/// so appending many small parts doesn't create many small pieces. A CR at
+/// the end of a part is held back until the next part arrives, so a CRLF
+/// sequence which is split between parts is still a single line break.
+pub(crate) struct Builder {
+    table: PieceTable<'static>,
+    pending: String,
+}
+
+impl Builder {
+    pub(crate) fn new() -> Self {
+        Self::with_table(PieceTable::new(""))
+    }
+
+    /// Create a builder of a table in which the line breaks are the ones
+    /// selected by `config`, so the text is scanned only for them.
+    #[cfg(feature = "lines")]
+    pub(crate) fn with_line_breaks(config: LineBreakConfig) -> Self {
+        Self::with_table(PieceTable::new_with_line_breaks("", config))
+    }
+
+    fn with_table(table: PieceTable<'static>) -> Self {
+        Self { table, pending: String::with_capacity(Piece::MAX_LEN_BYTES + 1) }
+    }
+
+    pub(crate) fn push_str(&mut self, text: &str) {
+        for range in crate::str_utils::chunk_ranges(
+            text.as_bytes(),
+            Piece::MAX_LEN_BYTES,
+        ) {
+            if self.pending.len() + range.len() > Piece::MAX_LEN_BYTES {
+                self.flush();
+            }
+            self.pending.push_str(&text[range]);
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> PieceTable<'static> {
+        self.push_pending(self.pending.len());
+        self.table
+    }
+
+    /// Push the pending text to the table, except for a CR at its end.
+    fn flush(&mut self) {
+        let len =
+            self.pending.strip_suffix('\r').unwrap_or(&self.pending).len();
+        self.push_pending(len);
+    }
+
+    /// Push the first `len` bytes of the pending text to the table.
+    fn push_pending(&mut self, len: usize) {
+        let table = &mut self.table;
+        for range in
+            table.buffers.append(&self.pending[..len], Piece::MAX_LEN_BYTES)
+        {
+            let piece = Piece::new(&table.buffers, BufferType::Add, range);
+            table.tree.push_back(piece);
+        }
+        self.pending.drain(..len);
+    }
+}
+
+/// Create a table which owns its contents, from the concatenation of `&str`
+/// parts.
+///
+/// # Examples
+///
+/// ```
+/// # use peace_table::PieceTable;
+/// let pt = ["lorem", " ", "ipsum"].into_iter().collect::<PieceTable>();
+/// assert_eq!(pt.text(), "lorem ipsum");
+/// ```
+impl<'a> FromIterator<&'a str> for PieceTable<'static> {
+    fn from_iter<T: IntoIterator<Item = &'a str>>(iter: T) -> Self {
+        let mut builder = Builder::new();
+        iter.into_iter().for_each(|s| builder.push_str(s));
+        builder.finish()
+    }
+}
+
+/// Create a table which owns its contents, from chars.
+///
+/// # Examples
+///
+/// ```
+/// # use peace_table::PieceTable;
+/// let pt = "abc".chars().rev().collect::<PieceTable>();
+/// assert_eq!(pt.text(), "cba");
+/// ```
+impl FromIterator<char> for PieceTable<'static> {
+    fn from_iter<T: IntoIterator<Item = char>>(iter: T) -> Self {
+        let mut builder = Builder::new();
+        let mut buf = [0; 4];
+        iter.into_iter()
+            .for_each(|ch| builder.push_str(ch.encode_utf8(&mut buf)));
+        builder.finish()
+    }
+}
+
+/// Append `&str` parts to the end of the table, as a single step in its
+/// history (see [`PieceTable::undo`]).
+///
+/// # Examples
+///
+/// ```
+/// # use peace_table::PieceTable;
+/// let mut pt = PieceTable::new("lorem");
+/// pt.extend([" ", "ipsum", " ", "dolor"]);
+/// assert_eq!(pt.text(), "lorem ipsum dolor");
+/// pt.undo();
+/// assert_eq!(pt.text(), "lorem");
+/// ```
+impl<'a> Extend<&'a str> for PieceTable<'_> {
+    fn extend<T: IntoIterator<Item = &'a str>>(&mut self, iter: T) {
+        self.begin_group(&[self.len_chars()]);
+        for text in iter {
+            self.insert(self.len_chars(), text);
+        }
+        self.end_group(&[self.len_chars()]);
+    }
+}
+
+/// Append chars to the end of the table, as a single step in its history (see
+/// [`PieceTable::undo`]).
+///
+/// # Examples
+///
+/// ```
+/// # use peace_table::PieceTable;
+/// let mut pt = PieceTable::new("abc");
+/// pt.extend("def".chars().rev());
+/// assert_eq!(pt.text(), "abcfed");
+/// ```
+impl Extend<char> for PieceTable<'_> {
+    fn extend<T: IntoIterator<Item = char>>(&mut self, iter: T) {
+        let mut text = String::new();
+        self.begin_group(&[self.len_chars()]);
+        for ch in iter {
+            text.push(ch);
+            if text.len() >= Piece::MAX_LEN_BYTES {
+                self.insert(self.len_chars(), &text);
+                text.clear();
+            }
+        }
+        self.insert(self.len_chars(), &text);
+        self.end_group(&[self.len_chars()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_parts() {
+        let parts = ["ab\r", "\ncd", "🦀", "\r", "", "\n", "\r"];
+        let expected = parts.concat().repeat(5000);
+
+        let pt = parts.iter().cycle().take(parts.len() * 5000).copied();
+        let pt = pt.collect::<PieceTable>();
+        assert_eq!(pt.text(), expected);
+        assert!(pt.tree.len() <= expected.len() / Piece::MAX_LEN_BYTES + 2);
+        #[cfg(feature = "lines")]
+        assert_eq!(pt.len_lines(), PieceTable::new(&expected).len_lines());
+
+        let pt = expected.chars().collect::<PieceTable>();
+        assert_eq!(pt.text(), expected);
+        #[cfg(feature = "lines")]
+        assert_eq!(pt.len_lines(), PieceTable::new(&expected).len_lines());
+    }
+}
@@ -0,0 +1,670 @@
+//! Change sets: edits to several ranges of a table, which are made at once.
+//!
+//! A change set walks the text it applies to from its start, keeping,
+//! removing and inserting text as it goes, so every edit in it refers to the
+//! text before the change set, and the caller never has to shift the indexes
+//! of the later edits by hand.
+
+use std::ops::Range;
+
+use crate::error::{self, Error};
+use crate::{PieceTable, str_utils};
+
+/// An operation of a [`ChangeSet`], made at the position the previous
+/// operations reached in the text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Keep the next chars.
+    Retain(usize),
+    /// Remove the next chars.
+    Delete(usize),
+    /// Insert text before the next chars.
+    Insert(String),
+}
+
+/// The side of the text inserted at a position that the position is mapped
+/// to (see [`ChangeSet::map_pos`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assoc {
+    Before,
+    After,
+}
+
+/// A set of edits to several ranges of a text, which are applied together (see
+/// [`PieceTable::apply`]) as a single step in the history of the table.
+///
+/// A change set is a sequence of [`Operation`]s which covers the whole text it
+/// applies to. Adjacent operations of the same kind are merged, and an
+/// insertion always comes before a deletion at the same position, so equal
+/// edits make equal change sets.
+///
+/// # Examples
+///
+/// ```
+/// # use peace_table::{ChangeSet, PieceTable};
+/// let mut pt = PieceTable::new("a b c");
+/// let edits = [(1..1, "!"), (2..4, ""), (5..5, "?")];
+/// let changes = ChangeSet::from_edits(pt.len_chars(), edits).unwrap();
+/// pt.apply(&changes);
+/// assert_eq!(pt.text(), "a! c?");
+///
+/// let mut changes = ChangeSet::new();
+/// changes.retain(3).delete(2).insert("d");
+/// pt.apply(&changes);
+/// assert_eq!(pt.text(), "a! d");
+///
+/// pt.undo();
+/// assert_eq!(pt.text(), "a! c?");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ChangeSet {
+    operations: Vec<Operation>,
+    /// The length (in chars) of the text the change set applies to.
+    len_before: usize,
+    /// The length (in chars) of the text after applying the change set.
+    len_after: usize,
+}
+
+impl ChangeSet {
+    /// Create an empty change set, which applies to an empty text.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a change set which replaces the chars in every range of `edits`
+    /// with its text, in a text of `len` chars. The ranges must be sorted and
+    /// must not overlap (but several edits can insert at the same index).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidRange`] if the start of a range is larger than
+    /// its end, [`Error::OutOfBounds`] if the end of a range is larger than
+    /// `len`, and [`Error::OverlappingRanges`] if a range starts before the
+    /// end of the previous one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{ChangeSet, Error};
+    /// let changes = ChangeSet::from_edits(5, [(0..2, "x"), (2..2, "y")]);
+    /// assert_eq!(changes.map(|c| c.len_after()), Ok(5));
+    ///
+    /// let changes = ChangeSet::from_edits(5, [(1..3, "x"), (2..4, "y")]);
+    /// assert_eq!(
+    ///     changes,
+    ///     Err(Error::OverlappingRanges { prev_end: 3, start: 2 })
+    /// );
+    /// ```
+    pub fn from_edits<I, T>(len: usize, edits: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (Range<usize>, T)>,
+        T: AsRef<str>,
+    {
+        let mut changes = Self::new();
+        let mut pos = 0;
+        for (range, text) in edits {
+            error::check_range(range.start, range.end, len)?;
+            if range.start < pos {
+                return Err(Error::OverlappingRanges {
+                    prev_end: pos,
+                    start: range.start,
+                });
+            }
+
+            changes
+                .retain(range.start - pos)
+                .delete(range.len())
+                .insert(text.as_ref());
+            pos = range.end;
+        }
+
+        changes.retain(len - pos);
+        Ok(changes)
+    }
+
+    /// Keep the next `len_chars` chars.
+    pub fn retain(&mut self, len_chars: usize) -> &mut Self {
+        if len_chars == 0 {
+            return self;
+        }
+
+        self.len_before += len_chars;
+        self.len_after += len_chars;
+        match self.operations.last_mut() {
+            Some(Operation::Retain(len)) => *len += len_chars,
+            _ => self.operations.push(Operation::Retain(len_chars)),
+        }
+        self
+    }
+
+    /// Remove the next `len_chars` chars.
+    pub fn delete(&mut self, len_chars: usize) -> &mut Self {
+        if len_chars == 0 {
+            return self;
+        }
+
+        self.len_before += len_chars;
+        match self.operations.last_mut() {
+            Some(Operation::Delete(len)) => *len += len_chars,
+            _ => self.operations.push(Operation::Delete(len_chars)),
+        }
+        self
+    }
+
+    /// Insert `text` before the next chars.
+    pub fn insert(&mut self, text: &str) -> &mut Self {
+        if text.is_empty() {
+            return self;
+        }
+
+        self.len_after += str_utils::count_chars(text);
+        match self.operations.as_mut_slice() {
+            [.., Operation::Insert(last)]
+            | [.., Operation::Insert(last), Operation::Delete(_)] => {
+                last.push_str(text);
+            }
+            [.., Operation::Delete(_)] => {
+                let delete = self.operations.pop();
+                self.operations.push(Operation::Insert(text.to_owned()));
+                self.operations.extend(delete);
+            }
+            _ => self.operations.push(Operation::Insert(text.to_owned())),
+        }
+        self
+    }
+
+    /// The operations of the change set, in order.
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// The length (in chars) of the text the change set applies to.
+    pub const fn len_before(&self) -> usize {
+        self.len_before
+    }
+
+    /// The length (in chars) of the text after applying the change set.
+    pub const fn len_after(&self) -> usize {
+        self.len_after
+    }
+
+    /// Whether applying the change set keeps the text as it is.
+    pub fn is_identity(&self) -> bool {
+        self.operations.iter().all(|op| matches!(op, Operation::Retain(_)))
+    }
+
+    /// Map `char_idx` in the text before the change set to the matching index
+    /// in the text after it. An index inside a removed range is mapped to the
+    /// index of the removal, and `assoc` decides whether an index that text is
+    /// inserted at is mapped to before or after the inserted text.
+    ///
+    /// This is how cursors (other than the ones making the edits) are kept at
+    /// their places in the text. Runs in `O(M)` where `M` is the amount of
+    /// operations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{Assoc, ChangeSet};
+    /// let mut changes = ChangeSet::new();
+    /// changes.retain(2).insert("ab").retain(1).delete(3).retain(1);
+    /// assert_eq!(changes.map_pos(2, Assoc::Before), 2);
+    /// assert_eq!(changes.map_pos(2, Assoc::After), 4);
+    /// assert_eq!(changes.map_pos(5, Assoc::After), 5);
+    /// assert_eq!(changes.map_pos(6, Assoc::After), 5);
+    /// assert_eq!(changes.map_pos(7, Assoc::After), 6);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the length of the text the
+    /// change set applies to.
+    pub fn map_pos(&self, char_idx: usize, assoc: Assoc) -> usize {
+        error::unwrap(error::check_idx(char_idx, self.len_before));
+
+        // The index the operations reached, in the text before and after.
+        let (mut old, mut new) = (0, 0);
+        for op in &self.operations {
+            match op {
+                Operation::Retain(len) => {
+                    if char_idx < old + len {
+                        return new + (char_idx - old);
+                    }
+                    old += len;
+                    new += len;
+                }
+                Operation::Delete(len) => {
+                    if char_idx < old + len {
+                        return new;
+                    }
+                    old += len;
+                }
+                Operation::Insert(text) => {
+                    if char_idx == old && assoc == Assoc::Before {
+                        return new;
+                    }
+                    new += str_utils::count_chars(text);
+                }
+            }
+        }
+        new
+    }
+
+    /// Returns the change set which reverts this one, given the `original`
+    /// table this one applies to.
+    ///
+    /// Runs in `O(N + M log P)` where `N` is the length of the removed text,
+    /// `M` is the amount of operations and `P` is the amount of pieces.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{ChangeSet, PieceTable};
+    /// let mut pt = PieceTable::new("hello world");
+    /// let edits = [(0..1, "H"), (6..11, "there")];
+    /// let changes = ChangeSet::from_edits(pt.len_chars(), edits).unwrap();
+    /// let inverted = changes.invert(&pt);
+    ///
+    /// pt.apply(&changes);
+    /// assert_eq!(pt.text(), "Hello there");
+    /// pt.apply(&inverted);
+    /// assert_eq!(pt.text(), "hello world");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the length of `original` is not the length of the text
+    /// the change set applies to.
+    pub fn invert(&self, original: &PieceTable) -> Self {
+        error::unwrap(self.check_len(original.len_chars()));
+
+        let mut inverted = Self::new();
+        let mut pos = 0;
+        for op in &self.operations {
+            match op {
+                Operation::Retain(len) => {
+                    inverted.retain(*len);
+                    pos += len;
+                }
+                Operation::Delete(len) => {
+                    let removed = original.slice(pos..pos + len).to_string();
+                    inverted.insert(&removed);
+                    pos += len;
+                }
+                Operation::Insert(text) => {
+                    inverted.delete(str_utils::count_chars(text));
+                }
+            }
+        }
+        inverted
+    }
+
+    /// Returns the change set which makes the changes of this one and then
+    /// the changes of `other`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{ChangeSet, PieceTable};
+    /// let mut pt = PieceTable::new("abc");
+    /// let first = ChangeSet::from_edits(3, [(1..2, "xyz")]).unwrap();
+    /// let second = ChangeSet::from_edits(5, [(0..3, ""), (4..4, "!")]).unwrap();
+    /// pt.apply(&first.compose(second));
+    /// assert_eq!(pt.text(), "z!c");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `other` does not apply to the text after this change
+    /// set.
+    pub fn compose(self, other: Self) -> Self {
+        error::unwrap(self.try_compose(other))
+    }
+
+    /// Like [`ChangeSet::compose`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::LenMismatch`] if `other` does not apply to the text
+    /// after this change set.
+    pub fn try_compose(self, other: Self) -> Result<Self, Error> {
+        other.check_len(self.len_after)?;
+
+        let mut composed = Self::new();
+        let mut first = self.operations.into_iter();
+        let mut second = other.operations.into_iter();
+        let (mut a, mut b) = (first.next(), second.next());
+
+        // Every iteration consumes (a part of) an operation of either change
+        // set. The parts that remain are put back into `a` and `b`.
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => break,
+                (Some(Operation::Delete(len)), op) => {
+                    composed.delete(len);
+                    a = first.next();
+                    b = op;
+                }
+                (op, Some(Operation::Insert(text))) => {
+                    composed.insert(&text);
+                    a = op;
+                    b = second.next();
+                }
+                (Some(Operation::Retain(i)), Some(Operation::Retain(j))) => {
+                    composed.retain(i.min(j));
+                    a = rest(i, j, Operation::Retain).or_else(|| first.next());
+                    b = rest(j, i, Operation::Retain).or_else(|| second.next());
+                }
+                (Some(Operation::Retain(i)), Some(Operation::Delete(j))) => {
+                    composed.delete(i.min(j));
+                    a = rest(i, j, Operation::Retain).or_else(|| first.next());
+                    b = rest(j, i, Operation::Delete).or_else(|| second.next());
+                }
+                (Some(Operation::Insert(text)), Some(Operation::Retain(j))) => {
+                    let len = str_utils::count_chars(&text);
+                    let split = str_utils::char_to_byte(&text, len.min(j));
+                    composed.insert(&text[..split]);
+                    a = rest_of_insert(text, split).or_else(|| first.next());
+                    b = rest(j, len, Operation::Retain)
+                        .or_else(|| second.next());
+                }
+                (Some(Operation::Insert(text)), Some(Operation::Delete(j))) => {
+                    // The inserted text is deleted right away.
+                    let len = str_utils::count_chars(&text);
+                    let split = str_utils::char_to_byte(&text, len.min(j));
+                    a = rest_of_insert(text, split).or_else(|| first.next());
+                    b = rest(j, len, Operation::Delete)
+                        .or_else(|| second.next());
+                }
+                (None, Some(_)) | (Some(_), None) => {
+                    unreachable!("the lengths of the change sets match")
+                }
+            }
+        }
+
+        Ok(composed)
+    }
+
+    /// Check that the change set applies to a text of `len` chars.
+    fn check_len(&self, len: usize) -> Result<(), Error> {
+        if self.len_before == len {
+            Ok(())
+        } else {
+            Err(Error::LenMismatch { len, expected: self.len_before })
+        }
+    }
+}
+
+/// The part of an operation of length `len` which remains after consuming
+/// `consumed` chars of it, if there is any.
+fn rest(
+    len: usize,
+    consumed: usize,
+    op: impl FnOnce(usize) -> Operation,
+) -> Option<Operation> {
+    (len > consumed).then(|| op(len - consumed))
+}
+
+/// The part of an insertion of `text` which remains after consuming its first
+/// `split` bytes, if there is any.
+fn rest_of_insert(mut text: String, split: usize) -> Option<Operation> {
+    (split < text.len()).then(|| Operation::Insert(text.split_off(split)))
+}
+
+impl PieceTable<'_> {
+    /// Apply `changes` to the table, as a single step in the history of the
+    /// table.
+    ///
+    /// The pieces of the table are rebuilt in a single pass, so this runs in
+    /// `O(N + M log P)` where `N` is the length of the edited part of the
+    /// table (from the start of the first edit to the end of the last),
+    /// `M` is the amount of edits and `P` is the amount of pieces.
+    ///
+    /// Edits which split a CRLF sequence are rejected if the table's
+    /// [`CrlfPolicy`] is [`CrlfPolicy::Reject`], and are made as they are
+    /// otherwise, as snapping them would change the text the change set (and
+    /// its inverse) expect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{Assoc, ChangeSet, PieceTable};
+    /// let mut pt = PieceTable::new("let a = b;\nlet c = d;");
+    /// let cursors = [0, 11];
+    /// let edits = cursors.map(|i| (i..i + 3, "const"));
+    /// let changes = ChangeSet::from_edits(pt.len_chars(), edits).unwrap();
+    ///
+    /// pt.apply(&changes);
+    /// assert_eq!(pt.text(), "const a = b;\nconst c = d;");
+    /// let cursors = cursors.map(|i| changes.map_pos(i + 3, Assoc::After));
+    /// assert_eq!(cursors, [5, 18]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the change set does not apply to a text of the length of
+    /// the table, or if an edit splits a CRLF sequence (and the table's
+    /// [`CrlfPolicy`] rejects it).
+    ///
+    /// [`CrlfPolicy`]: crate::CrlfPolicy
+    /// [`CrlfPolicy::Reject`]: crate::CrlfPolicy::Reject
+    pub fn apply(&mut self, changes: &ChangeSet) {
+        error::unwrap(self.try_apply(changes));
+    }
+
+    /// Like [`PieceTable::apply`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::LenMismatch`] if the change set does not apply to a
+    /// text of the length of the table, and [`Error::CrlfSplit`] if the start
+    /// or the end of an edit are between the CR and the LF of a CRLF sequence
+    /// (and the table's [`CrlfPolicy`] rejects it).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{ChangeSet, Error, PieceTable};
+    /// let mut pt = PieceTable::new("a\r\nb");
+    /// let changes = ChangeSet::from_edits(3, [(1..2, "")]).unwrap();
+    /// assert_eq!(
+    ///     pt.try_apply(&changes),
+    ///     Err(Error::LenMismatch { len: 4, expected: 3 })
+    /// );
+    /// let changes = ChangeSet::from_edits(4, [(2..2, "c")]).unwrap();
+    /// assert_eq!(
+    ///     pt.try_apply(&changes),
+    ///     Err(Error::CrlfSplit { char_idx: 2 })
+    /// );
+    /// ```
+    ///
+    /// [`CrlfPolicy`]: crate::CrlfPolicy
+    pub fn try_apply(&mut self, changes: &ChangeSet) -> Result<(), Error> {
+        changes.check_len(self.len_chars())?;
+
+        // Every edit is an insertion, a deletion, or an insertion followed by
+        // a deletion, between retained chars.
+        let mut ranges: Vec<Range<usize>> = vec![];
+        let mut texts = vec![];
+        let mut pos = 0;
+        let mut in_edit = false;
+        for op in &changes.operations {
+            if !in_edit && !matches!(op, Operation::Retain(_)) {
+                ranges.push(pos..pos);
+                texts.push("");
+            }
+            in_edit = !matches!(op, Operation::Retain(_));
+
+            match op {
+                Operation::Retain(len) => pos += len,
+                Operation::Delete(len) => {
+                    pos += len;
+                    if let Some(range) = ranges.last_mut() {
+                        range.end = pos;
+                    }
+                }
+                Operation::Insert(text) => {
+                    if let Some(last) = texts.last_mut() {
+                        *last = text.as_str();
+                    }
+                }
+            }
+        }
+        if ranges.is_empty() {
+            return Ok(());
+        }
+
+        self.check_crlf_splits(&ranges)?;
+
+        #[cfg(feature = "lines")]
+        for (range, text) in ranges.iter().zip(&texts).rev() {
+            self.record_replace(range.start, range.end, text);
+        }
+        let pieces: Vec<_> =
+            texts.iter().map(|text| self.append(text)).collect();
+        self.replace_ranges(&ranges, pieces.into_iter());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CrlfPolicy;
+
+    /// Apply `changes` to `text`, the slow way.
+    fn apply_str(changes: &ChangeSet, text: &str) -> String {
+        let mut chars = text.chars();
+        let mut applied = String::new();
+        for op in changes.operations() {
+            match op {
+                Operation::Retain(len) => {
+                    applied.extend(chars.by_ref().take(*len));
+                }
+                Operation::Delete(len) => {
+                    chars.by_ref().take(*len).for_each(drop);
+                }
+                Operation::Insert(text) => applied.push_str(text),
+            }
+        }
+        assert_eq!(chars.next(), None);
+        applied
+    }
+
+    /// A change set for a text of `len` chars, made of `seed`.
+    fn changes(len: usize, seed: usize) -> ChangeSet {
+        let mut changes = ChangeSet::new();
+        let mut i = seed;
+        while changes.len_before() < len {
+            i = i.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let n = (i >> 33) % 4;
+            match (i >> 40) % 3 {
+                0 => changes.retain(n),
+                1 => changes.delete(n),
+                _ => changes.insert(["\r", "\n", "é🦀", "xy"][n]),
+            };
+            if changes.len_before() > len {
+                changes = ChangeSet::new();
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn normalized() {
+        let mut changes = ChangeSet::new();
+        changes
+            .retain(1)
+            .retain(0)
+            .delete(2)
+            .insert("a")
+            .delete(1)
+            .insert("")
+            .insert("b")
+            .retain(2);
+        assert_eq!(
+            changes.operations(),
+            [
+                Operation::Retain(1),
+                Operation::Insert("ab".to_owned()),
+                Operation::Delete(3),
+                Operation::Retain(2),
+            ]
+        );
+        assert_eq!((changes.len_before(), changes.len_after()), (6, 5));
+
+        let edits = [(1..4, "a"), (4..4, "b")];
+        assert_eq!(ChangeSet::from_edits(6, edits), Ok(changes.clone()));
+        let edits = [(1..1, "a"), (1..4, "b")];
+        assert_eq!(ChangeSet::from_edits(6, edits), Ok(changes));
+    }
+
+    #[test]
+    fn apply_invert_compose() {
+        let mut pt = PieceTable::new("a\r\nb\rc\nd\r\n");
+        pt.set_crlf_policy(CrlfPolicy::Split);
+
+        for seed in 0..200 {
+            let text = pt.text();
+            let first = changes(pt.len_chars(), seed);
+            let inverted = first.invert(&pt);
+            let second = changes(first.len_after(), seed + 1000);
+
+            pt.apply(&first);
+            assert_eq!(pt.text(), apply_str(&first, &text));
+            assert_eq!(pt.validate(), Ok(()));
+
+            pt.apply(&inverted);
+            assert_eq!(pt.text(), text);
+            assert_eq!(pt.validate(), Ok(()));
+
+            let composed = first.clone().compose(second.clone());
+            let expected = apply_str(&second, &apply_str(&first, &text));
+            assert_eq!(apply_str(&composed, &text), expected);
+            pt.apply(&composed);
+            assert_eq!(pt.text(), expected);
+            assert_eq!(pt.validate(), Ok(()));
+
+            let reverted = first.compose(inverted);
+            assert_eq!(apply_str(&reverted, &text), text);
+        }
+
+        let text = pt.text();
+        while pt.undo().is_some() {
+            assert_eq!(pt.validate(), Ok(()));
+        }
+        while pt.redo().is_some() {}
+        assert_eq!(pt.text(), text);
+    }
+
+    #[test]
+    fn errors() {
+        let mut pt = PieceTable::new("ab\r\ncd");
+        let edits = [(1..2, ""), (0..1, "")];
+        assert_eq!(
+            ChangeSet::from_edits(6, edits),
+            Err(Error::OverlappingRanges { prev_end: 2, start: 0 })
+        );
+        assert_eq!(
+            ChangeSet::from_edits(6, [(5..7, "")]),
+            Err(Error::OutOfBounds { idx: 7, len: 6 })
+        );
+
+        let changes = ChangeSet::from_edits(6, [(0..1, "x"), (2..3, "")]);
+        pt.set_crlf_policy(CrlfPolicy::Reject);
+        assert_eq!(
+            pt.try_apply(&changes.clone().unwrap()),
+            Err(Error::CrlfSplit { char_idx: 3 })
+        );
+        assert_eq!(pt.text(), "ab\r\ncd");
+
+        let first = changes.unwrap();
+        let second = ChangeSet::from_edits(6, [(0..0, "")]).unwrap();
+        assert_eq!(
+            first.try_compose(second),
+            Err(Error::LenMismatch { len: 5, expected: 6 })
+        );
+    }
+}
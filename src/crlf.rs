@@ -0,0 +1,325 @@
+//! The handling of edits next to (or inside) CRLF sequences.
+//!
+//! The line breaks of every piece are scanned from its buffer, separately from
+//! the other pieces. Thus a CR and an LF which are in different pieces are two
+//! line breaks instead of one, and a piece which ends (or starts) inside a
+//! CRLF sequence has the wrong line breaks. To avoid both, edits are widened
+//! so that every CRLF sequence in the table is always inside a single piece
+//! (see [`PieceTable::fit_crlf`]).
+//!
+//! This holds under every [`LineBreakConfig`], even where a CRLF sequence is
+//! not a line break of the table, because the lines of LSP (which are counted
+//! per piece as well) always break at it.
+//!
+//! [`LineBreakConfig`]: crate::LineBreakConfig
+
+use std::borrow::Cow;
+
+#[cfg(feature = "lines")]
+use crate::line::Break;
+#[cfg(feature = "lines")]
+use crate::piece::Piece;
+use crate::{Error, PieceTable};
+
+/// What an edit does when it would split the CR and the LF of a CRLF
+/// sequence, i.e., when it inserts between them, or when a range it removes
+/// starts or ends between them (see [`PieceTable::set_crlf_policy`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrlfPolicy {
+    /// Reject the edit with [`Error::CrlfSplit`] (or panic, for the methods
+    /// which don't return a [`Result`]).
+    #[default]
+    Reject,
+    /// Move the index to before the CR.
+    SnapBefore,
+    /// Move the index to after the LF.
+    SnapAfter,
+    /// Split the sequence into a CR and an LF, which are then two separate
+    /// line breaks (although a CR is a line break only with
+    /// [`LineBreakConfig::Unicode`]).
+    ///
+    /// [`LineBreakConfig::Unicode`]: crate::LineBreakConfig::Unicode
+    Split,
+}
+
+impl PieceTable<'_> {
+    /// Returns the [`CrlfPolicy`] of the table.
+    pub const fn crlf_policy(&self) -> CrlfPolicy {
+        self.crlf_policy
+    }
+
+    /// Set what edits do when they would split the CR and the LF of a CRLF
+    /// sequence. The default is [`CrlfPolicy::Reject`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{CrlfPolicy, Error, PieceTable};
+    /// let mut pt = PieceTable::new("a\r\nb");
+    /// assert_eq!(pt.try_insert(2, "c"), Err(Error::CrlfSplit { char_idx: 2 }));
+    ///
+    /// pt.set_crlf_policy(CrlfPolicy::SnapAfter);
+    /// pt.insert(2, "c");
+    /// assert_eq!(pt.text(), "a\r\ncb");
+    ///
+    /// pt.set_crlf_policy(CrlfPolicy::SnapBefore);
+    /// pt.remove(2..4);
+    /// assert_eq!(pt.text(), "ab");
+    ///
+    /// pt.set_crlf_policy(CrlfPolicy::Split);
+    /// pt.insert(1, "\r\n");
+    /// pt.insert(2, "-");
+    /// assert_eq!(pt.text(), "a\r-\nb");
+    /// # #[cfg(feature = "lines")]
+    /// assert_eq!(pt.line(0).to_string(), "a\r-");
+    /// ```
+    pub const fn set_crlf_policy(&mut self, policy: CrlfPolicy) {
+        self.crlf_policy = policy;
+    }
+
+    /// Apply `policy` to the replacement of the chars in `start..end` (which
+    /// must be in bounds) with `text`, and return the replacement to make
+    /// instead.
+    ///
+    /// Besides applying the policy, the replacement is widened so that a CR
+    /// and an LF which end up next to each other are reinserted together as a
+    /// CRLF sequence, and so that the CR and the LF of a split sequence are
+    /// reinserted separately.
+    pub(crate) fn fit_crlf<'t>(
+        &self,
+        mut start: usize,
+        mut end: usize,
+        text: &'t str,
+        policy: CrlfPolicy,
+    ) -> Result<(usize, usize, Cow<'t, str>), Error> {
+        let splits = |around| matches!(around, (Some('\r'), Some('\n')));
+        let mut around_start = self.chars_around(start);
+        let mut around_end =
+            if start == end { around_start } else { self.chars_around(end) };
+
+        match policy {
+            CrlfPolicy::Reject if splits(around_start) => {
+                return Err(Error::CrlfSplit { char_idx: start });
+            }
+            CrlfPolicy::Reject if splits(around_end) => {
+                return Err(Error::CrlfSplit { char_idx: end });
+            }
+            CrlfPolicy::Reject | CrlfPolicy::Split => {}
+            CrlfPolicy::SnapBefore | CrlfPolicy::SnapAfter => {
+                let snap = |idx| match policy {
+                    CrlfPolicy::SnapBefore => idx - 1,
+                    _ => idx + 1,
+                };
+                if splits(around_start) {
+                    start = snap(start);
+                    around_start = self.chars_around(start);
+                }
+                if splits(around_end) {
+                    end = snap(end);
+                    around_end = self.chars_around(end);
+                }
+            }
+        }
+
+        let (before, _) = around_start;
+        let (_, after) = around_end;
+        let mut text = Cow::Borrowed(text);
+
+        if splits(around_start)
+            || (before == Some('\r')
+                && text.chars().next().or(after) == Some('\n'))
+        {
+            start -= 1;
+            text = Cow::Owned(format!("\r{text}"));
+        }
+        if splits(around_end)
+            || (after == Some('\n')
+                && text.chars().next_back().or(before) == Some('\r'))
+        {
+            end += 1;
+            text.to_mut().push('\n');
+        }
+
+        Ok((start, end, text))
+    }
+
+    /// Make `pieces`, which are inserted together in place of a range, keep
+    /// every CRLF sequence inside a single piece, like
+    /// [`PieceTable::fit_crlf`] does for the text of a single edit.
+    ///
+    /// A piece which starts (or ends) between the CR and the LF of a CRLF
+    /// sequence of its buffer has the wrong line breaks, so the LF (or the CR)
+    /// is split from it. Then, a CR and an LF at the ends of adjacent pieces
+    /// are joined into a CRLF piece. The split and joined chars are appended
+    /// to the `add` buffer at most once each.
+    #[cfg(feature = "lines")]
+    pub(crate) fn fit_crlf_pieces(&mut self, pieces: Vec<Piece>) -> Vec<Piece> {
+        let mut cr = None;
+        let mut lf = None;
+        let mut crlf = None;
+        let mut fitted: Vec<Piece> = vec![];
+
+        for piece in pieces {
+            let text = piece.text(&self.buffers);
+            let line_breaks = piece.line_breaks(&self.buffers);
+            let starts_inside = text.starts_with('\n')
+                && line_breaks.first().is_none_or(|&(i, _ty)| i != piece.start);
+            let ends_inside = text.ends_with('\r')
+                && line_breaks.last().is_some_and(|&(i, ty)| {
+                    ty == Break::Crlf && i + 1 == piece.byte_range().end
+                });
+
+            let start = usize::from(starts_inside);
+            let end = piece.len_chars - usize::from(ends_inside);
+            let parts = [
+                starts_inside.then(|| self.line_break_piece(&mut lf, "\n")),
+                (start < end).then(|| piece.slice(&self.buffers, start..end)),
+                ends_inside.then(|| self.line_break_piece(&mut cr, "\r")),
+            ];
+
+            for part in parts.into_iter().flatten() {
+                let joins = fitted.last().is_some_and(|last| {
+                    last.text(&self.buffers).ends_with('\r')
+                        && part.text(&self.buffers).starts_with('\n')
+                });
+                if !joins {
+                    fitted.push(part);
+                    continue;
+                }
+
+                let Some(last) = fitted.pop() else { unreachable!() };
+                if last.len_chars > 1 {
+                    let before = 0..last.len_chars - 1;
+                    fitted.push(last.slice(&self.buffers, before));
+                }
+                fitted.push(self.line_break_piece(&mut crlf, "\r\n"));
+                if part.len_chars > 1 {
+                    let after = 1..part.len_chars;
+                    fitted.push(part.slice(&self.buffers, after));
+                }
+            }
+        }
+
+        fitted
+    }
+
+    /// The piece `cached` holds, or a piece referencing `text` (which is a
+    /// line break) after appending it to the `add` buffer.
+    #[cfg(feature = "lines")]
+    fn line_break_piece(
+        &mut self,
+        cached: &mut Option<Piece>,
+        text: &str,
+    ) -> Piece {
+        if let Some(piece) = cached {
+            return piece.clone();
+        }
+        let Some(piece) = self.append(text).pop() else {
+            unreachable!("`text` is not empty")
+        };
+        cached.insert(piece).clone()
+    }
+
+    /// Check that none of `ranges` (which must be in bounds), which are
+    /// replaced together, starts or ends inside a CRLF sequence if the policy
+    /// of the table is [`CrlfPolicy::Reject`]. With the other policies, the
+    /// ranges are replaced as they are, like with [`CrlfPolicy::Split`].
+    pub(crate) fn check_crlf_splits(
+        &self,
+        ranges: &[std::ops::Range<usize>],
+    ) -> Result<(), Error> {
+        if self.crlf_policy != CrlfPolicy::Reject {
+            return Ok(());
+        }
+
+        let splits =
+            |idx| matches!(self.chars_around(idx), (Some('\r'), Some('\n')));
+        for range in ranges {
+            if splits(range.start) {
+                return Err(Error::CrlfSplit { char_idx: range.start });
+            }
+            if !range.is_empty() && splits(range.end) {
+                return Err(Error::CrlfSplit { char_idx: range.end });
+            }
+        }
+        Ok(())
+    }
+
+    /// The chars before and after `char_idx` (which must be in bounds).
+    pub(crate) fn chars_around(
+        &self,
+        char_idx: usize,
+    ) -> (Option<char>, Option<char>) {
+        let mut chars = self.chars_at(char_idx);
+        let after = chars.next();
+        if after.is_some() {
+            chars.prev();
+        }
+        (chars.prev(), after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make random edits of CRs, LFs and CRLFs with `policy`, and compare the
+    /// line breaks of the table with the line breaks of its text.
+    fn random_edits(policy: CrlfPolicy) {
+        let mut pt = PieceTable::new("a\r\nb\rc\nd");
+        pt.set_crlf_policy(policy);
+
+        for i in 0..600 {
+            let len = pt.len_chars();
+            let text = ["\r", "\n", "\r\n", "x\r", "\ny", "z"][i % 6];
+            let start = (i * 7919) % (len + 1);
+            let result = if i % 3 == 0 {
+                pt.try_remove(start..(start + i % 4).min(len))
+            } else {
+                pt.try_insert(start, text)
+            };
+            assert!(result.is_ok() || policy == CrlfPolicy::Reject);
+            assert_eq!(pt.validate(), Ok(()));
+
+            #[cfg(feature = "lines")]
+            {
+                let text = pt.text();
+                let mut expected = vec![];
+                let config = pt.line_break_config();
+                crate::str_utils::line_breaks(&text, &mut expected, 0, config);
+                assert_eq!(pt.len_lines(), expected.len() + 1);
+
+                for (line_idx, (byte_idx, ty)) in expected.iter().enumerate() {
+                    assert_eq!(pt.byte_to_line(*byte_idx), line_idx);
+                    let next_line = byte_idx + ty.len_bytes();
+                    assert_eq!(pt.line_to_byte(line_idx + 1), next_line);
+                }
+            }
+        }
+
+        while pt.can_undo() {
+            pt.undo();
+        }
+        assert_eq!(pt.text(), "a\r\nb\rc\nd");
+    }
+
+    #[test]
+    fn reject() {
+        random_edits(CrlfPolicy::Reject);
+    }
+
+    #[test]
+    fn snap_before() {
+        random_edits(CrlfPolicy::SnapBefore);
+    }
+
+    #[test]
+    fn snap_after() {
+        random_edits(CrlfPolicy::SnapAfter);
+    }
+
+    #[test]
+    fn split() {
+        random_edits(CrlfPolicy::Split);
+    }
+}
@@ -0,0 +1,98 @@
+/// The errors returned by the `try_*` methods of [`PieceTable`] (and
+/// [`Slice`]), which are the cases the matching methods panic on.
+///
+/// [`PieceTable`]: crate::PieceTable
+/// [`Slice`]: crate::Slice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// An index is larger than the length of the text (or, for line indexes,
+    /// than the amount of lines), measured in the index's units.
+    OutOfBounds { idx: usize, len: usize },
+    /// The start of a range is larger than its end.
+    InvalidRange { start: usize, end: usize },
+    /// An edit at the char index `char_idx` would split the CR and the LF of a
+    /// CRLF sequence.
+    CrlfSplit { char_idx: usize },
+    /// A byte index is not on a char boundary.
+    NonCharBoundary { byte_idx: usize },
+    /// A range starts before the end of the previous one, in a sequence of
+    /// ranges which must be sorted and must not overlap.
+    OverlappingRanges { prev_end: usize, start: usize },
+    /// A change set applies to a text of `expected` chars, but the text it is
+    /// applied to (or composed with) has `len` chars.
+    LenMismatch { len: usize, expected: usize },
+    /// Line endings would be converted to CR in a table in which a lone CR is
+    /// not a line break, which would join the converted lines.
+    CrNotLineBreak,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutOfBounds { idx, len } => {
+                write!(
+                    f,
+                    "index out of bounds: the len is {len} but the index is \
+                     {idx}"
+                )
+            }
+            Self::InvalidRange { start, end } => {
+                write!(f, "slice index starts at {start} but ends at {end}")
+            }
+            Self::CrlfSplit { char_idx } => {
+                write!(f, "char index {char_idx} is inside a CRLF sequence")
+            }
+            Self::NonCharBoundary { byte_idx } => {
+                write!(f, "byte index {byte_idx} is not a char boundary")
+            }
+            Self::OverlappingRanges { prev_end, start } => {
+                write!(
+                    f,
+                    "range starts at {start} before the previous end \
+                     {prev_end}"
+                )
+            }
+            Self::LenMismatch { len, expected } => {
+                write!(
+                    f,
+                    "change set expects {expected} chars but the text has \
+                     {len}"
+                )
+            }
+            Self::CrNotLineBreak => {
+                write!(f, "a lone CR is not a line break in the table")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Check that `idx` is at most `len`.
+pub(crate) const fn check_idx(idx: usize, len: usize) -> Result<(), Error> {
+    if idx <= len { Ok(()) } else { Err(Error::OutOfBounds { idx, len }) }
+}
+
+/// Check that `start..end` is a valid range in a text of length `len`.
+pub(crate) const fn check_range(
+    start: usize,
+    end: usize,
+    len: usize,
+) -> Result<(), Error> {
+    if start > end {
+        Err(Error::InvalidRange { start, end })
+    } else {
+        check_idx(end, len)
+    }
+}
+
+/// Unwrap `result`, panicking with the message of its error. This is how the
+/// panicking methods report the errors of their `try_*` counterparts.
+#[track_caller]
+pub(crate) fn unwrap<T>(result: Result<T, Error>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => panic!("{err}"),
+    }
+}
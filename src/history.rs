@@ -0,0 +1,426 @@
+//! Undo and redo.
+//!
+//! Because the `add` buffer is append-only, the text of pieces that were
+//! removed from the table is never lost. Thus, the history only has to record
+//! which pieces were removed and inserted by every edit, and never copies text.
+//! This also makes it cheap to keep every branch of the history, instead of
+//! discarding the steps that were undone when making a new edit.
+
+use crate::piece::Piece;
+
+/// A single edit: replacing `removed` with `inserted` at `char_idx`.
+#[derive(Debug, Clone)]
+pub(crate) struct Change {
+    pub(crate) char_idx: usize,
+    pub(crate) removed: Vec<Piece>,
+    pub(crate) inserted: Vec<Piece>,
+}
+
+impl Change {
+    pub(crate) fn len_removed(&self) -> usize {
+        self.removed.iter().map(|p| p.len_chars).sum()
+    }
+
+    pub(crate) fn len_inserted(&self) -> usize {
+        self.inserted.iter().map(|p| p.len_chars).sum()
+    }
+}
+
+/// A group of changes which are undone and redone together, and the cursor
+/// positions before and after them.
+#[derive(Debug, Clone)]
+pub(crate) struct Step {
+    pub(crate) changes: Vec<Change>,
+    pub(crate) cursors_before: Vec<usize>,
+    pub(crate) cursors_after: Vec<usize>,
+}
+
+/// A move along an edge of the history tree, i.e., undoing or redoing the step
+/// of a revision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Move {
+    Undo(usize),
+    Redo(usize),
+}
+
+/// A state of the table, reached by applying `step` to the `parent` revision.
+#[derive(Debug)]
+struct Revision {
+    parent: usize,
+    /// The child that was visited most recently, which is the one that is
+    /// redone.
+    last_child: Option<usize>,
+    step: Step,
+}
+
+/// A tree of revisions, where the root (revision `0`) is the initial state of
+/// the table, and every other revision is created by a step made on top of its
+/// parent. Undoing never discards anything, as a new step made after undoing
+/// creates a new branch.
+///
+/// Revisions are numbered in the order they were created, thus a parent always
+/// has a smaller number than its children.
+#[derive(Debug)]
+pub(crate) struct History {
+    revisions: Vec<Revision>,
+    /// The revision the table is currently at.
+    current: usize,
+    /// The step of the group being built, and the amount of groups that were
+    /// begun but not ended yet.
+    group: Option<(Step, usize)>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        let root = Revision {
+            parent: 0,
+            last_child: None,
+            step: Step {
+                changes: vec![],
+                cursors_before: vec![],
+                cursors_after: vec![],
+            },
+        };
+
+        Self { revisions: vec![root], current: 0, group: None }
+    }
+}
+
+impl History {
+    /// Record a change that was just made to the table.
+    ///
+    /// If a group is open, the change becomes a part of it. Otherwise it is a
+    /// step of its own, with the cursor before it at its start, and the cursor
+    /// after it at the end of the inserted text.
+    pub(crate) fn record(&mut self, change: Change) {
+        if change.removed.is_empty() && change.inserted.is_empty() {
+            return;
+        }
+
+        match &mut self.group {
+            Some((step, _depth)) => step.changes.push(change),
+            None => {
+                let cursors_before = vec![change.char_idx];
+                let cursors_after =
+                    vec![change.char_idx + change.len_inserted()];
+                self.push(Step {
+                    changes: vec![change],
+                    cursors_before,
+                    cursors_after,
+                });
+            }
+        }
+    }
+
+    /// All the pieces recorded in the history (including the open group).
+    #[cfg(feature = "lines")]
+    pub(crate) fn pieces_mut(&mut self) -> impl Iterator<Item = &mut Piece> {
+        let group = self.group.as_mut().map(|(step, _depth)| step);
+        self.revisions
+            .iter_mut()
+            .map(|revision| &mut revision.step)
+            .chain(group)
+            .flat_map(|step| &mut step.changes)
+            .flat_map(|change| {
+                change.removed.iter_mut().chain(&mut change.inserted)
+            })
+    }
+
+    pub(crate) fn begin_group(&mut self, cursors: &[usize]) {
+        match &mut self.group {
+            Some((_step, depth)) => *depth += 1,
+            None => {
+                let step = Step {
+                    changes: vec![],
+                    cursors_before: cursors.to_vec(),
+                    cursors_after: vec![],
+                };
+                self.group = Some((step, 1));
+            }
+        }
+    }
+
+    pub(crate) fn end_group(&mut self, cursors: &[usize]) {
+        match &mut self.group {
+            Some((_step, depth)) if *depth > 1 => *depth -= 1,
+            Some(_) => {
+                let (mut step, _depth) = self.group.take().unwrap();
+                step.cursors_after = cursors.to_vec();
+                if !step.changes.is_empty() {
+                    self.push(step);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Close the open group (if there is one), as if all of its groups were
+    /// ended with the cursors of its last change.
+    fn close_group(&mut self) {
+        if let Some((step, _depth)) = &self.group {
+            let cursors = step.changes.last().map_or_else(
+                || step.cursors_before.clone(),
+                |c| vec![c.char_idx + c.len_inserted()],
+            );
+            self.group.as_mut().unwrap().1 = 1;
+            self.end_group(&cursors);
+        }
+    }
+
+    pub(crate) fn can_undo(&self) -> bool {
+        self.current > 0
+            || self.group.as_ref().is_some_and(|(s, _d)| !s.changes.is_empty())
+    }
+
+    pub(crate) fn can_redo(&self) -> bool {
+        self.revisions[self.current].last_child.is_some()
+    }
+
+    /// The revision the table is currently at.
+    pub(crate) const fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The amount of revisions in the tree.
+    pub(crate) fn len(&self) -> usize {
+        self.revisions.len()
+    }
+
+    /// The revisions that have no children, in the order they were created.
+    pub(crate) fn leaves(&self) -> impl Iterator<Item = usize> {
+        (0..self.revisions.len())
+            .filter(|&rev| self.revisions[rev].last_child.is_none())
+    }
+
+    /// The step that creates `revision` from its parent.
+    pub(crate) fn step(&self, revision: usize) -> &Step {
+        &self.revisions[revision].step
+    }
+
+    /// Move to the parent of the current revision.
+    pub(crate) fn undo(&mut self) -> Vec<Move> {
+        self.close_group();
+        if self.current == 0 {
+            return vec![];
+        }
+        self.jump_to(self.revisions[self.current].parent)
+    }
+
+    /// Move to the most recently visited child of the current revision.
+    pub(crate) fn redo(&mut self) -> Vec<Move> {
+        self.close_group();
+        match self.revisions[self.current].last_child {
+            Some(child) => self.jump_to(child),
+            None => vec![],
+        }
+    }
+
+    /// Move to `target`, and return the moves that lead to it from the
+    /// current revision: undoing up to their closest common ancestor, and then
+    /// redoing down to `target`.
+    pub(crate) fn jump_to(&mut self, target: usize) -> Vec<Move> {
+        assert!(target < self.revisions.len(), "revision out of bounds");
+        self.close_group();
+
+        let (mut up, mut down) = (self.current, target);
+        let mut undos = vec![];
+        let mut redos = vec![];
+        // Parents are always smaller than their children, so the larger of the
+        // two can't be the common ancestor.
+        while up != down {
+            if up > down {
+                undos.push(Move::Undo(up));
+                up = self.revisions[up].parent;
+            } else {
+                redos.push(Move::Redo(down));
+                let parent = self.revisions[down].parent;
+                self.revisions[parent].last_child = Some(down);
+                down = parent;
+            }
+        }
+
+        self.current = target;
+        undos.extend(redos.into_iter().rev());
+        undos
+    }
+
+    /// Push a new revision on top of the current one, with `step`.
+    fn push(&mut self, step: Step) {
+        let revision = self.revisions.len();
+        self.revisions.push(Revision {
+            parent: self.current,
+            last_child: None,
+            step,
+        });
+        self.revisions[self.current].last_child = Some(revision);
+        self.current = revision;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PieceTable;
+
+    #[test]
+    fn undo_and_redo_everything() {
+        let initial = "lorem ipsum dolor sit amet\n".repeat(100);
+        let mut pt = PieceTable::new(&initial);
+        let mut texts = vec![pt.text()];
+
+        for i in 0..300 {
+            let idx = (i * 7919) % (pt.len_chars() + 1);
+            pt.insert(idx, ["a", "🦀", "\n", "ö\nx"][i % 4]);
+            texts.push(pt.text());
+
+            let start = (i * 104729) % pt.len_chars();
+            pt.remove(start..(start + i % 40).min(pt.len_chars()));
+            if i % 40 != 0 {
+                texts.push(pt.text());
+            }
+        }
+
+        for expected in texts.iter().rev().skip(1) {
+            assert!(pt.undo().is_some());
+            assert_eq!(&pt.text(), expected);
+        }
+        assert!(!pt.can_undo());
+
+        for expected in texts.iter().skip(1) {
+            assert!(pt.redo().is_some());
+            assert_eq!(&pt.text(), expected);
+        }
+        assert!(!pt.can_redo());
+    }
+
+    #[test]
+    fn contiguous_inserts_are_separate_steps() {
+        let mut pt = PieceTable::new("");
+        pt.insert(0, "a");
+        pt.insert(1, "b");
+        pt.insert(2, "c");
+
+        assert_eq!(pt.undo(), Some(vec![2]));
+        assert_eq!(pt.text(), "ab");
+        pt.insert(2, "d");
+        assert_eq!(pt.text(), "abd");
+        assert!(!pt.can_redo());
+        assert_eq!(pt.undo(), Some(vec![2]));
+        assert_eq!(pt.undo(), Some(vec![1]));
+        assert_eq!(pt.text(), "a");
+    }
+
+    #[test]
+    fn nested_groups() {
+        let mut pt = PieceTable::new("abc");
+        pt.begin_group(&[0]);
+        pt.insert(0, "x");
+        pt.begin_group(&[1]);
+        pt.remove(1..2);
+        pt.end_group(&[1]);
+        pt.insert(3, "y");
+        pt.end_group(&[4]);
+        pt.insert(0, "z");
+
+        assert_eq!(pt.text(), "zxbcy");
+        assert_eq!(pt.undo(), Some(vec![0]));
+        assert_eq!(pt.undo(), Some(vec![0]));
+        assert_eq!(pt.text(), "abc");
+        assert_eq!(pt.redo(), Some(vec![4]));
+        assert_eq!(pt.text(), "xbcy");
+    }
+
+    #[test]
+    fn undo_closes_open_group() {
+        let mut pt = PieceTable::new("abc");
+        pt.begin_group(&[3]);
+        pt.insert(3, "d");
+        pt.insert(4, "e");
+
+        assert!(pt.can_undo());
+        assert_eq!(pt.undo(), Some(vec![3]));
+        assert_eq!(pt.text(), "abc");
+        assert_eq!(pt.redo(), Some(vec![5]));
+        assert_eq!(pt.text(), "abcde");
+    }
+
+    #[test]
+    fn jump_between_branches() {
+        let initial = "lorem ipsum\n".repeat(50);
+        let mut pt = PieceTable::new(&initial);
+        let mut texts = vec![pt.text()];
+
+        for i in 0..300 {
+            if i % 7 == 3 {
+                pt.undo();
+                pt.undo();
+            }
+            if i % 2 == 0 {
+                let idx = (i * 7919) % (pt.len_chars() + 1);
+                pt.insert(idx, ["a", "🦀", "\n", "ö\nx"][i % 4]);
+            } else {
+                let start = (i * 104729) % pt.len_chars();
+                pt.remove(start..(start + i % 13 + 1).min(pt.len_chars()));
+            }
+            assert_eq!(pt.revision(), texts.len());
+            texts.push(pt.text());
+        }
+
+        for i in 0..texts.len() {
+            let revision = (i * 31) % texts.len();
+            pt.jump_to_revision(revision);
+            assert_eq!(pt.revision(), revision);
+            assert_eq!(pt.text(), texts[revision]);
+        }
+
+        pt.jump_to_revision(0);
+        for (revision, text) in texts.iter().enumerate().skip(1) {
+            assert!(pt.later(1).is_some());
+            assert_eq!(pt.revision(), revision);
+            assert_eq!(&pt.text(), text);
+        }
+        assert_eq!(pt.later(1), None);
+        pt.earlier(usize::MAX);
+        assert_eq!(pt.text(), texts[0]);
+    }
+
+    #[test]
+    fn redo_follows_last_visited_branch() {
+        let mut pt = PieceTable::new("");
+        pt.insert(0, "a");
+        pt.undo();
+        pt.insert(0, "b");
+        pt.undo();
+        pt.insert(0, "c");
+
+        assert_eq!(pt.leaf_revisions(), vec![1, 2, 3]);
+        pt.jump_to_revision(2);
+        pt.undo();
+        pt.redo();
+        assert_eq!(pt.text(), "b");
+        pt.jump_to_revision(1);
+        pt.undo();
+        pt.redo();
+        assert_eq!(pt.text(), "a");
+    }
+
+    #[test]
+    #[cfg(feature = "lines")]
+    fn record_undo_and_redo() {
+        let initial = "fn main() {\n}\n";
+        let mut client = PieceTable::new(initial);
+        let mut server = PieceTable::new(initial);
+        client.record_changes(true);
+
+        client.insert(12, "    🦀\n");
+        client.remove(0..3);
+        client.undo();
+        client.undo();
+        client.redo();
+
+        for change in client.take_changes() {
+            server.apply_change(&change);
+        }
+        assert_eq!(server.text(), client.text());
+        assert_eq!(server.text(), "fn main() {\n    🦀\n}\n");
+    }
+}
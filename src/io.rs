@@ -0,0 +1,344 @@
+//! Reading tables from readers, and writing their contents to files and other
+//! writers.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "lines")]
+use crate::LineBreakConfig;
+use crate::PieceTable;
+use crate::builder::Builder;
+use crate::piece::Piece;
+
+impl PieceTable<'static> {
+    /// Create a table which owns its contents, by reading them from `reader`
+    /// until it reaches EOF.
+    ///
+    /// The contents are decoded and added to the table as they are read, so
+    /// they are never collected into a string first. `reader` is read in
+    /// chunks, thus it doesn't have to be buffered.
+    ///
+    /// # Errors
+    ///
+    /// Will return the first error returned by `reader` (except for
+    /// [`io::ErrorKind::Interrupted`], on which reading is retried), or an
+    /// error with [`io::ErrorKind::InvalidData`] if the contents are not valid
+    /// UTF-8.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let reader = std::io::Cursor::new("streamed\r\ncontents");
+    /// let pt = PieceTable::from_reader(reader)?;
+    /// assert_eq!(pt.text(), "streamed\r\ncontents");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn from_reader(reader: impl Read) -> io::Result<Self> {
+        read_into(Builder::new(), reader)
+    }
+
+    /// Create a table which owns its contents, by reading them from `reader`
+    /// until it reaches EOF, in which the line breaks are the ones selected by
+    /// `config` (see [`PieceTable::new_with_line_breaks`]).
+    ///
+    /// # Errors
+    ///
+    /// The same as [`PieceTable::from_reader`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{LineBreakConfig, PieceTable};
+    /// let reader = std::io::Cursor::new("a\r\nb");
+    /// let pt =
+    ///     PieceTable::from_reader_with_line_breaks(reader, LineBreakConfig::Lf)?;
+    /// assert_eq!(pt.line(0).to_string(), "a\r");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn from_reader_with_line_breaks(
+        reader: impl Read,
+        config: LineBreakConfig,
+    ) -> io::Result<Self> {
+        read_into(Builder::with_line_breaks(config), reader)
+    }
+}
+
+/// Append the contents of `reader` to `builder` until it reaches EOF, and
+/// return the built table.
+fn read_into(
+    mut builder: Builder,
+    mut reader: impl Read,
+) -> io::Result<PieceTable<'static>> {
+    let mut buf = vec![0; Piece::MAX_LEN_BYTES];
+    // The amount of bytes at the start of `buf` which are the start of a
+    // char that was not read completely.
+    let mut incomplete = 0;
+
+    loop {
+        let len = match reader.read(&mut buf[incomplete..]) {
+            Ok(0) if incomplete == 0 => return Ok(builder.finish()),
+            Ok(0) => return Err(invalid_utf8("the stream ends mid char")),
+            Ok(len) => incomplete + len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        let valid = match std::str::from_utf8(&buf[..len]) {
+            Ok(text) => text,
+            Err(e) if e.error_len().is_none() => {
+                // SAFETY: `valid_up_to` is the length of the valid prefix.
+                unsafe {
+                    std::str::from_utf8_unchecked(&buf[..e.valid_up_to()])
+                }
+            }
+            Err(e) => return Err(invalid_utf8(e)),
+        };
+        builder.push_str(valid);
+
+        let valid_len = valid.len();
+        buf.copy_within(valid_len..len, 0);
+        incomplete = len - valid_len;
+    }
+}
+
+fn invalid_utf8(
+    error: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl PieceTable<'_> {
+    /// Write the contents of the table to `writer`, chunk by chunk (see
+    /// [`PieceTable::iter`]), without collecting them into a string.
+    ///
+    /// Every chunk is written with a separate call to [`Write::write_all`], so
+    /// unbuffered writers should be wrapped with an [`io::BufWriter`].
+    ///
+    /// # Errors
+    ///
+    /// Will return the first error returned by `writer`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("hello");
+    /// pt.insert(5, " world");
+    ///
+    /// let mut out = vec![];
+    /// pt.write_to(&mut out)?;
+    /// assert_eq!(out, b"hello world");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for chunk in self.iter() {
+            writer.write_all(chunk.as_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Save the contents of the table to the file at `path` atomically: they
+    /// are written to a temporary file in the same directory, which is synced
+    /// to the disk and then renamed to `path`. Thus, `path` contains either
+    /// the old contents or the new ones, even if the process crashes midway.
+    ///
+    /// The file at `path` is never truncated or written to, so it is safe to
+    /// save a table which was opened from the same file with
+    /// [`PieceTable::map_file`]: the table keeps reading the old file, which
+    /// the OS keeps alive for as long as it is mapped. On Windows, replacing a
+    /// file which is mapped fails instead.
+    ///
+    /// If the file exists, its permissions are kept.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if creating, writing or syncing the temporary file
+    /// fails, or if it could not be renamed to `path`. The temporary file is
+    /// removed in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("fn main() {}\n");
+    /// pt.insert(11, "\n");
+    /// pt.save_to_path("main.rs")?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn save_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let (temp_path, temp) = create_temp_file(path)?;
+
+        let result = self.save_to_temp_file(path, &temp_path, temp);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn save_to_temp_file(
+        &self,
+        path: &Path,
+        temp_path: &Path,
+        temp: File,
+    ) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(temp);
+        self.write_to(&mut writer)?;
+        let temp = writer.into_inner().map_err(|e| e.into_error())?;
+
+        if let Ok(metadata) = fs::metadata(path) {
+            temp.set_permissions(metadata.permissions())?;
+        }
+        temp.sync_all()?;
+        drop(temp);
+
+        fs::rename(temp_path, path)?;
+        sync_parent_dir(path)
+    }
+}
+
+/// Create a new temporary file next to `path`, and return its path.
+fn create_temp_file(path: &Path) -> io::Result<(PathBuf, File)> {
+    let Some(file_name) = path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the path does not name a file",
+        ));
+    };
+
+    let mut attempt = 0;
+    loop {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}-{attempt}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        match File::options().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Sync the directory containing `path`, so the rename to it is durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("peace-table-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A reader which returns a few bytes at a time, to split chars and CRLF
+    /// sequences.
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1 += 1;
+            if self.1.is_multiple_of(5) {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let len = (self.1 % 7 + 1).min(buf.len()).min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn read_in_parts() {
+        let contents = "lorem 🦀 ipsum\r\ndolor\rsit ämet\n".repeat(2000);
+        let pt = PieceTable::from_reader(Trickle(contents.as_bytes(), 0));
+        let pt = pt.unwrap();
+
+        assert_eq!(pt.text(), contents);
+        #[cfg(feature = "lines")]
+        assert_eq!(pt.len_lines(), PieceTable::new(&contents).len_lines());
+
+        let pt = PieceTable::from_reader(io::Cursor::new(&contents)).unwrap();
+        assert_eq!(pt.text(), contents);
+    }
+
+    #[test]
+    fn read_invalid_utf8() {
+        let invalid = b"valid \xF0\x9F\xA6";
+        let err = PieceTable::from_reader(&invalid[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let invalid = b"valid \xF0\x9F\xA6 invalid";
+        let err = PieceTable::from_reader(&invalid[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn save_and_replace() {
+        let dir = temp_dir("save_and_replace");
+        let path = dir.join("file.txt");
+        fs::write(&path, "old contents").unwrap();
+
+        let initial = "lorem ipsum\n".repeat(10000);
+        let mut pt = PieceTable::new(&initial);
+        pt.insert(5, "🦀");
+        pt.save_to_path(&path).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), pt.text());
+        // Only the saved file is left.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_to_missing_dir() {
+        let dir = temp_dir("save_to_missing_dir");
+        let pt = PieceTable::new("contents");
+        assert!(pt.save_to_path(dir.join("missing/file.txt")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "mmap", unix))]
+    fn save_over_mapped_file() {
+        let dir = temp_dir("save_over_mapped_file");
+        let path = dir.join("file.txt");
+        let contents = "lorem ipsum dolor sit amet\n".repeat(10000);
+        fs::write(&path, &contents).unwrap();
+
+        let mut pt = unsafe { PieceTable::map_file(&path) }.unwrap();
+        pt.remove(0..6);
+        pt.insert(pt.len_chars(), "the end");
+        pt.save_to_path(&path).unwrap();
+
+        let expected = format!("{}the end", &contents[6..]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        // The table still reads the contents of the old file.
+        assert_eq!(pt.text(), expected);
+        pt.undo();
+        pt.undo();
+        assert_eq!(pt.text(), contents);
+
+        drop(pt);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+//! Iterators over the contents of a table, which can start anywhere in it.
+//!
+//! Every iterator is a cursor between two items: [`Iterator::next`] returns
+//! the item after the cursor and moves the cursor forward, while `prev` returns
+//! the item before it and moves it backward. The iterators are also
+//! double-ended, where [`DoubleEndedIterator::next_back`] consumes the items
+//! from the end of the table (or slice) towards the cursor.
+
+use std::ops::Range;
+
+use crate::PieceTable;
+#[cfg(feature = "lines")]
+use crate::Slice;
+use crate::tree::NodeIdx;
+
+/// A byte position in a table, and the piece it is in.
+#[derive(Debug, Clone)]
+struct Cursor<'a> {
+    table: &'a PieceTable<'a>,
+    node: Option<NodeIdx>,
+    /// The text of the piece of `node`.
+    text: &'a str,
+    /// The byte index of the start of `text` in the table.
+    text_start: usize,
+    /// The byte index of the cursor in `text`.
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(table: &'a PieceTable<'a>, byte_idx: usize) -> Self {
+        let Some((before, node)) = table.tree.node_at_byte(byte_idx) else {
+            return Self {
+                table,
+                node: None,
+                text: "",
+                text_start: 0,
+                offset: 0,
+            };
+        };
+
+        Self {
+            table,
+            node: Some(node),
+            text: table.tree.piece(node).text(&table.buffers),
+            text_start: before.bytes,
+            offset: byte_idx - before.bytes,
+        }
+    }
+
+    const fn byte_idx(&self) -> usize {
+        self.text_start + self.offset
+    }
+
+    /// If the cursor is at the end of its piece, move it to the start of the
+    /// next piece (if there is one).
+    fn skip_piece_end(&mut self) {
+        if self.offset < self.text.len() {
+            return;
+        }
+        let Some(next) = self.node.and_then(|n| self.table.tree.next(n)) else {
+            return;
+        };
+
+        self.text_start += self.text.len();
+        self.node = Some(next);
+        self.text = self.table.tree.piece(next).text(&self.table.buffers);
+        self.offset = 0;
+    }
+
+    /// If the cursor is at the start of its piece, move it to the end of the
+    /// previous piece (if there is one).
+    fn skip_piece_start(&mut self) {
+        if self.offset > 0 {
+            return;
+        }
+        let Some(prev) = self.node.and_then(|n| self.table.tree.prev(n)) else {
+            return;
+        };
+
+        self.node = Some(prev);
+        self.text = self.table.tree.piece(prev).text(&self.table.buffers);
+        self.text_start -= self.text.len();
+        self.offset = self.text.len();
+    }
+}
+
+/// The part of a table an iterator moves in: between the `start` and the back
+/// cursor, with the front cursor somewhere in between.
+#[derive(Debug, Clone)]
+struct Span<'a> {
+    front: Cursor<'a>,
+    back: Cursor<'a>,
+    /// The byte index of the start of the span.
+    start: usize,
+}
+
+impl<'a> Span<'a> {
+    /// Create a span of `byte_range` of `table`, with the front cursor at
+    /// `byte_idx` (which must be inside `byte_range`).
+    fn new(
+        table: &'a PieceTable<'a>,
+        byte_range: Range<usize>,
+        byte_idx: usize,
+    ) -> Self {
+        debug_assert!(
+            byte_range.contains(&byte_idx) || byte_idx == byte_range.end
+        );
+
+        Self {
+            front: Cursor::new(table, byte_idx),
+            back: Cursor::new(table, byte_range.end),
+            start: byte_range.start,
+        }
+    }
+
+    /// The text of the front cursor's piece, and the range in it from the
+    /// front cursor up to the end of the piece (or the back cursor).
+    fn ahead(&mut self) -> Option<(&'a str, Range<usize>)> {
+        let len = self.back.byte_idx().checked_sub(self.front.byte_idx())?;
+        if len == 0 {
+            return None;
+        }
+
+        self.front.skip_piece_end();
+        let Cursor { text, offset, .. } = self.front;
+        Some((text, offset..text.len().min(offset + len)))
+    }
+
+    /// The text of the front cursor's piece, and the range in it from its
+    /// start (or the start of the span) up to the front cursor.
+    fn behind(&mut self) -> Option<(&'a str, Range<usize>)> {
+        let len = self.front.byte_idx().checked_sub(self.start)?;
+        if len == 0 {
+            return None;
+        }
+
+        self.front.skip_piece_start();
+        let Cursor { text, offset, .. } = self.front;
+        Some((text, offset.saturating_sub(len)..offset))
+    }
+
+    /// The text of the back cursor's piece, and the range in it from its
+    /// start (or the front cursor) up to the back cursor.
+    fn behind_back(&mut self) -> Option<(&'a str, Range<usize>)> {
+        let len = self.back.byte_idx().checked_sub(self.front.byte_idx())?;
+        if len == 0 {
+            return None;
+        }
+
+        self.back.skip_piece_start();
+        let Cursor { text, offset, .. } = self.back;
+        Some((text, offset.saturating_sub(len)..offset))
+    }
+}
+
+/// An iterator over the `&str` chunks of a table (see
+/// [`PieceTable::chunks_at`]).
+#[derive(Debug, Clone)]
+pub struct Chunks<'a> {
+    span: Span<'a>,
+}
+
+impl<'a> Chunks<'a> {
+    pub(crate) fn new(
+        table: &'a PieceTable<'a>,
+        byte_range: Range<usize>,
+        byte_idx: usize,
+    ) -> Self {
+        Self { span: Span::new(table, byte_range, byte_idx) }
+    }
+
+    /// Returns the chunk before the cursor, and moves the cursor backward.
+    pub fn prev(&mut self) -> Option<&'a str> {
+        let (text, range) = self.span.behind()?;
+        self.span.front.offset = range.start;
+        Some(&text[range])
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (text, range) = self.span.ahead()?;
+        self.span.front.offset = range.end;
+        Some(&text[range])
+    }
+}
+
+impl DoubleEndedIterator for Chunks<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (text, range) = self.span.behind_back()?;
+        self.span.back.offset = range.start;
+        Some(&text[range])
+    }
+}
+
+/// An iterator over the chars of a table (see [`PieceTable::chars_at`]).
+#[derive(Debug, Clone)]
+pub struct Chars<'a> {
+    span: Span<'a>,
+}
+
+impl<'a> Chars<'a> {
+    pub(crate) fn new(
+        table: &'a PieceTable<'a>,
+        byte_range: Range<usize>,
+        byte_idx: usize,
+    ) -> Self {
+        Self { span: Span::new(table, byte_range, byte_idx) }
+    }
+
+    /// Returns the char before the cursor, and moves the cursor backward.
+    pub fn prev(&mut self) -> Option<char> {
+        let (text, range) = self.span.behind()?;
+        let ch = text[..range.end].chars().next_back()?;
+        self.span.front.offset -= ch.len_utf8();
+        Some(ch)
+    }
+}
+
+impl Iterator for Chars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (text, range) = self.span.ahead()?;
+        let ch = text[range.start..].chars().next()?;
+        self.span.front.offset += ch.len_utf8();
+        Some(ch)
+    }
+}
+
+impl DoubleEndedIterator for Chars<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (text, range) = self.span.behind_back()?;
+        let ch = text[..range.end].chars().next_back()?;
+        self.span.back.offset -= ch.len_utf8();
+        Some(ch)
+    }
+}
+
+/// An iterator over the bytes of a table (see [`PieceTable::bytes_at`]).
+#[derive(Debug, Clone)]
+pub struct Bytes<'a> {
+    span: Span<'a>,
+}
+
+impl<'a> Bytes<'a> {
+    pub(crate) fn new(
+        table: &'a PieceTable<'a>,
+        byte_range: Range<usize>,
+        byte_idx: usize,
+    ) -> Self {
+        Self { span: Span::new(table, byte_range, byte_idx) }
+    }
+
+    /// Returns the byte before the cursor, and moves the cursor backward.
+    pub fn prev(&mut self) -> Option<u8> {
+        let (text, range) = self.span.behind()?;
+        self.span.front.offset -= 1;
+        Some(text.as_bytes()[range.end - 1])
+    }
+}
+
+impl Iterator for Bytes<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (text, range) = self.span.ahead()?;
+        self.span.front.offset += 1;
+        Some(text.as_bytes()[range.start])
+    }
+}
+
+impl DoubleEndedIterator for Bytes<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (text, range) = self.span.behind_back()?;
+        self.span.back.offset -= 1;
+        Some(text.as_bytes()[range.end - 1])
+    }
+}
+
+/// An iterator over the lines of a table or a slice, **without** their line
+/// break sequences (see [`PieceTable::lines_at`]).
+#[cfg(feature = "lines")]
+#[derive(Debug, Clone)]
+pub struct Lines<'a> {
+    slice: Slice<'a>,
+    /// The index of the line after the cursor.
+    front: usize,
+    /// The index of the line after the last line which wasn't consumed by
+    /// [`DoubleEndedIterator::next_back`], or [`None`] if it wasn't called
+    /// yet (so that the lines are counted only if it is).
+    back: Option<usize>,
+}
+
+#[cfg(feature = "lines")]
+impl<'a> Lines<'a> {
+    pub(crate) fn new(slice: Slice<'a>, line_idx: usize) -> Self {
+        Self { slice, front: line_idx, back: None }
+    }
+
+    /// Returns the line before the cursor, and moves the cursor backward.
+    pub fn prev(&mut self) -> Option<Slice<'a>> {
+        if self.front == 0 {
+            return None;
+        }
+        self.front -= 1;
+        Some(self.slice.line(self.front))
+    }
+}
+
+#[cfg(feature = "lines")]
+impl<'a> Iterator for Lines<'a> {
+    type Item = Slice<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let has_next = match self.back {
+            Some(back) => self.front < back,
+            None => self.slice.has_line(self.front),
+        };
+        if !has_next {
+            return None;
+        }
+        self.front += 1;
+        Some(self.slice.line(self.front - 1))
+    }
+}
+
+#[cfg(feature = "lines")]
+impl DoubleEndedIterator for Lines<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let back = self.back.get_or_insert_with(|| self.slice.len_lines());
+        if *back <= self.front {
+            return None;
+        }
+        *back -= 1;
+        Some(self.slice.line(*back))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a table with many pieces, and insert the same text to
+    /// `expected`.
+    fn table(expected: &mut String) -> PieceTable<'static> {
+        let mut pt = PieceTable::from(expected.clone());
+        for i in 0..300 {
+            let mut byte_idx =
+                pt.char_to_byte((i * 7919) % (pt.len_chars() + 1));
+            if expected[..byte_idx].ends_with('\r') {
+                byte_idx += 1; // don't split a CRLF sequence
+            }
+            let text = ["a", "🦀", "\n", "ö\r\nx"][i % 4];
+            pt.insert_at_byte(byte_idx, text);
+            expected.insert_str(byte_idx, text);
+        }
+        pt
+    }
+
+    #[test]
+    fn chars_and_bytes_both_ways() {
+        let mut expected = "lorem ipsum\n".repeat(20);
+        let pt = table(&mut expected);
+        let chars = expected.chars().collect::<Vec<_>>();
+
+        for char_idx in (0..=chars.len()).step_by(7) {
+            let mut iter = pt.chars_at(char_idx);
+            assert!(iter.clone().eq(chars[char_idx..].iter().copied()));
+            assert!(
+                iter.clone().rev().eq(chars[char_idx..].iter().rev().copied())
+            );
+            for &ch in chars[..char_idx].iter().rev() {
+                assert_eq!(iter.prev(), Some(ch));
+            }
+            assert_eq!(iter.prev(), None);
+            assert!(iter.eq(chars.iter().copied()));
+        }
+
+        let bytes = expected.as_bytes();
+        for byte_idx in (0..=bytes.len()).step_by(5) {
+            let mut iter = pt.bytes_at(byte_idx);
+            assert!(iter.clone().eq(bytes[byte_idx..].iter().copied()));
+            assert!(
+                iter.clone().rev().eq(bytes[byte_idx..].iter().rev().copied())
+            );
+            for &byte in bytes[..byte_idx].iter().rev() {
+                assert_eq!(iter.prev(), Some(byte));
+            }
+            assert_eq!(iter.prev(), None);
+        }
+    }
+
+    #[test]
+    fn chunks_both_ways() {
+        let mut expected = "lorem ipsum\n".repeat(20);
+        let pt = table(&mut expected);
+
+        for char_idx in (0..=pt.len_chars()).step_by(11) {
+            let byte_idx = pt.char_to_byte(char_idx);
+            let mut chunks = pt.chunks_at(char_idx);
+            assert_eq!(
+                chunks.clone().collect::<String>(),
+                expected[byte_idx..]
+            );
+
+            let mut back = chunks.clone().rev().collect::<Vec<_>>();
+            back.reverse();
+            assert_eq!(back.concat(), expected[byte_idx..]);
+
+            let mut before = vec![];
+            while let Some(chunk) = chunks.prev() {
+                assert!(!chunk.is_empty());
+                before.push(chunk);
+            }
+            before.reverse();
+            assert_eq!(before.concat(), expected[..byte_idx]);
+        }
+    }
+
+    #[test]
+    fn meet_in_the_middle() {
+        let mut expected = "lorem ipsum\n".repeat(20);
+        let pt = table(&mut expected);
+
+        let mut chars = pt.chars_at(0);
+        let mut collected = String::new();
+        let mut back = vec![];
+        for i in 0.. {
+            let next = if i % 3 == 0 {
+                chars.next_back().map(|c| back.push(c))
+            } else {
+                chars.next().map(|c| collected.push(c))
+            };
+            if next.is_none() && chars.clone().next().is_none() {
+                break;
+            }
+        }
+        collected.extend(back.iter().rev());
+        assert_eq!(collected, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "lines")]
+    fn lines_both_ways() {
+        let mut expected = "lorem ipsum\n".repeat(20);
+        let pt = table(&mut expected);
+        let lines = expected
+            .split('\n')
+            .map(|l| l.trim_end_matches('\r'))
+            .collect::<Vec<_>>();
+        assert_eq!(pt.len_lines(), lines.len());
+
+        for line_idx in 0..=lines.len() {
+            let mut iter = pt.lines_at(line_idx);
+            assert!(
+                iter.clone()
+                    .map(|l| l.to_string())
+                    .eq(lines[line_idx..].iter().copied())
+            );
+            assert!(
+                iter.clone()
+                    .rev()
+                    .map(|l| l.to_string())
+                    .eq(lines[line_idx..].iter().rev().copied())
+            );
+            for line in lines[..line_idx].iter().rev() {
+                assert_eq!(iter.prev().unwrap().to_string(), *line);
+            }
+            assert!(iter.prev().is_none());
+        }
+    }
+}
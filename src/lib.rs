@@ -3,40 +3,84 @@
 //!
 //! [Piece Table]: https://en.wikipedia.org/wiki/Piece_table
 
-#![feature(test, if_let_guard, stmt_expr_attributes)]
+#![feature(test, stmt_expr_attributes)]
 
 mod buffer;
+mod builder;
+mod change_set;
+mod crlf;
+mod error;
+mod history;
+mod io;
+mod iter;
 #[cfg(feature = "lines")]
 mod line;
+#[cfg(feature = "lines")]
+mod line_ending;
+#[cfg(feature = "lines")]
+pub mod lsp;
+#[cfg(feature = "mmap")]
+mod mmap;
 mod piece;
-mod rbtree;
+#[cfg(feature = "regex")]
+mod regex;
+mod replace;
+mod search;
 mod slice;
+mod snapshot;
 mod str_utils;
+mod tree;
+mod validate;
 
-use buffer::{BufferType, Buffers};
+use std::sync::Arc;
+
+use buffer::{BufferType, Buffers, Original};
+pub use change_set::{Assoc, ChangeSet, Operation};
+pub use crlf::CrlfPolicy;
+pub use error::Error;
+use history::{Change, History, Move};
+#[cfg(feature = "lines")]
+pub use iter::Lines;
+pub use iter::{Bytes, Chars, Chunks};
+#[cfg(feature = "lines")]
+pub use line::{Break as LineBreak, LineBreakConfig};
+#[cfg(feature = "lines")]
+pub use line_ending::{LineEnding, LineEndings};
 use piece::Piece;
-use slice::Slice;
+#[cfg(feature = "regex")]
+pub use regex::{
+    Regex, RegexBuildError, RegexMatch, RegexMatches, RegexRMatches,
+};
+pub use search::{Matches, RMatches};
+pub use slice::Slice;
+pub use snapshot::Snapshot;
+#[cfg(feature = "lines")]
+use tree::{Metrics, Size};
+use tree::{NodeIdx, Tree};
+pub use validate::{Inconsistency, Metric};
 
 #[derive(Debug)]
 pub struct PieceTable<'b> {
-    pieces: Vec<Piece>,
+    tree: Tree,
     buffers: Buffers<'b>,
-
-    len_bytes: usize,
-    len_chars: usize,
-    #[cfg(feature = "lines")]
-    len_lines: usize,
+    history: History,
+    crlf_policy: CrlfPolicy,
 
     /// The char index after the last insertion, and the piece the last
-    /// insertion was inserting to (i.e., `(char_idx, piece_idx)`). If there is
-    /// no last insertion, or the last edit is not an insertion (thus
-    /// invalidating the `last_insert` value), it will contain a [`None`].
+    /// insertion was inserting to (i.e., `(char_idx, node)`). If there is no
+    /// last insertion, or the last edit is not an insertion (thus invalidating
+    /// the `last_insert` value), it will contain a [`None`].
     ///
     /// This is used as an optimization, so that instead of creating a new
     /// piece when inserting contiguous text (for every insert), we will just
     /// expand the last piece.
     #[cfg(feature = "contiguous-inserts")]
-    last_insert: Option<(usize, usize)>,
+    last_insert: Option<(usize, NodeIdx)>,
+
+    /// The edits made to the table that were not taken yet, if they are
+    /// being recorded. See [`PieceTable::record_changes`].
+    #[cfg(feature = "lines")]
+    changes: Option<Vec<lsp::ContentChange>>,
 }
 
 impl<'b> PieceTable<'b> {
@@ -50,30 +94,96 @@ impl<'b> PieceTable<'b> {
     /// assert_eq!(pt.text(), "initial");
     /// ```
     pub fn new(initial: &'b str) -> Self {
-        let buffers = Buffers::from_initial(initial);
-        let initial_piece = Piece {
-            buffer: BufferType::Original,
-            start: 0,
-            len_bytes: initial.len(),
-            len_chars: str_utils::count_chars(initial),
-            first_line_break: if buffers.original.line_breaks.is_empty() {
-                None
-            } else {
-                Some(0)
-            },
-        };
+        Self::from_original(Original::Borrowed(initial))
+    }
+
+    /// Create a new [`PieceTable`] with the initial contents set to `initial`,
+    /// in which the line breaks are the ones selected by `config`.
+    ///
+    /// This scans `initial` for line breaks once, unlike creating the table
+    /// with [`PieceTable::new`] and then calling
+    /// [`PieceTable::with_line_breaks`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{LineBreakConfig, PieceTable};
+    /// let pt = PieceTable::new_with_line_breaks("a\r\nb", LineBreakConfig::Lf);
+    /// assert_eq!(pt.line(0).to_string(), "a\r");
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn new_with_line_breaks(
+        initial: &'b str,
+        config: LineBreakConfig,
+    ) -> Self {
+        let mut buffers = Buffers::from_original(Original::Borrowed(initial));
+        buffers.line_break_config = config;
+        Self::from_buffers(buffers)
+    }
+
+    /// Convert the table to a table which owns all of its contents, by copying
+    /// the initial contents if they are borrowed. The history of the table is
+    /// kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// fn open() -> PieceTable<'static> {
+    ///     let initial = String::from("hello");
+    ///     let mut pt = PieceTable::new(&initial);
+    ///     pt.insert(5, " world");
+    ///     pt.into_owned()
+    /// }
+    ///
+    /// let mut pt = open();
+    /// assert_eq!(pt.text(), "hello world");
+    /// pt.undo();
+    /// assert_eq!(pt.text(), "hello");
+    /// ```
+    pub fn into_owned(self) -> PieceTable<'static> {
+        PieceTable {
+            tree: self.tree,
+            buffers: self.buffers.into_owned(),
+            history: self.history,
+            crlf_policy: self.crlf_policy,
+
+            #[cfg(feature = "contiguous-inserts")]
+            last_insert: self.last_insert,
 
-        Self {
-            len_bytes: initial.len(),
-            len_chars: str_utils::count_chars(initial),
             #[cfg(feature = "lines")]
-            len_lines: buffers.original.line_breaks.len() + 1,
+            changes: self.changes,
+        }
+    }
+
+    fn from_original(original: Original<'b>) -> Self {
+        Self::from_buffers(Buffers::from_original(original))
+    }
+
+    /// Create a table from buffers which have only the `original` buffer, with
+    /// a piece for each of its regions. The pieces are scanned (for their line
+    /// breaks and UTF-16 lengths) only when they are first needed.
+    fn from_buffers(buffers: Buffers<'b>) -> Self {
+        let mut tree = Tree::new();
+        for region in &buffers.original.regions {
+            tree.push_back(Piece::unscanned(
+                BufferType::Original,
+                region.range.clone(),
+                region.len_chars,
+            ));
+        }
+
+        Self {
+            tree,
+            buffers,
+            history: History::default(),
+            crlf_policy: CrlfPolicy::default(),
 
             #[cfg(feature = "contiguous-inserts")]
             last_insert: None,
 
-            buffers,
-            pieces: vec![initial_piece],
+            #[cfg(feature = "lines")]
+            changes: None,
         }
     }
 
@@ -91,18 +201,98 @@ impl<'b> PieceTable<'b> {
     /// assert_eq!(pt.text(), "abcd, content");
     /// ```
     pub fn text(&self) -> String {
-        let mut text = String::with_capacity(self.len_bytes);
+        let mut text = String::with_capacity(self.len_bytes());
 
-        for piece in &self.pieces {
-            text.push_str(&self.buffers[piece.buffer][piece.byte_range()]);
+        for chunk in self.iter() {
+            text.push_str(chunk);
         }
 
-        debug_assert_eq!(text.len(), self.len_bytes);
-        debug_assert_eq!(str_utils::count_chars(&text), self.len_chars);
+        debug_assert_eq!(text.len(), self.len_bytes());
+        debug_assert_eq!(str_utils::count_chars(&text), self.len_chars());
 
         text
     }
 
+    /// Returns a read-only [`Snapshot`] of the current contents of the table,
+    /// which is unaffected by later edits. Snapshots are `Send` and `Sync`, so
+    /// they can be handed to readers on other threads.
+    ///
+    /// The text and the tree of pieces are shared between the table and its
+    /// snapshots, and the table copies only the parts of the tree that it edits
+    /// while they are shared. Runs in `O(1)`, besides cloning a handle to every
+    /// 64 KiB segment of the inserted text.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("hello");
+    /// let snapshot = pt.snapshot();
+    /// pt.insert(5, " world");
+    ///
+    /// let len = std::thread::scope(|s| {
+    ///     s.spawn(|| snapshot.len_chars()).join().unwrap()
+    /// });
+    /// assert_eq!(len, 5);
+    /// assert_eq!(snapshot.text(), "hello");
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<'b> {
+        Snapshot::new(Self {
+            tree: self.tree.clone(),
+            buffers: self.buffers.clone(),
+            history: History::default(),
+            crlf_policy: self.crlf_policy,
+
+            #[cfg(feature = "contiguous-inserts")]
+            last_insert: None,
+
+            #[cfg(feature = "lines")]
+            changes: None,
+        })
+    }
+
+    /// Returns a [`Slice`] of the chars in `char_range`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the end of the range is larger than the size of the
+    /// contents, or if its start is larger than its end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("hello world");
+    /// pt.insert(5, ",");
+    /// assert_eq!(pt.slice(3..8).to_string(), "lo, w");
+    /// assert_eq!(pt.slice(7..).to_string(), "world");
+    /// ```
+    pub fn slice<R>(&self, char_range: R) -> Slice<'_>
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        error::unwrap(self.try_slice(char_range))
+    }
+
+    /// Like [`PieceTable::slice`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidRange`] if the start of the range is larger than
+    /// its end, and [`Error::OutOfBounds`] if its end is larger than the size
+    /// of the contents.
+    pub fn try_slice<R>(&self, char_range: R) -> Result<Slice<'_>, Error>
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        let (start, end) =
+            Self::simplify_range_bounds(char_range, self.len_chars())?;
+        error::check_range(start, end, self.len_chars())?;
+
+        Ok(Slice::new(self, start..end))
+    }
+
     /// Returns a [`Slice`] containing the `line_idx`-th line, **without** the
     /// line break sequence.
     ///
@@ -119,40 +309,292 @@ impl<'b> PieceTable<'b> {
     /// assert_eq!(pt.line(1).to_string(), "Second");
     /// ```
     #[cfg(feature = "lines")]
-    pub fn line(&self, line_idx: usize) -> Slice {
-        assert!(line_idx < self.len_lines, "line index out of bounds");
+    pub fn line(&self, line_idx: usize) -> Slice<'_> {
+        assert!(self.has_line(line_idx), "line index out of bounds");
+
+        let start = self.line_to_char(line_idx);
+        let end = self.line_end(line_idx);
+
+        Slice::new(self, start..end)
+    }
+
+    /// Like [`PieceTable::line`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if there is no such line.
+    #[cfg(feature = "lines")]
+    pub fn try_line(&self, line_idx: usize) -> Result<Slice<'_>, Error> {
+        self.check_line_idx(line_idx)?;
+        Ok(self.line(line_idx))
+    }
 
-        let mut current_line = 0;
-        let mut start = (0, 0);
+    /// Returns a [`Slice`] containing the `line_idx`-th line, **with** its
+    /// line break sequence (if it has one, as the last line doesn't).
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is out of bounds (i.e., there is no such line).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\r\ntwo");
+    /// assert_eq!(pt.line_with_ending(0), "one\r\n");
+    /// assert_eq!(pt.line_with_ending(1), "two");
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn line_with_ending(&self, line_idx: usize) -> Slice<'_> {
+        assert!(self.has_line(line_idx), "line index out of bounds");
 
-        for (piece_idx, piece) in self.pieces.iter().enumerate() {
-            let Some(first_lb) = piece.first_line_break else { continue };
+        let start = self.line_to_char(line_idx);
+        let end = self.line_to_char(line_idx + 1);
 
-            let line_breaks = self.buffers.line_breaks(piece.buffer)
-                [first_lb..]
-                .iter()
-                .take_while(|(i, _)| *i < piece.byte_range().end);
+        Slice::new(self, start..end)
+    }
 
-            for &(idx, ty) in line_breaks {
-                let relative_idx = idx - piece.start;
+    /// Like [`PieceTable::line_with_ending`], but returns an [`Error`] instead
+    /// of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if there is no such line.
+    #[cfg(feature = "lines")]
+    pub fn try_line_with_ending(
+        &self,
+        line_idx: usize,
+    ) -> Result<Slice<'_>, Error> {
+        self.check_line_idx(line_idx)?;
+        Ok(self.line_with_ending(line_idx))
+    }
 
-                if current_line == line_idx {
-                    let end = (piece_idx, relative_idx);
-                    return Slice::new(start, end, self);
-                }
-                if current_line + 1 == line_idx {
-                    start = (piece_idx, relative_idx + ty.len_bytes());
-                }
+    /// Returns the line break which ends the `line_idx`-th line, or [`None`]
+    /// for the last line (which doesn't have one).
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is out of bounds (i.e., there is no such line).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{LineBreak, PieceTable};
+    /// let pt = PieceTable::new("one\r\ntwo\nthree");
+    ///
+    /// let text = (0..pt.len_lines())
+    ///     .map(|i| {
+    ///         let ending = pt.line_ending(i).map_or("", |b| b.as_str());
+    ///         pt.line(i).to_string() + ending
+    ///     })
+    ///     .collect::<String>();
+    /// assert_eq!(text, pt.text());
+    /// assert_eq!(pt.line_ending(0), Some(LineBreak::Crlf));
+    /// assert_eq!(pt.line_ending(2), None);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn line_ending(&self, line_idx: usize) -> Option<LineBreak> {
+        assert!(self.has_line(line_idx), "line index out of bounds");
+        self.find_line_break(line_idx).map(|(_position, ty)| ty)
+    }
 
-                current_line += 1;
-            }
-        }
+    /// Like [`PieceTable::line_ending`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if there is no such line.
+    #[cfg(feature = "lines")]
+    pub fn try_line_ending(
+        &self,
+        line_idx: usize,
+    ) -> Result<Option<LineBreak>, Error> {
+        self.check_line_idx(line_idx)?;
+        Ok(self.line_ending(line_idx))
+    }
 
-        debug_assert_eq!(line_idx, self.len_lines - 1);
+    /// Returns the index of the line that contains the char at `char_idx`.
+    ///
+    /// This is the same as counting the line breaks that end before (or at)
+    /// `char_idx`. Notice that `char_idx` can be one past the end of the
+    /// table.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\ntwo\r\nthree");
+    /// assert_eq!(pt.char_to_line(3), 0);
+    /// assert_eq!(pt.char_to_line(4), 1);
+    /// assert_eq!(pt.char_to_line(8), 1); // the LF of the CRLF
+    /// assert_eq!(pt.char_to_line(9), 2);
+    /// assert_eq!(pt.char_to_line(14), 2);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        assert!(char_idx <= self.len_chars(), "index out of bounds");
+
+        let Some((before, node)) = self.tree.node_at_char(char_idx) else {
+            return 0; // the table is empty
+        };
+        let piece = self.tree.piece(node);
+        let text = piece.text(&self.buffers);
+        let byte_idx = str_utils::char_to_byte(text, char_idx - before.chars);
+
+        self.tree.metrics_before(node, &self.buffers).line_breaks
+            + self.count_line_breaks_before(piece, piece.start + byte_idx)
+    }
+
+    /// Like [`PieceTable::char_to_line`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    #[cfg(feature = "lines")]
+    pub fn try_char_to_line(&self, char_idx: usize) -> Result<usize, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.char_to_line(char_idx))
+    }
+
+    /// Returns the index of the line that contains the byte at `byte_idx`.
+    ///
+    /// This is the same as counting the line breaks that end before (or at)
+    /// `byte_idx`. Notice that `byte_idx` can be one past the end of the
+    /// table.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `byte_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("ab\n⑤\ncd");
+    /// assert_eq!(pt.byte_to_line(2), 0);
+    /// assert_eq!(pt.byte_to_line(3), 1);
+    /// assert_eq!(pt.byte_to_line(6), 1);
+    /// assert_eq!(pt.byte_to_line(7), 2);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn byte_to_line(&self, byte_idx: usize) -> usize {
+        assert!(byte_idx <= self.len_bytes(), "index out of bounds");
+
+        let Some((before, node)) = self.tree.node_at_byte(byte_idx) else {
+            return 0; // the table is empty
+        };
+        let piece = self.tree.piece(node);
+        let relative_idx = byte_idx - before.bytes;
+
+        self.tree.metrics_before(node, &self.buffers).line_breaks
+            + self.count_line_breaks_before(piece, piece.start + relative_idx)
+    }
+
+    /// Like [`PieceTable::byte_to_line`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `byte_idx` is larger than the size of
+    /// the contents.
+    #[cfg(feature = "lines")]
+    pub fn try_byte_to_line(&self, byte_idx: usize) -> Result<usize, Error> {
+        error::check_idx(byte_idx, self.len_bytes())?;
+        Ok(self.byte_to_line(byte_idx))
+    }
+
+    /// Returns the char index of the start of the `line_idx`-th line.
+    ///
+    /// Notice that `line_idx` can be one past the last line, in which case the
+    /// length of the table is returned.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is larger than the amount of lines.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\ntwo\r\nthree");
+    /// assert_eq!(pt.line_to_char(0), 0);
+    /// assert_eq!(pt.line_to_char(1), 4);
+    /// assert_eq!(pt.line_to_char(2), 9);
+    /// assert_eq!(pt.line_to_char(3), 14);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn line_to_char(&self, line_idx: usize) -> usize {
+        let Ok(start) = self.line_start(line_idx) else {
+            panic!("line index out of bounds");
+        };
+        start.chars
+    }
+
+    /// Like [`PieceTable::line_to_char`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `line_idx` is larger than the amount
+    /// of lines.
+    #[cfg(feature = "lines")]
+    pub fn try_line_to_char(&self, line_idx: usize) -> Result<usize, Error> {
+        Ok(self.line_start(line_idx)?.chars)
+    }
+
+    /// Returns the byte index of the start of the `line_idx`-th line.
+    ///
+    /// Notice that `line_idx` can be one past the last line, in which case the
+    /// length of the table (in bytes) is returned.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is larger than the amount of lines.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("ab\n⑤\ncd");
+    /// assert_eq!(pt.line_to_byte(1), 3);
+    /// assert_eq!(pt.line_to_byte(2), 7);
+    /// assert_eq!(pt.line_to_byte(3), 9);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn line_to_byte(&self, line_idx: usize) -> usize {
+        let Ok(start) = self.line_start(line_idx) else {
+            panic!("line index out of bounds");
+        };
+        start.bytes
+    }
 
-        let last_idx = self.pieces.len() - 1;
-        let end_byte = self.pieces[last_idx].len_bytes;
-        Slice::new(start, (last_idx, end_byte), self)
+    /// Like [`PieceTable::line_to_byte`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `line_idx` is larger than the amount
+    /// of lines.
+    #[cfg(feature = "lines")]
+    pub fn try_line_to_byte(&self, line_idx: usize) -> Result<usize, Error> {
+        Ok(self.line_start(line_idx)?.bytes)
     }
 
     /// Removes the text in the given char index range.
@@ -178,37 +620,70 @@ impl<'b> PieceTable<'b> {
     /// ```
     /// # use peace_table::PieceTable;
     /// let mut pt = PieceTable::new("012345");
-    /// pt.remove(5..0); // an empty range
+    /// pt.remove(5..5); // an empty range
     /// assert_eq!(pt.text(), "012345"); // unchanged
     /// ```
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the start of the range is larger than its end, if its end
+    /// is larger than the size of the contents, or if the start or the end of
+    /// a non-empty range are inside a CRLF sequence (and the table's
+    /// [`CrlfPolicy`] rejects it).
     pub fn remove<R>(&mut self, range: R)
     where
         R: std::ops::RangeBounds<usize>,
     {
-        let (start, end) = self.simplify_range_bounds(range);
-        if start >= end {
-            return; // the range is empty
-        }
-
-        // If the removal is _after_ the index of the last insert, it does not
-        // affect it.
-        #[cfg(feature = "contiguous-inserts")]
-        if self.last_insert.is_some_and(|(i, _piece)| i >= start) {
-            self.last_insert = None;
-        }
-
-        let (start_piece_idx, start_char_idx) = self.piece_at_char(start);
-        let (end_piece_idx, end_char_idx) = self.piece_at_char(end);
+        error::unwrap(self.try_remove(range));
+    }
 
-        if start_piece_idx == end_piece_idx {
-            let piece_idx = start_piece_idx;
-            self.remove_within_piece(piece_idx, start_char_idx, end_char_idx);
-            return;
+    /// Like [`PieceTable::remove`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidRange`] if the start of the range is larger than
+    /// its end, [`Error::OutOfBounds`] if its end is larger than the size of
+    /// the contents, and [`Error::CrlfSplit`] if the start or the end of a
+    /// non-empty range are between the CR and the LF of a CRLF sequence (and
+    /// the table's [`CrlfPolicy`] rejects it).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{Error, PieceTable};
+    /// let mut pt = PieceTable::new("one\r\ntwo");
+    /// assert_eq!(pt.try_remove(2..4), Err(Error::CrlfSplit { char_idx: 4 }));
+    /// assert_eq!(
+    ///     pt.try_remove(9..12),
+    ///     Err(Error::OutOfBounds { idx: 12, len: 8 })
+    /// );
+    /// assert_eq!(
+    ///     pt.try_remove(9..3),
+    ///     Err(Error::InvalidRange { start: 9, end: 3 })
+    /// );
+    /// assert_eq!(
+    ///     pt.try_remove(0..=usize::MAX),
+    ///     Err(Error::OutOfBounds { idx: usize::MAX, len: 8 })
+    /// );
+    /// assert_eq!(pt.try_remove(3..5), Ok(()));
+    /// assert_eq!(pt.text(), "onetwo");
+    /// ```
+    pub fn try_remove<R>(&mut self, range: R) -> Result<(), Error>
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        let (start, end) =
+            Self::simplify_range_bounds(range, self.len_chars())?;
+        error::check_range(start, end, self.len_chars())?;
+        if start == end {
+            return Ok(()); // the range is empty
         }
 
-        self.trim_piece_start(end_piece_idx, end_char_idx);
-        self.remove_pieces(start_piece_idx + 1..end_piece_idx);
-        self.trim_piece_end(start_piece_idx, start_char_idx);
+        let (start, end, text) =
+            self.fit_crlf(start, end, "", self.crlf_policy)?;
+        self.splice(start, end, &text);
+        Ok(())
     }
 
     /// Insert `content` at position `index`.
@@ -225,7 +700,8 @@ impl<'b> PieceTable<'b> {
     ///
     /// # Panics
     ///
-    /// Will panic if index is larger than the size of the contents.
+    /// Will panic if index is larger than the size of the contents, or if it
+    /// is inside a CRLF sequence (and the table's [`CrlfPolicy`] rejects it).
     ///
     /// ```should_panic
     /// # use peace_table::PieceTable;
@@ -233,112 +709,961 @@ impl<'b> PieceTable<'b> {
     /// pt.insert(4, " "); // will panic
     /// ```
     pub fn insert(&mut self, char_idx: usize, text: &str) {
-        let len_chars = str_utils::count_chars(text);
+        error::unwrap(self.try_insert(char_idx, text));
+    }
+
+    /// Like [`PieceTable::insert`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents, and [`Error::CrlfSplit`] if it is between the CR and the
+    /// LF of a CRLF sequence (and the table's [`CrlfPolicy`] rejects it).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{Error, PieceTable};
+    /// let mut pt = PieceTable::new("a\r\nb");
+    /// assert_eq!(
+    ///     pt.try_insert(5, "c"),
+    ///     Err(Error::OutOfBounds { idx: 5, len: 4 })
+    /// );
+    /// assert_eq!(pt.try_insert(2, "c"), Err(Error::CrlfSplit { char_idx: 2 }));
+    /// assert_eq!(pt.try_insert(1, "c"), Ok(()));
+    /// assert_eq!(pt.text(), "ac\r\nb");
+    /// ```
+    pub fn try_insert(
+        &mut self,
+        char_idx: usize,
+        text: &str,
+    ) -> Result<(), Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let (start, end, text) =
+            self.fit_crlf(char_idx, char_idx, text, self.crlf_policy)?;
+        if start < end {
+            self.splice(start, end, &text);
+            return Ok(());
+        }
+        let (char_idx, text) = (start, &*text);
 
-        self.len_chars += len_chars;
-        self.len_bytes += text.len();
+        #[cfg(feature = "lines")]
+        self.record_insert(char_idx, text);
 
         #[cfg(feature = "contiguous-inserts")]
-        if let Some((ref mut i, piece_idx)) = self.last_insert
-            && *i == char_idx
+        if let Some((i, node)) = self.last_insert
+            && i == char_idx
+            && self.tree.piece(node).len_bytes + text.len()
+                <= Piece::MAX_LEN_BYTES
+            && self.buffers.fits_last_segment(text.len())
         {
-            *i += len_chars;
-            self.extend_piece(text, len_chars, piece_idx);
-            return;
+            let piece = self.extend_piece(text, node);
+            self.last_insert = Some((i + piece.len_chars, node));
+            self.history.record(Change {
+                char_idx,
+                removed: vec![],
+                inserted: vec![piece],
+            });
+            return Ok(());
         }
 
-        let (piece_idx, relative_char_idx) = self.piece_at_char(char_idx);
+        let pieces = self.append(text);
+        #[cfg(feature = "contiguous-inserts")]
+        let len_chars = pieces.iter().map(|p| p.len_chars).sum::<usize>();
 
-        if relative_char_idx == 0 {
-            self.insert_piece(piece_idx, text);
-        } else if relative_char_idx == self.pieces[piece_idx].len_chars {
-            self.insert_piece(piece_idx + 1, text);
-        } else {
-            // This is guarenteed to be a valid char index inside the piece, due
-            // to an earlier assertion in `piece_at_char`.
-            self.split_piece_and_insert(piece_idx, relative_char_idx, text);
-        }
+        self.history.record(Change {
+            char_idx,
+            removed: vec![],
+            inserted: pieces.clone(),
+        });
+
+        #[cfg_attr(
+            not(feature = "contiguous-inserts"),
+            expect(unused_variables)
+        )]
+        let last = self.insert_pieces(char_idx, pieces);
 
         #[cfg(feature = "contiguous-inserts")]
         {
-            let piece_idx =
-                if relative_char_idx == 0 { piece_idx } else { piece_idx + 1 };
-            self.last_insert = Some((char_idx + len_chars, piece_idx));
+            self.last_insert = Some((char_idx + len_chars, last));
         }
+        Ok(())
     }
 
-    /// Total number of chars in the piece table.
+    /// Undo the last step in the history of the table, and return the cursor
+    /// positions from before it. Returns [`None`] if there is nothing to undo.
     ///
-    /// Runs in `O(1)`.
+    /// Every call to [`PieceTable::insert`] or [`PieceTable::remove`] is a
+    /// step of its own, unless it is made inside a group (see
+    /// [`PieceTable::begin_group`]).
     ///
     /// # Examples
     ///
     /// ```
     /// # use peace_table::PieceTable;
-    /// let mut pt = PieceTable::new("123456");
-    /// assert_eq!(pt.len_chars(), 6);
-    /// ```
+    /// let mut pt = PieceTable::new("hello");
+    /// pt.insert(5, " world");
+    /// pt.remove(0..1);
+    ///
+    /// assert_eq!(pt.undo(), Some(vec![0]));
+    /// assert_eq!(pt.text(), "hello world");
+    /// assert_eq!(pt.undo(), Some(vec![5]));
+    /// assert_eq!(pt.text(), "hello");
+    /// assert_eq!(pt.undo(), None);
+    /// ```
+    pub fn undo(&mut self) -> Option<Vec<usize>> {
+        self.travel(History::undo)
+    }
+
+    /// Redo the last undone step, and return the cursor positions from after
+    /// it. Returns [`None`] if there is nothing to redo.
+    ///
+    /// If several branches were made on top of the current revision (by
+    /// undoing and making new edits), the one that was visited last is redone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("hello");
+    /// pt.insert(5, " world");
+    /// pt.undo();
+    ///
+    /// assert_eq!(pt.redo(), Some(vec![11]));
+    /// assert_eq!(pt.text(), "hello world");
+    /// assert_eq!(pt.redo(), None);
+    /// ```
+    pub fn redo(&mut self) -> Option<Vec<usize>> {
+        self.travel(History::redo)
+    }
+
+    /// The revision the table is currently at.
+    ///
+    /// Revision `0` is the initial state of the table, and every step (see
+    /// [`PieceTable::undo`]) creates a new revision, numbered in the order
+    /// they were created. Undoing and making a new edit does not discard the
+    /// undone revisions, but creates a new branch instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("");
+    /// assert_eq!(pt.revision(), 0);
+    /// pt.insert(0, "a");
+    /// pt.undo();
+    /// pt.insert(0, "b");
+    /// assert_eq!(pt.revision(), 2);
+    /// ```
+    pub fn revision(&self) -> usize {
+        self.history.current()
+    }
+
+    /// The revisions which have no children, i.e., the latest revision of
+    /// every branch, in the order they were created.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("");
+    /// pt.insert(0, "a");
+    /// pt.insert(1, "b");
+    /// pt.undo();
+    /// pt.insert(1, "c");
+    /// assert_eq!(pt.leaf_revisions(), vec![2, 3]);
+    /// ```
+    pub fn leaf_revisions(&self) -> Vec<usize> {
+        self.history.leaves().collect()
+    }
+
+    /// Move the table to `revision`, undoing and redoing steps along the way,
+    /// and return the cursor positions of the last step that was undone or
+    /// redone. Returns [`None`] if the table is already at `revision`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if there is no such revision.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("");
+    /// pt.insert(0, "a");
+    /// pt.undo();
+    /// pt.insert(0, "b");
+    ///
+    /// assert_eq!(pt.jump_to_revision(1), Some(vec![1]));
+    /// assert_eq!(pt.text(), "a");
+    /// ```
+    pub fn jump_to_revision(&mut self, revision: usize) -> Option<Vec<usize>> {
+        self.travel(|history| history.jump_to(revision))
+    }
+
+    /// Move `n` revisions back in time (like vim's `:earlier`), regardless of
+    /// the branch they are in. See [`PieceTable::jump_to_revision`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("");
+    /// pt.insert(0, "a");
+    /// pt.undo();
+    /// pt.insert(0, "b");
+    ///
+    /// pt.earlier(1);
+    /// assert_eq!(pt.text(), "a"); // `undo` would go back to ""
+    /// pt.earlier(1);
+    /// assert_eq!(pt.text(), "");
+    /// ```
+    pub fn earlier(&mut self, n: usize) -> Option<Vec<usize>> {
+        self.travel(|history| {
+            history.jump_to(history.current().saturating_sub(n))
+        })
+    }
+
+    /// Move `n` revisions forward in time (like vim's `:later`), regardless of
+    /// the branch they are in. See [`PieceTable::jump_to_revision`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("");
+    /// pt.insert(0, "a");
+    /// pt.undo();
+    /// pt.insert(0, "b");
+    /// pt.jump_to_revision(0);
+    ///
+    /// pt.later(1);
+    /// assert_eq!(pt.text(), "a");
+    /// pt.later(5);
+    /// assert_eq!(pt.text(), "b");
+    /// ```
+    pub fn later(&mut self, n: usize) -> Option<Vec<usize>> {
+        self.travel(|history| {
+            let last = history.len() - 1;
+            history.jump_to(history.current().saturating_add(n).min(last))
+        })
+    }
+
+    /// Whether there is a step that [`PieceTable::undo`] can undo.
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    /// Whether there is a step that [`PieceTable::redo`] can redo.
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Begin a group of edits, which will be undone and redone as a single
+    /// step. `cursors` are the cursor positions before the group, which
+    /// [`PieceTable::undo`] returns when undoing it.
+    ///
+    /// Groups can be nested, in which case only the outermost group counts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("a b c");
+    /// pt.begin_group(&[1, 3, 5]);
+    /// pt.insert(5, "!");
+    /// pt.insert(3, "!");
+    /// pt.insert(1, "!");
+    /// pt.end_group(&[2, 5, 8]);
+    /// assert_eq!(pt.text(), "a! b! c!");
+    ///
+    /// assert_eq!(pt.undo(), Some(vec![1, 3, 5]));
+    /// assert_eq!(pt.text(), "a b c");
+    /// assert_eq!(pt.redo(), Some(vec![2, 5, 8]));
+    /// ```
+    pub fn begin_group(&mut self, cursors: &[usize]) {
+        self.history.begin_group(cursors);
+    }
+
+    /// End a group of edits that was begun with [`PieceTable::begin_group`].
+    /// `cursors` are the cursor positions after the group, which
+    /// [`PieceTable::redo`] returns when redoing it.
+    pub fn end_group(&mut self, cursors: &[usize]) {
+        self.history.end_group(cursors);
+    }
+
+    /// Total number of chars in the piece table.
+    ///
+    /// Runs in `O(1)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("123456");
+    /// assert_eq!(pt.len_chars(), 6);
+    /// ```
     #[inline(always)]
     pub fn len_chars(&self) -> usize {
-        self.len_chars
+        self.tree.size().chars
+    }
+
+    /// Total number of bytes in the piece table.
+    ///
+    /// Runs in `O(1)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("1234⑤");
+    /// assert_eq!(pt.len_bytes(), 7); // the 5 takes 3 bytes
+    /// ```
+    #[inline(always)]
+    pub fn len_bytes(&self) -> usize {
+        self.tree.size().bytes
+    }
+
+    /// Set which chars (and sequences of chars) are line breaks in the table.
+    /// The default is [`LineBreakConfig::default`].
+    ///
+    /// This finds the line breaks of the inserted text again (including the
+    /// text of the history), which runs in `O(N)` where `N` is its size. The
+    /// original text is scanned again only when it is needed. To select the
+    /// line breaks of a new table, create it with
+    /// [`PieceTable::new_with_line_breaks`] instead, which scans the text only
+    /// once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{LineBreakConfig, PieceTable};
+    /// let text = "one\r\ntwo\u{2028}three";
+    /// let pt = PieceTable::new(text).with_line_breaks(LineBreakConfig::Lf);
+    /// assert_eq!(pt.len_lines(), 2);
+    /// assert_eq!(pt.line(0).to_string(), "one\r");
+    ///
+    /// let pt = pt.with_line_breaks(LineBreakConfig::LfCrlf);
+    /// assert_eq!(pt.line(0).to_string(), "one");
+    /// assert_eq!(pt.line(1).to_string(), "two\u{2028}three");
+    ///
+    /// # #[cfg(feature = "unicode-line-breaks")]
+    /// # {
+    /// let pt = pt.with_line_breaks(LineBreakConfig::Unicode);
+    /// assert_eq!(pt.len_lines(), 3);
+    /// # }
+    /// ```
+    #[cfg(feature = "lines")]
+    #[must_use]
+    pub fn with_line_breaks(mut self, config: LineBreakConfig) -> Self {
+        if config == self.buffers.line_break_config {
+            return self;
+        }
+        self.buffers.rescan(config);
+
+        let mut tree = Tree::new();
+        for piece in self.tree.iter() {
+            let mut piece = piece.clone();
+            piece.forget_scanned();
+            tree.push_back(piece);
+        }
+        self.tree = tree;
+        for piece in self.history.pieces_mut() {
+            piece.forget_scanned();
+        }
+
+        #[cfg(feature = "contiguous-inserts")]
+        {
+            self.last_insert = None;
+        }
+        self
+    }
+
+    /// Returns which chars are line breaks in the table (see
+    /// [`PieceTable::with_line_breaks`]).
+    #[cfg(feature = "lines")]
+    pub const fn line_break_config(&self) -> LineBreakConfig {
+        self.buffers.line_break_config
+    }
+
+    /// Total number of lines in the piece table.
+    ///
+    /// Runs in `O(1)`, once the whole text was scanned for line breaks (the
+    /// first call scans the parts of it which weren't yet).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("first\nsecond");
+    /// assert_eq!(pt.len_lines(), 2);
+    /// pt.remove(5..6);
+    /// assert_eq!(pt.len_lines(), 1);
+    /// ```
+    #[cfg(feature = "lines")]
+    #[inline(always)]
+    pub fn len_lines(&self) -> usize {
+        self.tree.total(&self.buffers).line_breaks + 1
+    }
+
+    /// Returns the byte index of the char at `char_idx`.
+    ///
+    /// Notice that `char_idx` can be one past the end of the table, in which
+    /// case the length of the table (in bytes) is returned.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("a⑤b");
+    /// assert_eq!(pt.char_to_byte(1), 1);
+    /// assert_eq!(pt.char_to_byte(2), 4);
+    /// assert_eq!(pt.char_to_byte(3), 5);
+    /// ```
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        assert!(char_idx <= self.len_chars(), "index out of bounds");
+
+        let Some((before, node)) = self.tree.node_at_char(char_idx) else {
+            return 0; // the table is empty
+        };
+        let text = self.tree.piece(node).text(&self.buffers);
+        before.bytes + str_utils::char_to_byte(text, char_idx - before.chars)
+    }
+
+    /// Like [`PieceTable::char_to_byte`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    pub fn try_char_to_byte(&self, char_idx: usize) -> Result<usize, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.char_to_byte(char_idx))
+    }
+
+    /// Returns the char index of the char that contains the byte at
+    /// `byte_idx`.
+    ///
+    /// Notice that `byte_idx` can be one past the end of the table, in which
+    /// case the length of the table (in chars) is returned.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `byte_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("a⑤b");
+    /// assert_eq!(pt.byte_to_char(1), 1);
+    /// assert_eq!(pt.byte_to_char(3), 1); // inside the ⑤
+    /// assert_eq!(pt.byte_to_char(4), 2);
+    /// assert_eq!(pt.byte_to_char(5), 3);
+    /// ```
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        assert!(byte_idx <= self.len_bytes(), "index out of bounds");
+
+        let Some((before, node)) = self.tree.node_at_byte(byte_idx) else {
+            return 0; // the table is empty
+        };
+        let text = self.tree.piece(node).text(&self.buffers);
+        before.chars + str_utils::byte_to_char(text, byte_idx - before.bytes)
+    }
+
+    /// Like [`PieceTable::byte_to_char`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `byte_idx` is larger than the size of
+    /// the contents.
+    pub fn try_byte_to_char(&self, byte_idx: usize) -> Result<usize, Error> {
+        error::check_idx(byte_idx, self.len_bytes())?;
+        Ok(self.byte_to_char(byte_idx))
+    }
+
+    /// Insert `text` at the byte index `byte_idx`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("⑤⑦");
+    /// pt.insert_at_byte(3, "⑥");
+    /// assert_eq!(pt.text(), "⑤⑥⑦");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `byte_idx` is larger than the size of the contents, or if
+    /// it is not on a char boundary.
+    ///
+    /// ```should_panic
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("⑤⑦");
+    /// pt.insert_at_byte(1, "⑥"); // will panic
+    /// ```
+    pub fn insert_at_byte(&mut self, byte_idx: usize, text: &str) {
+        error::unwrap(self.try_insert_at_byte(byte_idx, text));
+    }
+
+    /// Like [`PieceTable::insert_at_byte`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NonCharBoundary`] if `byte_idx` is not on a char
+    /// boundary, and the errors of [`PieceTable::try_insert`].
+    pub fn try_insert_at_byte(
+        &mut self,
+        byte_idx: usize,
+        text: &str,
+    ) -> Result<(), Error> {
+        let char_idx = self.char_boundary_to_char(byte_idx)?;
+        self.try_insert(char_idx, text)
+    }
+
+    /// Removes the text in the given byte index range.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("⑤⑥⑦");
+    /// pt.remove_bytes(3..6);
+    /// assert_eq!(pt.text(), "⑤⑦");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the start of the range is larger than its end, if its end
+    /// is larger than the size of the contents, or if the start or the end of
+    /// a non-empty range are not on char boundaries.
+    ///
+    /// ```should_panic
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("⑤⑥⑦");
+    /// pt.remove_bytes(3..5); // will panic
+    /// ```
+    pub fn remove_bytes<R>(&mut self, range: R)
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        error::unwrap(self.try_remove_bytes(range));
+    }
+
+    /// Like [`PieceTable::remove_bytes`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NonCharBoundary`] if the start or the end of a
+    /// non-empty range are not on char boundaries, and the errors of
+    /// [`PieceTable::try_remove`].
+    pub fn try_remove_bytes<R>(&mut self, range: R) -> Result<(), Error>
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        let (start, end) =
+            Self::simplify_range_bounds(range, self.len_bytes())?;
+        error::check_range(start, end, self.len_bytes())?;
+        if start == end {
+            return Ok(()); // the range is empty
+        }
+
+        let start = self.char_boundary_to_char(start)?;
+        let end = self.char_boundary_to_char(end)?;
+        self.try_remove(start..end)
+    }
+
+    /// Total number of UTF-16 code units the text would take, if it was encoded
+    /// as UTF-16.
+    ///
+    /// Runs in `O(1)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("a⑤🦀");
+    /// assert_eq!(pt.len_utf16(), 4); // the crab takes a surrogate pair
+    /// ```
+    #[inline(always)]
+    pub fn len_utf16(&self) -> usize {
+        self.tree.total(&self.buffers).utf16
+    }
+
+    /// Returns the UTF-16 code unit index of the char at `char_idx`.
+    ///
+    /// Notice that `char_idx` can be one past the end of the table.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("🦀a🐙");
+    /// assert_eq!(pt.char_to_utf16(1), 2);
+    /// assert_eq!(pt.char_to_utf16(2), 3);
+    /// assert_eq!(pt.char_to_utf16(3), 5);
+    /// ```
+    pub fn char_to_utf16(&self, char_idx: usize) -> usize {
+        assert!(char_idx <= self.len_chars(), "index out of bounds");
+
+        let Some((before, node)) = self.tree.node_at_char(char_idx) else {
+            return 0; // the table is empty
+        };
+        let text = self.tree.piece(node).text(&self.buffers);
+        let byte_idx = str_utils::char_to_byte(text, char_idx - before.chars);
+        self.tree.metrics_before(node, &self.buffers).utf16
+            + str_utils::byte_to_utf16(text, byte_idx)
+    }
+
+    /// Like [`PieceTable::char_to_utf16`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    pub fn try_char_to_utf16(&self, char_idx: usize) -> Result<usize, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.char_to_utf16(char_idx))
+    }
+
+    /// Returns the char index of the char that contains the UTF-16 code unit
+    /// at `utf16_idx`.
+    ///
+    /// If `utf16_idx` is in the middle of a surrogate pair, the index of the
+    /// char which is encoded by that pair is returned. Notice that `utf16_idx`
+    /// can be one past the end of the table.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `utf16_idx` is larger than the UTF-16 length of the
+    /// contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("🦀a🐙");
+    /// assert_eq!(pt.utf16_to_char(1), 0); // inside the crab
+    /// assert_eq!(pt.utf16_to_char(2), 1);
+    /// assert_eq!(pt.utf16_to_char(5), 3);
+    /// ```
+    pub fn utf16_to_char(&self, utf16_idx: usize) -> usize {
+        error::unwrap(self.try_utf16_to_char(utf16_idx))
+    }
+
+    /// Like [`PieceTable::utf16_to_char`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `utf16_idx` is larger than the UTF-16
+    /// length of the contents.
+    pub fn try_utf16_to_char(&self, utf16_idx: usize) -> Result<usize, Error> {
+        let Some((before, node)) =
+            self.tree.node_at_utf16(utf16_idx, &self.buffers)
+        else {
+            // The table is empty, or the index is past its end.
+            error::check_idx(utf16_idx, self.len_utf16())?;
+            return Ok(0);
+        };
+        let text = self.tree.piece(node).text(&self.buffers);
+        let byte_idx = str_utils::utf16_to_byte(text, utf16_idx - before.utf16);
+        Ok(before.chars + str_utils::byte_to_char(text, byte_idx))
+    }
+
+    /// Returns the line of the char at `char_idx`, and its column in UTF-16
+    /// code units.
+    ///
+    /// The lines are the table's (see [`LineBreakConfig`]), which are not
+    /// always LSP's. For an LSP `Position`, use
+    /// [`PieceTable::char_to_position`].
+    ///
+    /// Notice that `char_idx` can be one past the end of the table.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("fn 🦀() {\n    🐙\n}");
+    /// assert_eq!(pt.char_to_line_utf16(3), (0, 3));
+    /// assert_eq!(pt.char_to_line_utf16(4), (0, 5));
+    /// assert_eq!(pt.char_to_line_utf16(14), (1, 6));
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn char_to_line_utf16(&self, char_idx: usize) -> (usize, usize) {
+        let line_idx = self.char_to_line(char_idx);
+        let line_start = self.char_to_utf16(self.line_to_char(line_idx));
+        (line_idx, self.char_to_utf16(char_idx) - line_start)
+    }
+
+    /// Like [`PieceTable::char_to_line_utf16`], but returns an [`Error`]
+    /// instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    #[cfg(feature = "lines")]
+    pub fn try_char_to_line_utf16(
+        &self,
+        char_idx: usize,
+    ) -> Result<(usize, usize), Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.char_to_line_utf16(char_idx))
+    }
+
+    /// Returns the char index of the char at `utf16_col` UTF-16 code units into
+    /// the `line_idx`-th line.
+    ///
+    /// The lines are the table's (see [`LineBreakConfig`]), which are not
+    /// always LSP's. For an LSP `Position`, use
+    /// [`PieceTable::position_to_char`].
+    ///
+    /// Like in LSP, if `utf16_col` is larger than the length of the line, it
+    /// defaults back to the end of the line (before its line break).
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is out of bounds (i.e., there is no such line).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("fn 🦀() {\n    🐙\n}");
+    /// assert_eq!(pt.line_utf16_to_char(0, 5), 4);
+    /// assert_eq!(pt.line_utf16_to_char(1, 6), 14);
+    /// assert_eq!(pt.line_utf16_to_char(1, 100), 14); // clamped
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn line_utf16_to_char(
+        &self,
+        line_idx: usize,
+        utf16_col: usize,
+    ) -> usize {
+        assert!(self.has_line(line_idx), "line index out of bounds");
+
+        let line_start = self.char_to_utf16(self.line_to_char(line_idx));
+        let line_end = self.char_to_utf16(self.line_end(line_idx));
+        self.utf16_to_char(line_end.min(line_start + utf16_col))
+    }
+
+    /// Like [`PieceTable::line_utf16_to_char`], but returns an [`Error`]
+    /// instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if there is no such line.
+    #[cfg(feature = "lines")]
+    pub fn try_line_utf16_to_char(
+        &self,
+        line_idx: usize,
+        utf16_col: usize,
+    ) -> Result<usize, Error> {
+        self.check_line_idx(line_idx)?;
+        Ok(self.line_utf16_to_char(line_idx, utf16_col))
+    }
+
+    /// Insert `pieces` at `char_idx`, and return the node of the last one.
+    fn insert_pieces(
+        &mut self,
+        char_idx: usize,
+        pieces: Vec<Piece>,
+    ) -> NodeIdx {
+        match self.tree.node_at_char(char_idx) {
+            Some((before, node)) => {
+                let relative_char_idx = char_idx - before.chars;
+
+                if relative_char_idx == 0 {
+                    self.insert_pieces_before(node, pieces)
+                } else if relative_char_idx == self.tree.piece(node).len_chars {
+                    self.insert_pieces_after(node, pieces)
+                } else {
+                    self.split_piece_and_insert(node, relative_char_idx, pieces)
+                }
+            }
+            // The table is empty.
+            None => self.push_pieces(pieces),
+        }
+    }
+
+    /// Move in the history using `travel`, apply the moves it returns, and
+    /// return the cursors of the last one.
+    fn travel<F>(&mut self, travel: F) -> Option<Vec<usize>>
+    where
+        F: FnOnce(&mut History) -> Vec<Move>,
+    {
+        let mut history = std::mem::take(&mut self.history);
+        let moves = travel(&mut history);
+
+        for &m in &moves {
+            match m {
+                Move::Undo(revision) => {
+                    for change in history.step(revision).changes.iter().rev() {
+                        self.replace_pieces(
+                            change.char_idx,
+                            change.len_inserted(),
+                            change.removed.clone(),
+                        );
+                    }
+                }
+                Move::Redo(revision) => {
+                    for change in &history.step(revision).changes {
+                        self.replace_pieces(
+                            change.char_idx,
+                            change.len_removed(),
+                            change.inserted.clone(),
+                        );
+                    }
+                }
+            }
+        }
+
+        let cursors = moves.last().map(|&m| match m {
+            Move::Undo(revision) => {
+                history.step(revision).cursors_before.clone()
+            }
+            Move::Redo(revision) => {
+                history.step(revision).cursors_after.clone()
+            }
+        });
+        self.history = history;
+        cursors
+    }
+
+    /// Remove `len_chars` chars at `char_idx`, and insert `pieces` instead,
+    /// without recording it in the history.
+    fn replace_pieces(
+        &mut self,
+        char_idx: usize,
+        len_chars: usize,
+        pieces: Vec<Piece>,
+    ) {
+        #[cfg(feature = "contiguous-inserts")]
+        {
+            self.last_insert = None;
+        }
+
+        if len_chars > 0 {
+            #[cfg(feature = "lines")]
+            self.record_remove(char_idx, char_idx + len_chars);
+            self.remove_range(char_idx, char_idx + len_chars);
+        }
+        if !pieces.is_empty() {
+            #[cfg(feature = "lines")]
+            self.record_insert_pieces(char_idx, &pieces);
+            self.insert_pieces(char_idx, pieces);
+        }
     }
 
-    /// Total number of bytes in the piece table.
-    ///
-    /// Runs in `O(1)`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use peace_table::PieceTable;
-    /// let mut pt = PieceTable::new("1234⑤");
-    /// assert_eq!(pt.len_bytes(), 7); // the 5 takes 3 bytes
-    /// ```
-    #[inline(always)]
-    pub fn len_bytes(&self) -> usize {
-        self.len_bytes
+    /// Remove the chars in `start..end` (which must be a non-empty range in
+    /// bounds), and return the removed parts of the pieces.
+    fn remove_range(&mut self, start: usize, end: usize) -> Vec<Piece> {
+        let Some((before, mut node)) = self.tree.node_at_char(start) else {
+            unreachable!("`start` is smaller than `end`, which is in bounds")
+        };
+        // The char index to start removing from, relative to `node`.
+        let mut relative_start = start - before.chars;
+        let mut remaining = end - start;
+        let mut removed = vec![];
+
+        while remaining > 0 {
+            let piece = self.tree.piece(node);
+            let len_chars = piece.len_chars;
+            let relative_end = len_chars.min(relative_start + remaining);
+
+            if relative_start == 0 && relative_end == len_chars {
+                // The next piece takes the place of the removed one.
+                removed.push(self.tree.remove(node));
+            } else {
+                let range = relative_start..relative_end;
+                removed.push(piece.slice(&self.buffers, range));
+
+                if relative_start == 0 {
+                    self.trim_piece_start(node, relative_end);
+                } else if relative_end == len_chars {
+                    self.trim_piece_end(node, relative_start);
+                } else {
+                    self.remove_within_piece(
+                        node,
+                        relative_start,
+                        relative_end,
+                    );
+                }
+                node += 1;
+            }
+
+            remaining -= relative_end - relative_start;
+            relative_start = 0;
+        }
+
+        removed
     }
 
+    /// Split the piece of `node` at `char_idx` (relative to the piece), and
+    /// insert `pieces` between the two halves. Returns the node of the last
+    /// inserted piece.
     fn split_piece_and_insert(
         &mut self,
-        piece_idx: usize,
+        node: NodeIdx,
         char_idx: usize,
-        text: &str,
-    ) {
-        let piece = &mut self.pieces[piece_idx];
-
-        let piece_text = &self.buffers[piece.buffer][piece.start..];
+        pieces: Vec<Piece>,
+    ) -> NodeIdx {
+        let piece = self.tree.piece(node);
+        let piece_text = piece.text(&self.buffers);
 
-        // TODO: should we make this a `debug_assert!`?
-        assert!(
-            !(piece_text.as_bytes()[char_idx - 1] == 0x0D
-                && piece_text.as_bytes()[char_idx] == 0x0A),
+        // Edits never split a CRLF sequence (see `PieceTable::fit_crlf`).
+        let byte_idx = str_utils::char_to_byte(piece_text, char_idx);
+        debug_assert!(
+            !(piece_text.as_bytes()[byte_idx - 1] == 0x0D
+                && piece_text.as_bytes()[byte_idx] == 0x0A),
             "inserting inside a CRLF sequece is invalid"
         );
 
-        // Create the `after` piece, before modifying `piece`.
-        let byte_idx = str_utils::char_to_byte(piece_text, char_idx);
-        let after_start = piece.start + byte_idx;
-        let after_end = after_start + piece.len_bytes - byte_idx;
-        let first_line_break = piece.first_line_break.and_then(|flb| {
-            let mut lbs = self.buffers.line_breaks(piece.buffer)[flb..].iter();
-            lbs.find(|(idx, _ty)| *idx >= after_start && *idx < after_end)
-        });
-        let after = Piece {
-            buffer: piece.buffer,
-            start: after_start,
-            first_line_break: first_line_break.map(|(idx, _ty)| idx).copied(),
-            len_bytes: piece.len_bytes - byte_idx,
-            len_chars: piece.len_chars - char_idx,
-        };
+        let len_chars = piece.len_chars;
+        let before = piece.slice(&self.buffers, 0..char_idx);
+        let after = piece.slice(&self.buffers, char_idx..len_chars);
 
-        // Modify `piece` in-place to be the `before` piece.
-        piece.len_bytes = byte_idx;
-        piece.len_chars = char_idx;
+        self.tree.replace(node, before);
+        let last = self.insert_pieces_after(node, pieces);
+        self.tree.insert_after(last, after);
 
-        // Insert the new and the `after` piece.
-        self.insert_piece(piece_idx + 1, text);
-        self.pieces.insert(piece_idx + 2, after);
+        last
     }
 
     /// Returns an iterator over all the `&str` chunks in the table.
@@ -351,214 +1676,520 @@ impl<'b> PieceTable<'b> {
     /// pt.insert(2, ", and hello, ");
     /// assert_eq!(pt.iter().collect::<String>(), "hi, and hello, there");
     /// ```
-    pub fn iter(&self) -> impl Iterator<Item = &str> {
-        self.pieces.iter().map(|p| &self.buffers[p.buffer][p.byte_range()])
+    pub fn iter(&self) -> Chunks<'_> {
+        Chunks::new(self, 0..self.len_bytes(), 0)
     }
 
-    /// Create a new "add" piece with `content`, and insert that piece at
-    /// `index`.
-    fn insert_piece(&mut self, index: usize, text: &str) {
-        let first_lb = self.buffers.add.line_breaks.len();
-        self.len_lines += str_utils::line_breaks(
-            text,
-            &mut self.buffers.add.line_breaks,
-            self.buffers.add.content.len(),
-        );
+    /// Returns an iterator over the `&str` chunks of the table, starting at
+    /// the char at `char_idx`. It can also move backward (see
+    /// [`Chunks::prev`]).
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("hello world");
+    /// pt.insert(5, ",");
+    ///
+    /// let mut chunks = pt.chunks_at(3);
+    /// assert_eq!(chunks.next(), Some("lo"));
+    /// assert_eq!(chunks.next(), Some(","));
+    /// assert_eq!(chunks.next_back(), Some(" world"));
+    /// assert_eq!(chunks.next(), None);
+    /// assert_eq!(chunks.prev(), Some(","));
+    /// assert_eq!(chunks.prev(), Some("hello"));
+    /// ```
+    pub fn chunks_at(&self, char_idx: usize) -> Chunks<'_> {
+        let byte_idx = self.char_to_byte(char_idx);
+        Chunks::new(self, 0..self.len_bytes(), byte_idx)
+    }
+
+    /// Like [`PieceTable::chunks_at`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    pub fn try_chunks_at(&self, char_idx: usize) -> Result<Chunks<'_>, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.chunks_at(char_idx))
+    }
+
+    /// Returns an iterator over the chars of the table, starting at the char
+    /// at `char_idx`. It can also move backward (see [`Chars::prev`]).
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("a🦀b");
+    ///
+    /// let mut chars = pt.chars_at(1);
+    /// assert_eq!(chars.next(), Some('🦀'));
+    /// assert_eq!(chars.prev(), Some('🦀'));
+    /// assert_eq!(chars.prev(), Some('a'));
+    /// assert_eq!(chars.prev(), None);
+    /// assert_eq!(chars.rev().collect::<String>(), "b🦀a");
+    /// ```
+    pub fn chars_at(&self, char_idx: usize) -> Chars<'_> {
+        let byte_idx = self.char_to_byte(char_idx);
+        Chars::new(self, 0..self.len_bytes(), byte_idx)
+    }
+
+    /// Like [`PieceTable::chars_at`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    pub fn try_chars_at(&self, char_idx: usize) -> Result<Chars<'_>, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.chars_at(char_idx))
+    }
+
+    /// Returns an iterator over the bytes of the table, starting at the byte
+    /// at `byte_idx` (which doesn't have to be a char boundary). It can also
+    /// move backward (see [`Bytes::prev`]).
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `byte_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("aä");
+    ///
+    /// let mut bytes = pt.bytes_at(2);
+    /// assert_eq!(bytes.next(), Some(0xA4));
+    /// assert_eq!(bytes.prev(), Some(0xA4));
+    /// assert_eq!(bytes.prev(), Some(0xC3));
+    /// ```
+    pub fn bytes_at(&self, byte_idx: usize) -> Bytes<'_> {
+        assert!(byte_idx <= self.len_bytes(), "index out of bounds");
+        Bytes::new(self, 0..self.len_bytes(), byte_idx)
+    }
+
+    /// Like [`PieceTable::bytes_at`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `byte_idx` is larger than the size of
+    /// the contents.
+    pub fn try_bytes_at(&self, byte_idx: usize) -> Result<Bytes<'_>, Error> {
+        error::check_idx(byte_idx, self.len_bytes())?;
+        Ok(self.bytes_at(byte_idx))
+    }
+
+    /// Returns an iterator over the lines of the table (**without** their line
+    /// break sequences), starting at the `line_idx`-th line. It can also move
+    /// backward (see [`Lines::prev`]).
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is larger than the amount of lines.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\ntwo\r\nthree");
+    ///
+    /// let mut lines = pt.lines_at(1);
+    /// assert_eq!(lines.next().unwrap().to_string(), "two");
+    /// assert_eq!(lines.next_back().unwrap().to_string(), "three");
+    /// assert!(lines.next().is_none());
+    /// assert_eq!(lines.prev().unwrap().to_string(), "two");
+    /// assert_eq!(lines.prev().unwrap().to_string(), "one");
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn lines_at(&self, line_idx: usize) -> Lines<'_> {
+        self.slice(..).lines_at(line_idx)
+    }
 
-        let piece = Piece {
-            buffer: BufferType::Add,
-            start: self.buffers.add.content.len(),
-            first_line_break: (first_lb < self.buffers.add.line_breaks.len())
-                .then_some(first_lb),
-            len_chars: str_utils::count_chars(text),
-            len_bytes: text.len(),
+    /// Like [`PieceTable::lines_at`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `line_idx` is larger than the amount
+    /// of lines.
+    #[cfg(feature = "lines")]
+    pub fn try_lines_at(&self, line_idx: usize) -> Result<Lines<'_>, Error> {
+        self.slice(..).try_lines_at(line_idx)
+    }
+
+    /// Insert `pieces` right before `node`, and return the node of the last
+    /// one.
+    fn insert_pieces_before(
+        &mut self,
+        node: NodeIdx,
+        pieces: Vec<Piece>,
+    ) -> NodeIdx {
+        let mut last = node;
+        for (i, piece) in pieces.into_iter().enumerate() {
+            last = self.tree.insert(node + i, piece);
+        }
+        last
+    }
+
+    /// Insert `pieces` right after `node`, and return the node of the last
+    /// one.
+    fn insert_pieces_after(
+        &mut self,
+        node: NodeIdx,
+        pieces: Vec<Piece>,
+    ) -> NodeIdx {
+        let mut last = node;
+        for piece in pieces {
+            last = self.tree.insert_after(last, piece);
+        }
+        last
+    }
+
+    /// Insert `pieces` at the end of the table, and return the node of the last
+    /// one.
+    fn push_pieces(&mut self, pieces: Vec<Piece>) -> NodeIdx {
+        let mut pieces = pieces.into_iter();
+        let mut last = self.tree.push_back(pieces.next().unwrap());
+        for piece in pieces {
+            last = self.tree.insert_after(last, piece);
+        }
+        last
+    }
+
+    /// Convert `byte_idx` to a char index, checking that it is in bounds and
+    /// on a char boundary.
+    fn char_boundary_to_char(&self, byte_idx: usize) -> Result<usize, Error> {
+        error::check_idx(byte_idx, self.len_bytes())?;
+
+        let Some((before, node)) = self.tree.node_at_byte(byte_idx) else {
+            return Ok(0); // the table is empty
         };
+        let text = self.tree.piece(node).text(&self.buffers);
+        let relative_idx = byte_idx - before.bytes;
+        if !text.is_char_boundary(relative_idx) {
+            return Err(Error::NonCharBoundary { byte_idx });
+        }
 
-        self.buffers.add.content.push_str(text);
-        self.pieces.insert(index, piece);
+        Ok(before.chars + str_utils::byte_to_char(text, relative_idx))
     }
 
-    fn piece_at_char(&self, char_idx: usize) -> (usize, usize) {
-        assert!(char_idx <= self.len_chars, "index out of bounds");
+    /// Whether there is a `line_idx`-th line.
+    ///
+    /// Unlike comparing with [`PieceTable::len_lines`], this scans the text
+    /// only up to the line (if it wasn't scanned yet).
+    #[cfg(feature = "lines")]
+    pub(crate) fn has_line(&self, line_idx: usize) -> bool {
+        line_idx == 0 || self.find_line_break(line_idx - 1).is_some()
+    }
 
-        let mut offset = 0;
-        for (i, piece) in self.pieces.iter().enumerate() {
-            offset += piece.len_chars;
+    /// Check that there is a `line_idx`-th line.
+    #[cfg(feature = "lines")]
+    fn check_line_idx(&self, line_idx: usize) -> Result<(), Error> {
+        if self.has_line(line_idx) {
+            Ok(())
+        } else {
+            Err(Error::OutOfBounds { idx: line_idx, len: self.len_lines() })
+        }
+    }
 
-            if offset >= char_idx {
-                let relative_idx = char_idx - (offset - piece.len_chars);
-                return (i, relative_idx);
+    /// The position of the start of the `line_idx`-th line, which may be one
+    /// past the last line.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `line_idx` is larger than the amount
+    /// of lines.
+    #[cfg(feature = "lines")]
+    fn line_start(&self, line_idx: usize) -> Result<Size, Error> {
+        if line_idx == 0 {
+            return Ok(Size::default());
+        }
+        match self.find_line_break(line_idx - 1) {
+            Some((position, ty)) => Ok(Size {
+                bytes: position.bytes + ty.len_bytes(),
+                chars: position.chars + ty.len_chars(),
+            }),
+            None => {
+                // The line breaks were all scanned by the search, so counting
+                // them doesn't scan the text again.
+                error::check_idx(line_idx, self.len_lines())?;
+                Ok(self.tree.size())
             }
         }
+    }
 
-        unreachable!(
-            "this code will be ran only if `index` is larger than the total \
-             size len all the pieces together, but this was already asserted"
-        )
+    /// The char index of the end of the `line_idx`-th line, **without** the
+    /// line break sequence.
+    #[cfg(feature = "lines")]
+    pub(crate) fn line_end(&self, line_idx: usize) -> usize {
+        match self.find_line_break(line_idx) {
+            Some((position, _ty)) => position.chars,
+            None => self.len_chars(),
+        }
+    }
+
+    /// Find the `line_break_idx`-th line break in the table, and return the
+    /// position of its start and its type.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if there is no such line break.
+    #[cfg(feature = "lines")]
+    pub(crate) fn line_break(
+        &self,
+        line_break_idx: usize,
+    ) -> (Metrics, line::Break) {
+        let Some(line_break) = self.find_line_break(line_break_idx) else {
+            panic!("line index out of bounds");
+        };
+        line_break
+    }
+
+    /// Find the `line_break_idx`-th line break in the table, and return the
+    /// position of its start and its type, or [`None`] if there is no such
+    /// line break.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces, once the text up
+    /// to the line break was scanned.
+    #[cfg(feature = "lines")]
+    pub(crate) fn find_line_break(
+        &self,
+        line_break_idx: usize,
+    ) -> Option<(Metrics, line::Break)> {
+        let (before, node) =
+            self.tree.node_at_line_break(line_break_idx, &self.buffers)?;
+        let piece = self.tree.piece(node);
+        let nth = line_break_idx - before.line_breaks;
+
+        let (idx, ty) = piece.line_breaks(&self.buffers)[nth];
+        let relative_idx = idx - piece.start;
+        let text = piece.text(&self.buffers);
+        // The whole text, so that a CR right before `relative_idx` is not
+        // taken for a line break if an LF follows it.
+        let lsp_line_breaks = str_utils::lsp_line_breaks(text)
+            .take_while(|&(idx, len)| idx + len <= relative_idx)
+            .count();
+        let text = &text[..relative_idx];
+
+        let position = before
+            + Metrics {
+                bytes: relative_idx,
+                chars: str_utils::count_chars(text),
+                utf16: str_utils::count_utf16(text),
+                line_breaks: nth,
+                lsp_line_breaks,
+            };
+        Some((position, ty))
+    }
+
+    /// Count the line breaks of `piece` that end before (or at) `byte_idx`,
+    /// which is an index in the piece's buffer.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of line breaks in the piece.
+    #[cfg(feature = "lines")]
+    fn count_line_breaks_before(
+        &self,
+        piece: &Piece,
+        byte_idx: usize,
+    ) -> usize {
+        piece
+            .line_breaks(&self.buffers)
+            .partition_point(|(idx, ty)| idx + ty.len_bytes() <= byte_idx)
     }
 
-    fn simplify_range_bounds<R>(&mut self, range: R) -> (usize, usize)
+    /// The start and the end of `range`, in which an unbounded end is `len`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if the start or the end of the range
+    /// are past `usize::MAX` (e.g., `..=usize::MAX`), which is out of the
+    /// bounds of any text.
+    pub(crate) fn simplify_range_bounds<R>(
+        range: R,
+        len: usize,
+    ) -> Result<(usize, usize), Error>
     where
         R: std::ops::RangeBounds<usize>,
     {
+        let after = |i: usize| {
+            i.checked_add(1).ok_or(Error::OutOfBounds { idx: i, len })
+        };
         let start = match range.start_bound() {
             std::ops::Bound::Included(&i) => i,
-            std::ops::Bound::Excluded(&i) => i + 1,
+            std::ops::Bound::Excluded(&i) => after(i)?,
             std::ops::Bound::Unbounded => 0,
         };
         let end = match range.end_bound() {
-            std::ops::Bound::Included(&i) => i + 1,
+            std::ops::Bound::Included(&i) => after(i)?,
             std::ops::Bound::Excluded(&i) => i,
-            std::ops::Bound::Unbounded => self.len_chars,
+            std::ops::Bound::Unbounded => len,
         };
-        (start, end)
-    }
-
-    fn trim_piece_end(&mut self, piece_idx: usize, start_char_idx: usize) {
-        let piece = &mut self.pieces[piece_idx];
-        let text = &self.buffers[piece.buffer][piece.byte_range()];
-
-        if start_char_idx == 0 {
-            self.remove_piece(piece_idx);
-        } else if start_char_idx < piece.len_chars {
-            let byte_idx = str_utils::char_to_byte(text, start_char_idx);
-            self.len_chars -= piece.len_chars - start_char_idx;
-            self.len_bytes -= piece.len_bytes - byte_idx;
-            piece.len_bytes = byte_idx;
-            piece.len_chars = start_char_idx;
-
-            // Unset the `first_line_break` if it was in the removed part.
-            #[cfg(feature = "lines")]
-            piece
-                .first_line_break
-                .as_ref()
-                .take_if(|flb| **flb >= piece.byte_range().end);
-        }
+        Ok((start, end))
     }
 
-    fn trim_piece_start(&mut self, piece_idx: usize, end_char_idx: usize) {
-        let piece = &mut self.pieces[piece_idx];
-        let text = &self.buffers[piece.buffer][piece.byte_range()];
-
-        if end_char_idx == piece.len_chars {
-            self.remove_piece(piece_idx);
-        } else if end_char_idx > 0 {
-            let byte_idx = str_utils::char_to_byte(text, end_char_idx);
-            piece.start += byte_idx;
-            piece.len_bytes -= byte_idx;
-            piece.len_chars -= end_char_idx;
-            self.len_chars -= end_char_idx;
-            self.len_bytes -= byte_idx;
-
-            #[cfg(feature = "lines")]
-            piece.first_line_break = piece.first_line_break.and_then(|flb| {
-                // WARNING: if there is no matching line break, it will iterate
-                // over _all_ of the line breaks. fix that.
-                let mut lbs =
-                    self.buffers.line_breaks(piece.buffer)[flb..].iter();
-                Some(lbs.find(|(idx, _ty)| piece.byte_range().contains(idx))?.0)
-            });
-        }
+    /// Remove the end of the piece of `node`, starting at `start_char_idx`.
+    fn trim_piece_end(&mut self, node: NodeIdx, start_char_idx: usize) {
+        let piece = self.tree.piece(node);
+        let trimmed = piece.slice(&self.buffers, 0..start_char_idx);
+        self.tree.replace(node, trimmed);
     }
 
-    fn remove_piece(&mut self, piece_idx: usize) {
-        let piece = &self.pieces[piece_idx];
-        #[cfg(feature = "lines")]
-        let lbs = self.count_piece_line_breaks(piece_idx);
-
-        self.len_bytes -= piece.len_bytes;
-        self.len_chars -= piece.len_chars;
-        #[cfg(feature = "lines")]
-        self.len_lines -= lbs;
-
-        self.pieces.remove(piece_idx);
+    /// Remove the start of the piece of `node`, up to `end_char_idx`.
+    fn trim_piece_start(&mut self, node: NodeIdx, end_char_idx: usize) {
+        let piece = self.tree.piece(node);
+        let len_chars = piece.len_chars;
+        let trimmed = piece.slice(&self.buffers, end_char_idx..len_chars);
+        self.tree.replace(node, trimmed);
     }
 
+    /// Remove the chars `start_char_idx..end_char_idx` from the middle of the
+    /// piece of `node`, splitting it in two.
     fn remove_within_piece(
         &mut self,
-        piece_idx: usize,
+        node: NodeIdx,
         start_char_idx: usize,
         end_char_idx: usize,
     ) {
-        let piece = &mut self.pieces[piece_idx];
-        let text = &self.buffers[piece.buffer][piece.byte_range()];
+        let piece = self.tree.piece(node);
+        let len_chars = piece.len_chars;
+        let before = piece.slice(&self.buffers, 0..start_char_idx);
+        let after = piece.slice(&self.buffers, end_char_idx..len_chars);
 
-        // If the range describes an entire piece, remove it.
-        if start_char_idx == 0 && end_char_idx == piece.len_chars {
-            let piece = &self.pieces[piece_idx];
-            self.len_bytes -= piece.len_bytes;
-            self.len_chars -= piece.len_chars;
-            self.pieces.remove(piece_idx);
-            return;
-        }
+        self.tree.replace(node, before);
+        self.tree.insert_after(node, after);
+    }
 
-        let start_offset = str_utils::char_to_byte(text, start_char_idx);
-        let end_offset = str_utils::char_to_byte(text, end_char_idx);
+    /// Replace the chars in `start..end` with `text`, recording it as a
+    /// single change.
+    pub(crate) fn splice(&mut self, start: usize, end: usize, text: &str) {
+        #[cfg(feature = "lines")]
+        self.record_replace(start, end, text);
 
-        let new_len_bytes = end_offset - start_offset;
-        let new_len_chars = end_char_idx - start_char_idx;
+        // If the edit is _after_ the index of the last insert, it does not
+        // affect it (unless it appends to the `add` buffer).
+        #[cfg(feature = "contiguous-inserts")]
+        if !text.is_empty()
+            || self.last_insert.is_some_and(|(i, _node)| i >= start)
+        {
+            self.last_insert = None;
+        }
 
-        piece.start += start_offset;
-        piece.len_bytes = new_len_bytes;
-        piece.len_chars = new_len_chars;
+        let removed =
+            if start < end { self.remove_range(start, end) } else { vec![] };
+        let inserted = if text.is_empty() {
+            vec![]
+        } else {
+            let pieces = self.append(text);
+            self.insert_pieces(start, pieces.clone());
+            pieces
+        };
 
-        let removed_bytes = piece.len_bytes - new_len_bytes;
-        self.len_bytes -= removed_bytes;
-        let removed_chars = piece.len_chars - new_len_chars;
-        self.len_chars -= removed_chars;
+        self.history.record(Change { char_idx: start, removed, inserted });
     }
 
-    fn remove_pieces(&mut self, range: std::ops::Range<usize>) {
-        self.pieces.drain(range).for_each(|p| {
-            self.len_chars -= p.len_chars;
-            self.len_bytes -= p.len_bytes;
-        });
+    /// Append `text` to the `add` buffer, and return the pieces referencing
+    /// it.
+    fn append(&mut self, text: &str) -> Vec<Piece> {
+        self.buffers
+            .append(text, Piece::MAX_LEN_BYTES)
+            .into_iter()
+            .map(|r| Piece::new(&self.buffers, BufferType::Add, r))
+            .collect()
     }
 
     /// Extend a piece's end, and inserts the text to the end of the `add`
     /// buffer. This function assumes that the last insert to the table was to
-    /// the end of the piece.
+    /// the end of the piece. Returns a piece referencing just `text`.
     #[cfg(feature = "contiguous-inserts")]
-    fn extend_piece(
-        &mut self,
-        text: &str,
-        text_len_chars: usize,
-        piece_idx: usize,
-    ) {
-        let piece = &mut self.pieces[piece_idx];
+    fn extend_piece(&mut self, text: &str, node: NodeIdx) -> Piece {
+        let piece = self.tree.piece(node);
 
         debug_assert_eq!(piece.buffer, BufferType::Add);
-        debug_assert_eq!(
-            self.buffers.add.content.len(),
-            piece.byte_range().end
-        );
+        debug_assert_eq!(self.buffers.add_len(), piece.byte_range().end);
+        debug_assert!(self.buffers.fits_last_segment(text.len()));
 
-        self.len_lines += str_utils::line_breaks(
-            text,
-            &mut self.buffers.add.line_breaks,
-            piece.byte_range().end,
-        );
+        let start = piece.start;
+        let [range] = &self.buffers.append(text, Piece::MAX_LEN_BYTES)[..]
+        else {
+            unreachable!("`text` fits in the last segment")
+        };
+        let range = range.clone();
+        let extended =
+            Piece::new(&self.buffers, BufferType::Add, start..range.end);
+        self.tree.replace(node, extended);
 
-        piece.len_bytes += text.len();
-        piece.len_chars += text_len_chars;
+        Piece::new(&self.buffers, BufferType::Add, range)
+    }
+}
 
-        self.buffers.add.content.push_str(text);
+impl<'b> From<&'b str> for PieceTable<'b> {
+    fn from(initial: &'b str) -> Self {
+        Self::new(initial)
     }
+}
 
-    /// Count the amount of line breaks that a piece contains.
-    ///
-    /// Runs in `O(N)` where `N` is the amount of line breaks in the piece (this
-    /// is a good time complexity).
-    #[cfg(feature = "lines")]
-    fn count_piece_line_breaks(&self, piece_idx: usize) -> usize {
-        let piece = &self.pieces[piece_idx];
-        if let Some(first_lb) = piece.first_line_break {
-            let lbs = self.buffers.line_breaks(piece.buffer)[first_lb..].iter();
-            let (s, e) = (piece.start, piece.byte_range().end);
-            lbs.take_while(|(idx, _ty)| *idx >= s && *idx < e).count()
-        } else {
-            0
-        }
+/// Create a table which owns its initial contents.
+///
+/// # Examples
+///
+/// ```
+/// # use peace_table::PieceTable;
+/// let pt = PieceTable::from(String::from("owned"));
+/// let handle = std::thread::spawn(move || pt.text());
+/// assert_eq!(handle.join().unwrap(), "owned");
+/// ```
+impl From<String> for PieceTable<'static> {
+    fn from(initial: String) -> Self {
+        Self::from(Arc::<str>::from(initial))
+    }
+}
+
+/// Create a table which owns its initial contents, like the `From<String>`
+/// implementation.
+impl From<Box<str>> for PieceTable<'static> {
+    fn from(initial: Box<str>) -> Self {
+        Self::from(Arc::<str>::from(initial))
+    }
+}
+
+/// Create a table which shares its initial contents with other owners of
+/// `initial`, without copying them.
+///
+/// # Examples
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use peace_table::PieceTable;
+/// let initial: Arc<str> = Arc::from("shared");
+/// let mut a = PieceTable::from(Arc::clone(&initial));
+/// let b = PieceTable::from(initial);
+/// a.insert(0, "not ");
+/// assert_eq!(a.text(), "not shared");
+/// assert_eq!(b.text(), "shared");
+/// ```
+impl From<Arc<str>> for PieceTable<'static> {
+    fn from(initial: Arc<str>) -> Self {
+        Self::from_original(Original::Owned(initial))
     }
 }
 
@@ -582,11 +2213,208 @@ mod tests {
         assert_eq!(pt.text(), "abcdefg");
 
         if cfg!(feature = "contiguous-inserts") {
-            assert_eq!(pt.pieces.len(), 3);
+            assert_eq!(pt.tree.len(), 3);
         } else {
-            assert_eq!(pt.pieces.len(), 7);
+            assert_eq!(pt.tree.len(), 7);
         }
     }
+
+    #[test]
+    fn scattered_edits() {
+        let initial = "lorem ipsum dolor sit amet\n".repeat(2000);
+        let mut pt = PieceTable::new(&initial);
+        let mut expected = initial.clone();
+
+        for i in 0..2000 {
+            let idx = (i * 7919) % (pt.len_chars() + 1);
+            pt.insert(idx, "ab\ncd");
+            expected.insert_str(idx, "ab\ncd");
+
+            let start = (i * 104729) % pt.len_chars();
+            let end = (start + i % 40).min(pt.len_chars());
+            pt.remove(start..end);
+            expected.replace_range(start..end, "");
+        }
+
+        assert_eq!(pt.text(), expected);
+        assert_eq!(pt.len_chars(), expected.len());
+        #[cfg(feature = "lines")]
+        assert_eq!(pt.len_lines(), expected.matches('\n').count() + 1);
+    }
+
+    #[test]
+    fn into_owned() {
+        let initial = "lorem ipsum\n".repeat(100);
+        let mut pt = PieceTable::new(&initial);
+        pt.insert(6, "dolor ");
+        pt.remove(0..6);
+        let snapshot = pt.snapshot();
+
+        let mut owned = pt.into_owned();
+        assert_eq!(owned.text(), snapshot.text());
+        drop(snapshot);
+        drop(initial);
+        owned.insert(0, "a");
+        owned.undo();
+        owned.undo();
+        owned.undo();
+        assert_eq!(owned.text(), "lorem ipsum\n".repeat(100));
+    }
+
+    #[test]
+    fn index_conversions() {
+        let mut pt = PieceTable::new("aä⑤🦀");
+        for i in 0..200 {
+            let byte_idx = pt.char_to_byte((i * 13) % (pt.len_chars() + 1));
+            pt.insert_at_byte(byte_idx, ["b", "ö", "⑥", "🐙"][i % 4]);
+        }
+        let text = pt.text();
+
+        let mut utf16_idx = 0;
+        for (char_idx, (byte_idx, ch)) in text.char_indices().enumerate() {
+            assert_eq!(pt.char_to_byte(char_idx), byte_idx);
+            for i in byte_idx..byte_idx + ch.len_utf8() {
+                assert_eq!(pt.byte_to_char(i), char_idx);
+            }
+
+            assert_eq!(pt.char_to_utf16(char_idx), utf16_idx);
+            for i in utf16_idx..utf16_idx + ch.len_utf16() {
+                assert_eq!(pt.utf16_to_char(i), char_idx);
+            }
+            utf16_idx += ch.len_utf16();
+        }
+        assert_eq!(pt.char_to_byte(pt.len_chars()), pt.len_bytes());
+        assert_eq!(pt.byte_to_char(pt.len_bytes()), pt.len_chars());
+        assert_eq!(pt.len_utf16(), text.encode_utf16().count());
+        assert_eq!(pt.utf16_to_char(pt.len_utf16()), pt.len_chars());
+    }
+
+    #[test]
+    fn fallible_edits() {
+        use std::ops::Bound;
+
+        let mut pt = PieceTable::new("a⑤\r\nb");
+        pt.insert(5, "\r\n");
+        let text = pt.text();
+
+        let len = pt.len_chars();
+        let out_of_bounds = Err(Error::OutOfBounds { idx: len + 1, len });
+        assert_eq!(pt.try_insert(len + 1, "x"), out_of_bounds);
+        assert_eq!(pt.try_remove(1..len + 1), out_of_bounds);
+        assert_eq!(pt.try_char_to_byte(len + 1), out_of_bounds.map(|()| 0));
+        let (start, end) = (3, 2);
+        let invalid_range = Err(Error::InvalidRange { start, end });
+        assert_eq!(pt.try_slice(start..end).map(|_| ()), invalid_range);
+        assert_eq!(pt.try_remove(start..end), invalid_range);
+        assert_eq!(pt.try_remove_bytes(start..end), invalid_range);
+        let far = Err(Error::OutOfBounds { idx: 100, len });
+        assert_eq!(pt.try_remove(100..100), far);
+
+        // Inclusive ends at `usize::MAX` don't overflow.
+        let max = Err(Error::OutOfBounds { idx: usize::MAX, len });
+        assert_eq!(pt.try_slice(..=usize::MAX).map(|_| ()), max);
+        assert_eq!(pt.try_remove(0..=usize::MAX), max);
+        let after_max = (Bound::Excluded(usize::MAX), Bound::Unbounded);
+        assert_eq!(pt.try_remove(after_max), max);
+        let slice = pt.slice(1..3);
+        let max = Err(Error::OutOfBounds { idx: usize::MAX, len: 2 });
+        assert_eq!(slice.try_slice(..=usize::MAX).map(|_| ()), max);
+
+        for char_idx in [3, 6] {
+            let crlf_split = Err(Error::CrlfSplit { char_idx });
+            assert_eq!(pt.try_insert(char_idx, "x"), crlf_split);
+            assert_eq!(pt.try_remove(0..char_idx), crlf_split);
+            assert_eq!(pt.try_remove(char_idx..len), crlf_split);
+        }
+
+        let non_boundary = Err(Error::NonCharBoundary { byte_idx: 2 });
+        assert_eq!(pt.try_insert_at_byte(2, "x"), non_boundary);
+        assert_eq!(pt.try_remove_bytes(0..2), non_boundary);
+
+        assert_eq!(pt.text(), text);
+        assert_eq!(pt.try_insert(len, "x"), Ok(()));
+        assert_eq!(pt.try_remove(2..4), Ok(()));
+        assert_eq!(pt.text(), "a⑤b\r\nx");
+    }
+
+    #[test]
+    #[cfg(feature = "lines")]
+    fn line_break_configs() {
+        fn assert_lines(pt: &PieceTable) {
+            let text = pt.text();
+            let mut expected = vec![];
+            let config = pt.line_break_config();
+            str_utils::line_breaks(&text, &mut expected, 0, config);
+
+            assert_eq!(pt.len_lines(), expected.len() + 1);
+            for (line_idx, (byte_idx, ty)) in expected.into_iter().enumerate() {
+                assert_eq!(
+                    pt.line_to_byte(line_idx + 1),
+                    byte_idx + ty.len_bytes()
+                );
+            }
+        }
+
+        let mut pt = PieceTable::new("a\r\nb\rc\u{85}d\u{2029}");
+        for i in 0..300 {
+            let idx = (i * 31) % (pt.len_chars() + 1);
+            let _ =
+                pt.try_insert(idx, ["\r", "\n", "\u{2028}", "x\r\n"][i % 4]);
+        }
+
+        for config in [
+            LineBreakConfig::Lf,
+            #[cfg(feature = "unicode-line-breaks")]
+            LineBreakConfig::Unicode,
+            LineBreakConfig::LfCrlf,
+        ] {
+            pt = pt.with_line_breaks(config);
+            assert_lines(&pt);
+            for _ in 0..100 {
+                pt.undo();
+            }
+            assert_lines(&pt);
+            while pt.redo().is_some() {}
+            assert_lines(&pt);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "lines")]
+    fn line_lookups() {
+        let mut pt = PieceTable::new("ä\nbb\n\nc⑤c\n");
+        for i in 0..300 {
+            let idx = (i * 31) % (pt.len_chars() + 1);
+            pt.insert(idx, ["x", "\n", "yä\nz", "⑤"][i % 4]);
+        }
+        let text = pt.text();
+        let lines = text.split('\n').collect::<Vec<_>>();
+        assert_eq!(pt.len_lines(), lines.len());
+
+        let (mut char_idx, mut byte_idx) = (0, 0);
+        for (line_idx, line) in lines.iter().enumerate() {
+            assert_eq!(pt.line(line_idx).to_string(), *line);
+            let ending = (line_idx + 1 < lines.len()).then_some(LineBreak::Lf);
+            assert_eq!(pt.line_ending(line_idx), ending);
+            let with_ending = pt.line_with_ending(line_idx).to_string();
+            assert_eq!(
+                with_ending.strip_suffix('\n').unwrap_or(&with_ending),
+                *line
+            );
+            assert_eq!(pt.line_to_char(line_idx), char_idx);
+            assert_eq!(pt.line_to_byte(line_idx), byte_idx);
+
+            for (i, (j, _ch)) in line.char_indices().enumerate() {
+                assert_eq!(pt.char_to_line(char_idx + i), line_idx);
+                assert_eq!(pt.byte_to_line(byte_idx + j), line_idx);
+            }
+
+            char_idx += line.chars().count() + 1;
+            byte_idx += line.len() + 1;
+        }
+        assert_eq!(pt.line_to_char(pt.len_lines()), pt.len_chars());
+        assert_eq!(pt.char_to_line(pt.len_chars()), pt.len_lines() - 1);
+    }
 }
 
 #[cfg(test)]
@@ -613,4 +2441,58 @@ mod benches {
             }
         });
     }
+
+    #[bench]
+    fn bench_snapshot_per_edit(b: &mut Bencher) -> impl Termination {
+        let text = "lorem ipsum\n".repeat(10000);
+        let mut pt = PieceTable::new(&text);
+        for i in 0..10000 {
+            pt.insert(i * 11, "x");
+        }
+
+        let mut snapshots = vec![];
+        b.iter(|| {
+            for i in 0..100 {
+                snapshots.push(pt.snapshot());
+                pt.insert(i * 97, "y");
+            }
+            snapshots.clear();
+        });
+    }
+
+    #[bench]
+    fn bench_find(b: &mut Bencher) -> impl Termination {
+        let text = "lorem 🦀 ipsum\r\ndolor sit amet\n".repeat(1 << 15);
+        let mut pt = PieceTable::new(&text);
+        for i in (0..1000).rev() {
+            pt.insert(i * 30 * 32 + 2, "x");
+        }
+        pt.insert(pt.len_chars(), "needle");
+
+        b.iter(|| assert!(pt.find("needle").is_some()));
+    }
+
+    /// Opening a mapped file (here 16 MiB) and showing its first lines. The
+    /// file is read once to check it and count its chars, but only the first
+    /// pieces are scanned for line breaks.
+    #[bench]
+    #[cfg(feature = "mmap")]
+    fn bench_map_file(b: &mut Bencher) -> impl Termination {
+        let path = std::env::temp_dir()
+            .join(format!("peace-table-{}-bench_map_file", std::process::id()));
+        std::fs::write(
+            &path,
+            "lorem 🦀 ipsum\r\ndolor sit amet\n".repeat(1 << 19),
+        )
+        .unwrap();
+
+        // SAFETY: nothing else writes to the file.
+        b.iter(|| {
+            let pt = unsafe { PieceTable::map_file(&path) }.unwrap();
+            #[cfg(feature = "lines")]
+            assert_eq!(pt.lines_at(0).take(50).count(), 50);
+            pt
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
 }
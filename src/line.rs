@@ -1,26 +1,59 @@
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub(crate) enum Break {
+/// Which chars (and sequences of chars) are line breaks in a table (see
+/// [`PieceTable::new_with_line_breaks`]).
+///
+/// The default is [`LineBreakConfig::LfCrlf`], whichever features are enabled,
+/// so that enabling a feature never changes how lines are numbered. The
+/// `unicode-line-breaks` feature only adds [`LineBreakConfig::Unicode`].
+///
+/// [`PieceTable::new_with_line_breaks`]: crate::PieceTable::new_with_line_breaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum LineBreakConfig {
+    /// Only LF is a line break, so a CR before it is a part of the line.
+    Lf,
+    /// LF and CRLF are line breaks.
+    #[default]
+    LfCrlf,
+    /// All the line breaks defined by Unicode: LF, CRLF, VT, FF, CR, NEL, LS
+    /// and PS.
+    #[cfg(feature = "unicode-line-breaks")]
+    Unicode,
+}
+
+impl LineBreakConfig {
+    /// Whether a lone CR is a line break.
+    pub(crate) const fn breaks_at_cr(self) -> bool {
+        #[cfg(feature = "unicode-line-breaks")]
+        {
+            matches!(self, Self::Unicode)
+        }
+        #[cfg(not(feature = "unicode-line-breaks"))]
+        {
+            false
+        }
+    }
+}
+
+/// A line break (see [`PieceTable::line_ending`]).
+///
+/// [`PieceTable::line_ending`]: crate::PieceTable::line_ending
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Break {
     /// Line Feed, U+000A
     Lf,
     /// CR (U+000D) followed by LF (U+000A)
     Crlf,
     /// Vertical Tab, U+000B
-    #[cfg(feature = "unicode-line-breaks")]
     Vt,
     /// Form Feed, U+000C
-    #[cfg(feature = "unicode-line-breaks")]
     Ff,
     /// Carriage Return, U+000D
-    #[cfg(feature = "unicode-line-breaks")]
     Cr,
     /// Next Line, U+0085
-    #[cfg(feature = "unicode-line-breaks")]
     Nel,
     /// Line Separator, U+2028
-    #[cfg(feature = "unicode-line-breaks")]
     Ls,
     /// Paragraph Separator, U+2029
-    #[cfg(feature = "unicode-line-breaks")]
     Ps,
 }
 
@@ -34,8 +67,29 @@ impl Break {
     const LS: &str = "\u{2028}";
     const PS: &str = "\u{2029}";
 
+    /// The text of this line break.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::LineBreak;
+    /// assert_eq!(LineBreak::Crlf.as_str(), "\r\n");
+    /// ```
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => Self::LF,
+            Self::Crlf => Self::CRLF,
+            Self::Vt => Self::VT,
+            Self::Ff => Self::FF,
+            Self::Cr => Self::CR,
+            Self::Nel => Self::NEL,
+            Self::Ls => Self::LS,
+            Self::Ps => Self::PS,
+        }
+    }
+
     /// The amount of characters this line break takes.
-    pub(crate) const fn len_chars(&self) -> usize {
+    pub const fn len_chars(&self) -> usize {
         match self {
             Self::Crlf => 2,
             _ => 1,
@@ -43,22 +97,7 @@ impl Break {
     }
 
     /// The amount of bytes this line break takes.
-    pub(crate) const fn len_bytes(&self) -> usize {
-        match self {
-            Self::Lf => Self::LF.len(),
-            Self::Crlf => Self::CRLF.len(),
-            #[cfg(feature = "unicode-line-breaks")]
-            Self::Vt => Self::VT.len(),
-            #[cfg(feature = "unicode-line-breaks")]
-            Self::Ff => Self::FF.len(),
-            #[cfg(feature = "unicode-line-breaks")]
-            Self::Cr => Self::CR.len(),
-            #[cfg(feature = "unicode-line-breaks")]
-            Self::Nel => Self::NEL.len(),
-            #[cfg(feature = "unicode-line-breaks")]
-            Self::Ls => Self::LS.len(),
-            #[cfg(feature = "unicode-line-breaks")]
-            Self::Ps => Self::PS.len(),
-        }
+    pub const fn len_bytes(&self) -> usize {
+        self.as_str().len()
     }
 }
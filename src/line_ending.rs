@@ -0,0 +1,325 @@
+//! Detecting and converting the line endings of a table.
+
+use crate::PieceTable;
+use crate::crlf::CrlfPolicy;
+use crate::error::{self, Error};
+use crate::line::Break;
+
+/// A line ending, i.e., one of the line breaks which end lines in text files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    /// Line Feed, U+000A
+    Lf,
+    /// CR (U+000D) followed by LF (U+000A)
+    Crlf,
+    /// Carriage Return, U+000D
+    Cr,
+}
+
+impl LineEnding {
+    /// The text of the line ending.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    fn len_chars(self) -> usize {
+        self.as_str().chars().count()
+    }
+
+    const fn from_break(ty: Break) -> Option<Self> {
+        match ty {
+            Break::Lf => Some(Self::Lf),
+            Break::Crlf => Some(Self::Crlf),
+            Break::Cr => Some(Self::Cr),
+            _ => None,
+        }
+    }
+}
+
+/// The amount of every kind of line ending in a table (see
+/// [`PieceTable::line_endings`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LineEndings {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize,
+}
+
+impl LineEndings {
+    /// The most common line ending, or [`None`] if there are no line endings.
+    /// Ties are broken in favor of LF, and then of CRLF.
+    pub fn dominant(&self) -> Option<LineEnding> {
+        [
+            (self.cr, LineEnding::Cr),
+            (self.crlf, LineEnding::Crlf),
+            (self.lf, LineEnding::Lf),
+        ]
+        .into_iter()
+        .filter(|&(count, _ending)| count > 0)
+        .max_by_key(|&(count, _ending)| count)
+        .map(|(_count, ending)| ending)
+    }
+
+    /// Whether there is more than one kind of line ending.
+    pub fn is_mixed(&self) -> bool {
+        [self.lf, self.crlf, self.cr].iter().filter(|&&n| n > 0).count() > 1
+    }
+}
+
+impl PieceTable<'_> {
+    /// Count the line endings in the table. Only line breaks are counted, so a
+    /// lone CR is counted only with [`LineBreakConfig::Unicode`].
+    ///
+    /// Runs in `O(N)` where `N` is the amount of line breaks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{LineEnding, PieceTable};
+    /// let mut pt = PieceTable::new("one\r\ntwo\r\nthree");
+    /// assert_eq!(pt.line_endings().dominant(), Some(LineEnding::Crlf));
+    /// assert!(!pt.line_endings().is_mixed());
+    ///
+    /// pt.insert(3, "\n");
+    /// assert_eq!(pt.line_endings().lf, 1);
+    /// assert!(pt.line_endings().is_mixed());
+    /// ```
+    ///
+    /// [`LineBreakConfig::Unicode`]: crate::LineBreakConfig::Unicode
+    pub fn line_endings(&self) -> LineEndings {
+        let mut endings = LineEndings::default();
+        for piece in self.tree.iter() {
+            for &(_idx, ty) in piece.line_breaks(&self.buffers) {
+                match LineEnding::from_break(ty) {
+                    Some(LineEnding::Lf) => endings.lf += 1,
+                    Some(LineEnding::Crlf) => endings.crlf += 1,
+                    Some(LineEnding::Cr) => endings.cr += 1,
+                    None => {}
+                }
+            }
+        }
+        endings
+    }
+
+    /// Convert the line endings which are inside `char_range` to `ending`, as
+    /// a single step in the history of the table, and return the amount of
+    /// line endings that were changed. Like [`PieceTable::line_endings`], only
+    /// line breaks are converted, but a CR right before an LF is converted
+    /// along with it even if it is not a part of the line break (with
+    /// [`LineBreakConfig::Lf`]).
+    ///
+    /// Runs in `O(M log N)` where `M` is the amount of line breaks in the
+    /// range, and `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the end of the range is larger than the size of the
+    /// contents, or if its start is larger than its end. Will also panic if
+    /// `ending` is [`LineEnding::Cr`] but a lone CR is not a line break in the
+    /// table.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{LineEnding, PieceTable};
+    /// let mut pt = PieceTable::new("a\nb\r\nc\nd");
+    /// assert_eq!(pt.convert_line_endings(.., LineEnding::Crlf), 2);
+    /// assert_eq!(pt.text(), "a\r\nb\r\nc\r\nd");
+    ///
+    /// assert_eq!(pt.convert_line_endings(..4, LineEnding::Lf), 1);
+    /// assert_eq!(pt.text(), "a\nb\r\nc\r\nd");
+    ///
+    /// pt.undo();
+    /// pt.undo();
+    /// assert_eq!(pt.text(), "a\nb\r\nc\nd");
+    /// ```
+    ///
+    /// [`LineBreakConfig::Lf`]: crate::LineBreakConfig::Lf
+    pub fn convert_line_endings<R>(
+        &mut self,
+        char_range: R,
+        ending: LineEnding,
+    ) -> usize
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        error::unwrap(self.try_convert_line_endings(char_range, ending))
+    }
+
+    /// Like [`PieceTable::convert_line_endings`], but returns an [`Error`]
+    /// instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidRange`] if the start of the range is larger than
+    /// its end, [`Error::OutOfBounds`] if its end is larger than the size of
+    /// the contents, and [`Error::CrNotLineBreak`] if `ending` is
+    /// [`LineEnding::Cr`] but a lone CR is not a line break in the table. The
+    /// table is not changed in that case.
+    pub fn try_convert_line_endings<R>(
+        &mut self,
+        char_range: R,
+        ending: LineEnding,
+    ) -> Result<usize, Error>
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        let (start, end) =
+            Self::simplify_range_bounds(char_range, self.len_chars())?;
+        error::check_range(start, end, self.len_chars())?;
+        if ending == LineEnding::Cr && !self.line_break_config().breaks_at_cr()
+        {
+            return Err(Error::CrNotLineBreak);
+        }
+
+        // Adjacent line endings are replaced together, so that converting one
+        // of them doesn't join it with the next one (e.g., a CR and an LF).
+        let mut runs: Vec<(usize, usize, String)> = vec![];
+        let mut converted = 0;
+        let mut len_diff = 0;
+        for line_break_idx in self.char_to_line(start)..self.len_lines() - 1 {
+            let (position, ty) = self.line_break(line_break_idx);
+            if position.chars + ty.len_chars() > end {
+                break;
+            }
+            let Some(mut found) = LineEnding::from_break(ty) else {
+                continue;
+            };
+            let mut idx = position.chars;
+            if found == LineEnding::Lf && self.chars_around(idx).0 == Some('\r')
+            {
+                found = LineEnding::Crlf;
+                idx -= 1;
+            }
+            if idx < start || found == ending {
+                continue;
+            }
+
+            converted += 1;
+            len_diff +=
+                ending.len_chars() as isize - found.len_chars() as isize;
+            let found_end = idx + found.len_chars();
+            match runs.last_mut() {
+                Some((_start, run_end, text)) if *run_end == idx => {
+                    *run_end = found_end;
+                    text.push_str(ending.as_str());
+                }
+                _ => runs.push((idx, found_end, ending.as_str().to_owned())),
+            }
+        }
+
+        // The runs don't touch each other, so each of them is fitted without
+        // regard to the others, before any of them is replaced.
+        let replacements = runs
+            .iter()
+            .map(|(start, end, text)| {
+                self.fit_crlf(*start, *end, text, CrlfPolicy::Split)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.begin_group(&[start, end]);
+        // Converting from the end keeps the indexes of the earlier runs.
+        for (start, end, text) in replacements.into_iter().rev() {
+            self.splice(start, end, &text);
+        }
+        self.end_group(&[start, end.saturating_add_signed(len_diff)]);
+
+        Ok(converted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LineBreakConfig;
+
+    #[test]
+    fn convert_crlf_with_lf_config() {
+        let mut pt =
+            PieceTable::new_with_line_breaks("a\r\nb\nc", LineBreakConfig::Lf);
+        assert_eq!(pt.convert_line_endings(.., LineEnding::Crlf), 1);
+        assert_eq!(pt.text(), "a\r\nb\r\nc");
+        assert_eq!(pt.len_lines(), 3);
+
+        // The CR of the first line ending is outside the range.
+        assert_eq!(pt.convert_line_endings(2.., LineEnding::Lf), 1);
+        assert_eq!(pt.text(), "a\r\nb\nc");
+        assert_eq!(pt.convert_line_endings(.., LineEnding::Lf), 1);
+        assert_eq!(pt.text(), "a\nb\nc");
+
+        pt.undo();
+        pt.undo();
+        pt.undo();
+        assert_eq!(pt.text(), "a\r\nb\nc");
+    }
+
+    #[test]
+    fn convert_to_cr_without_cr_line_breaks() {
+        for config in [LineBreakConfig::Lf, LineBreakConfig::LfCrlf] {
+            let mut pt = PieceTable::new_with_line_breaks("a\r\nb\nc", config);
+            assert_eq!(
+                pt.try_convert_line_endings(.., LineEnding::Cr),
+                Err(Error::CrNotLineBreak)
+            );
+            assert_eq!(pt.text(), "a\r\nb\nc");
+            assert!(!pt.can_undo());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-line-breaks")]
+    fn convert_ranges() {
+        let initial = "a\nb\r\nc\rd\u{2028}e\r\n".repeat(50);
+        let mut pt = PieceTable::new(&initial)
+            .with_line_breaks(LineBreakConfig::Unicode);
+        let endings = pt.line_endings();
+        assert_eq!((endings.lf, endings.crlf, endings.cr), (50, 100, 50));
+        assert_eq!(endings.dominant(), Some(LineEnding::Crlf));
+
+        for (i, ending) in [LineEnding::Cr, LineEnding::Lf, LineEnding::Crlf]
+            .into_iter()
+            .cycle()
+            .take(30)
+            .enumerate()
+        {
+            let splits_crlf = |idx| {
+                idx > 0 && pt.slice(idx - 1..).to_string().starts_with("\r\n")
+            };
+            let mut start = (i * 7919) % pt.len_chars();
+            let mut end = (start + i * 13).min(pt.len_chars());
+            if splits_crlf(start) {
+                start += 1;
+            }
+            if splits_crlf(end) {
+                end += 1;
+            }
+            let end = end.max(start);
+            let expected = pt.slice(..start).to_string()
+                + &pt
+                    .slice(start..end)
+                    .to_string()
+                    .replace("\r\n", "\n")
+                    .replace('\r', "\n")
+                    .replace('\n', ending.as_str())
+                + &pt.slice(end..).to_string();
+
+            pt.convert_line_endings(start..end, ending);
+            assert_eq!(pt.text(), expected);
+
+            let text = pt.text();
+            let mut line_breaks = vec![];
+            let config = pt.line_break_config();
+            crate::str_utils::line_breaks(&text, &mut line_breaks, 0, config);
+            assert_eq!(pt.len_lines(), line_breaks.len() + 1);
+        }
+
+        pt.convert_line_endings(.., LineEnding::Lf);
+        assert!(!pt.line_endings().is_mixed());
+        while pt.undo().is_some() {}
+        assert_eq!(pt.text(), initial);
+    }
+}
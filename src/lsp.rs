@@ -0,0 +1,513 @@
+//! Interoperability with the [Language Server Protocol].
+//!
+//! LSP addresses text by lines and UTF-16 columns, and synchronizes documents
+//! using incremental content changes (`textDocument/didChange`). This module
+//! converts between LSP positions and char indexes, applies such changes to a
+//! [`PieceTable`], and records the edits made to a table as such changes, so an
+//! editor can keep a server in sync without copying the whole document.
+//!
+//! Lines are always split at LF, CRLF and CR here, as LSP splits them, no
+//! matter which [`LineBreakConfig`] the table has.
+//!
+//! [`LineBreakConfig`]: crate::LineBreakConfig
+//! [Language Server Protocol]: https://microsoft.github.io/language-server-protocol/
+
+use crate::PieceTable;
+use crate::error::{self, Error};
+use crate::piece::Piece;
+use crate::str_utils;
+
+/// A position in a document, as defined by LSP.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    /// Zero based line index.
+    pub line: usize,
+    /// Zero based column, in UTF-16 code units.
+    pub character: usize,
+}
+
+/// A range in a document, as defined by LSP. The end is exclusive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// A change to the contents of a document, i.e., LSP's
+/// `TextDocumentContentChangeEvent`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContentChange {
+    /// The range that is replaced by `text`. If it is [`None`], `text` is the
+    /// whole new contents of the document.
+    pub range: Option<Range>,
+    pub text: String,
+}
+
+impl PieceTable<'_> {
+    /// Returns the char index of an LSP [`Position`].
+    ///
+    /// Like in LSP, if the column is larger than the length of the line, it
+    /// defaults back to the end of the line (before its line break).
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the line is out of bounds (i.e., there is no such line).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{PieceTable, lsp::Position};
+    /// let pt = PieceTable::new("🦀\n🐙 ink\rend");
+    /// assert_eq!(pt.position_to_char(Position { line: 1, character: 3 }), 4);
+    /// assert_eq!(pt.position_to_char(Position { line: 1, character: 9 }), 7);
+    /// assert_eq!(pt.position_to_char(Position { line: 2, character: 0 }), 8);
+    /// ```
+    pub fn position_to_char(&self, position: Position) -> usize {
+        assert!(self.has_lsp_line(position.line), "line index out of bounds");
+
+        let start = self.lsp_line_start_utf16(position.line);
+        let end = match self.find_lsp_line_break_utf16(position.line) {
+            Some((end, _len)) => end,
+            None => self.len_utf16(), // the last line
+        };
+        self.utf16_to_char(end.min(start.saturating_add(position.character)))
+    }
+
+    /// Returns the LSP [`Position`] of the char at `char_idx`.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{PieceTable, lsp::Position};
+    /// let pt = PieceTable::new("🦀\n🐙 ink\rend");
+    /// assert_eq!(pt.char_to_position(4), Position { line: 1, character: 3 });
+    /// assert_eq!(pt.char_to_position(8), Position { line: 2, character: 0 });
+    /// ```
+    pub fn char_to_position(&self, char_idx: usize) -> Position {
+        assert!(char_idx <= self.len_chars(), "index out of bounds");
+
+        let Some((before, node)) = self.tree.node_at_char(char_idx) else {
+            return Position::default(); // the table is empty
+        };
+        let text = self.tree.piece(node).text(&self.buffers);
+        let byte_idx = str_utils::char_to_byte(text, char_idx - before.chars);
+
+        // A line break which ends after `byte_idx` (i.e., a CRLF sequence it
+        // is inside of) is still a part of the line.
+        let before = self.tree.metrics_before(node, &self.buffers);
+        let line = before.lsp_line_breaks
+            + str_utils::lsp_line_breaks(text)
+                .take_while(|&(idx, len)| idx + len <= byte_idx)
+                .count();
+        let utf16_idx = before.utf16 + str_utils::byte_to_utf16(text, byte_idx);
+        Position {
+            line,
+            character: utf16_idx - self.lsp_line_start_utf16(line),
+        }
+    }
+
+    /// Apply an LSP content change to the table, as a single step in its
+    /// history.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the range of the change has a line which is out of
+    /// bounds, or if its start is after its end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// # use peace_table::lsp::{ContentChange, Position, Range};
+    /// let mut pt = PieceTable::new("let 🦀 = 1;\nlet x = 2;");
+    /// pt.apply_change(&ContentChange {
+    ///     range: Some(Range {
+    ///         start: Position { line: 0, character: 4 },
+    ///         end: Position { line: 0, character: 6 },
+    ///     }),
+    ///     text: "crab".to_string(),
+    /// });
+    /// assert_eq!(pt.text(), "let crab = 1;\nlet x = 2;");
+    ///
+    /// pt.undo();
+    /// assert_eq!(pt.text(), "let 🦀 = 1;\nlet x = 2;");
+    /// ```
+    pub fn apply_change(&mut self, change: &ContentChange) {
+        error::unwrap(self.try_apply_change(change));
+    }
+
+    /// Like [`PieceTable::apply_change`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged). As changes come from
+    /// another process, this is the one to use for changes that were not
+    /// validated.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if the range of the change has a line
+    /// which is out of bounds, and [`Error::InvalidRange`] if its start is
+    /// after its end.
+    ///
+    /// Notice that a position is never between the CR and the LF of a CRLF
+    /// sequence, as LSP lines end before their line break, so the table's
+    /// [`CrlfPolicy`] doesn't apply.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{Error, PieceTable};
+    /// # use peace_table::lsp::{ContentChange, Position, Range};
+    /// let mut pt = PieceTable::new("one\ntwo");
+    /// let change = |start, end| ContentChange {
+    ///     range: Some(Range {
+    ///         start: Position { line: start, character: 0 },
+    ///         end: Position { line: end, character: 0 },
+    ///     }),
+    ///     text: String::new(),
+    /// };
+    /// assert_eq!(
+    ///     pt.try_apply_change(&change(1, 2)),
+    ///     Err(Error::OutOfBounds { idx: 2, len: 2 })
+    /// );
+    /// assert_eq!(
+    ///     pt.try_apply_change(&change(1, 0)),
+    ///     Err(Error::InvalidRange { start: 4, end: 0 })
+    /// );
+    /// assert_eq!(pt.try_apply_change(&change(0, 1)), Ok(()));
+    /// assert_eq!(pt.text(), "two");
+    /// ```
+    ///
+    /// [`CrlfPolicy`]: crate::CrlfPolicy
+    pub fn try_apply_change(
+        &mut self,
+        change: &ContentChange,
+    ) -> Result<(), Error> {
+        let (start, end) = match change.range {
+            Some(Range { start, end }) => {
+                self.check_lsp_line(start.line)?;
+                self.check_lsp_line(end.line)?;
+                (self.position_to_char(start), self.position_to_char(end))
+            }
+            None => (0, self.len_chars()),
+        };
+        error::check_range(start, end, self.len_chars())?;
+        if start == end && change.text.is_empty() {
+            return Ok(());
+        }
+
+        let (start, end, text) =
+            self.fit_crlf(start, end, &change.text, self.crlf_policy)?;
+        self.splice(start, end, &text);
+        Ok(())
+    }
+
+    /// Start (or stop) recording every edit made to the table as an LSP
+    /// content change. Stopping discards the changes that were not taken yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut server = PieceTable::new("hello\nworld");
+    /// let mut client = PieceTable::new("hello\nworld");
+    /// client.record_changes(true);
+    ///
+    /// client.insert(11, "!");
+    /// client.remove(0..1);
+    /// client.insert(0, "J");
+    ///
+    /// for change in client.take_changes() {
+    ///     server.apply_change(&change);
+    /// }
+    /// assert_eq!(server.text(), "Jello\nworld!");
+    /// ```
+    pub fn record_changes(&mut self, record: bool) {
+        match (record, &self.changes) {
+            (true, None) => self.changes = Some(vec![]),
+            (false, _) => self.changes = None,
+            (true, Some(_)) => {}
+        }
+    }
+
+    /// Take all the changes that were recorded since the last call, in the
+    /// order they were made. See [`PieceTable::record_changes`].
+    pub fn take_changes(&mut self) -> Vec<ContentChange> {
+        self.changes.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Record the insertion of `text` at `char_idx`, if changes are being
+    /// recorded. Must be called _before_ the insertion.
+    pub(crate) fn record_insert(&mut self, char_idx: usize, text: &str) {
+        if self.changes.is_none() {
+            return;
+        }
+
+        let position = self.char_to_position(char_idx);
+        self.push_change(position..position, text);
+    }
+
+    /// Record the insertion of `pieces` at `char_idx`, if changes are being
+    /// recorded. Must be called _before_ the insertion.
+    pub(crate) fn record_insert_pieces(
+        &mut self,
+        char_idx: usize,
+        pieces: &[Piece],
+    ) {
+        if self.changes.is_none() {
+            return;
+        }
+
+        let text =
+            pieces.iter().map(|p| p.text(&self.buffers)).collect::<String>();
+        self.record_insert(char_idx, &text);
+    }
+
+    /// Record the removal of the chars in `start..end`, if changes are being
+    /// recorded. Must be called _before_ the removal.
+    pub(crate) fn record_remove(&mut self, start: usize, end: usize) {
+        if self.changes.is_none() {
+            return;
+        }
+
+        let start = self.char_to_position(start);
+        let end = self.char_to_position(end);
+        self.push_change(start..end, "");
+    }
+
+    /// Record the replacement of the chars in `start..end` with `text`, if
+    /// changes are being recorded. Must be called _before_ the replacement.
+    pub(crate) fn record_replace(
+        &mut self,
+        start: usize,
+        end: usize,
+        text: &str,
+    ) {
+        if self.changes.is_none() {
+            return;
+        }
+
+        let start = self.char_to_position(start);
+        let end = self.char_to_position(end);
+        self.push_change(start..end, text);
+    }
+
+    /// The amount of lines in the table, as LSP splits them.
+    fn len_lsp_lines(&self) -> usize {
+        self.tree.total(&self.buffers).lsp_line_breaks + 1
+    }
+
+    /// Whether there is a `line_idx`-th LSP line, scanning the text only up to
+    /// the line (if it wasn't scanned yet).
+    fn has_lsp_line(&self, line_idx: usize) -> bool {
+        line_idx == 0 || self.find_lsp_line_break_utf16(line_idx - 1).is_some()
+    }
+
+    fn check_lsp_line(&self, line_idx: usize) -> Result<(), Error> {
+        if self.has_lsp_line(line_idx) {
+            Ok(())
+        } else {
+            Err(Error::OutOfBounds { idx: line_idx, len: self.len_lsp_lines() })
+        }
+    }
+
+    /// The UTF-16 index of the start of the `line_idx`-th LSP line, which must
+    /// be in bounds.
+    fn lsp_line_start_utf16(&self, line_idx: usize) -> usize {
+        match line_idx.checked_sub(1) {
+            Some(line_break_idx) => {
+                let (start, len) = self.lsp_line_break_utf16(line_break_idx);
+                start + len
+            }
+            None => 0,
+        }
+    }
+
+    /// Find the `line_break_idx`-th LSP line break in the table, and return
+    /// the UTF-16 index of its start and its length (which is the same in
+    /// every unit, as line breaks are ASCII).
+    ///
+    /// # Panics
+    ///
+    /// Will panic if there is no such line break.
+    fn lsp_line_break_utf16(&self, line_break_idx: usize) -> (usize, usize) {
+        let Some(line_break) = self.find_lsp_line_break_utf16(line_break_idx)
+        else {
+            panic!("line index out of bounds");
+        };
+        line_break
+    }
+
+    /// Like [`PieceTable::lsp_line_break_utf16`], but returns [`None`] if
+    /// there is no such line break.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces, once the text up
+    /// to the line break was scanned.
+    fn find_lsp_line_break_utf16(
+        &self,
+        line_break_idx: usize,
+    ) -> Option<(usize, usize)> {
+        let (before, node) =
+            self.tree.node_at_lsp_line_break(line_break_idx, &self.buffers)?;
+        let text = self.tree.piece(node).text(&self.buffers);
+        let nth = line_break_idx - before.lsp_line_breaks;
+        let Some((idx, len)) = str_utils::lsp_line_breaks(text).nth(nth) else {
+            unreachable!("the piece has more than `nth` LSP line breaks")
+        };
+        Some((before.utf16 + str_utils::count_utf16(&text[..idx]), len))
+    }
+
+    fn push_change(&mut self, range: std::ops::Range<Position>, text: &str) {
+        if let Some(changes) = &mut self.changes {
+            changes.push(ContentChange {
+                range: Some(Range { start: range.start, end: range.end }),
+                text: text.to_owned(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_recorded_changes() {
+        let initial = "fn main() {\n    println!(\"🦀\");\n}\n";
+        let mut client = PieceTable::new(initial);
+        let mut server = PieceTable::new(initial);
+        client.record_changes(true);
+
+        for i in 0..100 {
+            let idx = (i * 37) % (client.len_chars() + 1);
+            client.insert(idx, ["a", "🐙", "\n", "ö\nx"][i % 4]);
+            let start = (i * 53) % client.len_chars();
+            client.remove(start..(start + i % 3).min(client.len_chars()));
+        }
+
+        for change in client.take_changes() {
+            server.apply_change(&change);
+        }
+        assert_eq!(server.text(), client.text());
+        assert!(client.take_changes().is_empty());
+    }
+
+    #[test]
+    fn replay_across_line_break_configs() {
+        use crate::LineBreakConfig;
+
+        let configs = [
+            LineBreakConfig::Lf,
+            LineBreakConfig::LfCrlf,
+            #[cfg(feature = "unicode-line-breaks")]
+            LineBreakConfig::Unicode,
+        ];
+        let initial = "a\rb\r\nc\u{2028}d\u{b}e\n";
+
+        for config in configs {
+            let mut client = PieceTable::new_with_line_breaks(initial, config);
+            let mut server = PieceTable::new(initial);
+            client.record_changes(true);
+
+            for i in 0..100 {
+                let idx = (i * 37) % (client.len_chars() + 1);
+                let text = ["\r", "\n", "x\r\n", "\u{2028}", "🦀"][i % 5];
+                let _ = client.try_insert(idx, text);
+                let start = (i * 53) % client.len_chars();
+                let end = (start + i % 3).min(client.len_chars());
+                let _ = client.try_remove(start..end);
+            }
+
+            for change in client.take_changes() {
+                server.apply_change(&change);
+            }
+            assert_eq!(server.text(), client.text(), "{config:?}");
+            assert_eq!(client.validate(), Ok(()), "{config:?}");
+        }
+    }
+
+    #[test]
+    fn lone_cr() {
+        let pt = PieceTable::new("a\rb");
+        assert_eq!(pt.char_to_position(2), Position { line: 1, character: 0 });
+        assert_eq!(pt.position_to_char(Position { line: 0, character: 5 }), 1);
+        assert_eq!(pt.position_to_char(Position { line: 1, character: 1 }), 3);
+
+        // The CR and the LF are joined into a single line break.
+        let mut pt = PieceTable::new("a\r");
+        pt.insert(2, "\n");
+        pt.insert(3, "b\r");
+        assert_eq!(pt.char_to_position(2), Position { line: 0, character: 2 });
+        assert_eq!(pt.char_to_position(3), Position { line: 1, character: 0 });
+        assert_eq!(pt.char_to_position(5), Position { line: 2, character: 0 });
+        assert_eq!(
+            pt.try_apply_change(&ContentChange {
+                range: Some(Range {
+                    start: Position { line: 3, character: 0 },
+                    end: Position { line: 3, character: 0 },
+                }),
+                text: String::new(),
+            }),
+            Err(Error::OutOfBounds { idx: 3, len: 3 })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-line-breaks")]
+    fn unicode_line_breaks() {
+        let pt = PieceTable::new_with_line_breaks(
+            "a\u{2028}b\u{b}c\nd",
+            crate::LineBreakConfig::Unicode,
+        );
+        assert_eq!(pt.len_lines(), 4);
+        assert_eq!(pt.char_to_position(2), Position { line: 0, character: 2 });
+        assert_eq!(pt.char_to_position(6), Position { line: 1, character: 0 });
+        assert_eq!(
+            pt.position_to_char(Position { line: 0, character: 100 }),
+            5
+        );
+    }
+
+    #[test]
+    fn untrusted_changes() {
+        // Under `Lf`, a CR is an ordinary char of the table's lines, but LSP
+        // lines still end before it.
+        let mut pt = PieceTable::new("a\r\nb")
+            .with_line_breaks(crate::LineBreakConfig::Lf);
+        pt.set_crlf_policy(crate::CrlfPolicy::Reject);
+        let change =
+            |start: (usize, usize), end: (usize, usize), text: &str| {
+                ContentChange {
+                    range: Some(Range {
+                        start: Position { line: start.0, character: start.1 },
+                        end: Position { line: end.0, character: end.1 },
+                    }),
+                    text: text.to_owned(),
+                }
+            };
+
+        assert_eq!(
+            pt.try_apply_change(&change((0, 0), (5, 0), "")),
+            Err(Error::OutOfBounds { idx: 5, len: 2 })
+        );
+        assert_eq!(
+            pt.try_apply_change(&change((0, usize::MAX), (0, 2), "x")),
+            Ok(())
+        );
+        assert_eq!(pt.text(), "ax\r\nb");
+        assert_eq!(pt.try_apply_change(&change((0, 1), (0, 1), "")), Ok(()));
+        assert_eq!(pt.try_apply_change(&change((0, 1), (1, 0), "x")), Ok(()));
+        assert_eq!(pt.text(), "axb");
+
+        pt.undo();
+        pt.undo();
+        assert_eq!(pt.text(), "a\r\nb");
+        assert_eq!(pt.undo(), None, "every change is a single step");
+    }
+}
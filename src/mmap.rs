@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "lines")]
+use crate::LineBreakConfig;
+use crate::PieceTable;
+use crate::buffer::Buffers;
+
+impl PieceTable<'static> {
+    /// Open the file at `path` as the initial contents of a table, by mapping
+    /// it into memory instead of reading it into a string.
+    ///
+    /// The contents are never copied, so the memory used by the table is
+    /// mostly the pages of the file which the OS keeps cached. Opening the file
+    /// reads it once, in pieces of a few KiB, checking that each piece is valid
+    /// UTF-8 and counting its chars. The line breaks and the UTF-16 lengths of
+    /// a piece are scanned only the first time a lookup needs them, so showing
+    /// the first lines of the file doesn't scan the rest of it (while
+    /// [`PieceTable::len_lines`], for example, scans the whole file once).
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the file could not be opened or mapped, or if
+    /// its contents are not valid UTF-8 (with [`io::ErrorKind::InvalidData`]).
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified (by this process or by any other) while
+    /// the table, or any [`Snapshot`](crate::Snapshot) of it, is alive. See
+    /// [`memmap2::Mmap`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use peace_table::PieceTable;
+    /// // SAFETY: nobody modifies the log while it is open.
+    /// let pt = unsafe { PieceTable::map_file("huge.log")? };
+    /// println!("{}", pt.slice(..80));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub unsafe fn map_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        // SAFETY: the caller guarantees that the file won't be modified.
+        let buffers = unsafe { map(path.as_ref())? };
+        Ok(Self::from_buffers(buffers))
+    }
+
+    /// Open the file at `path` as the initial contents of a table, by mapping
+    /// it into memory, in which the line breaks are the ones selected by
+    /// `config` (see [`PieceTable::new_with_line_breaks`]).
+    ///
+    /// # Errors
+    ///
+    /// The same as [`PieceTable::map_file`].
+    ///
+    /// # Safety
+    ///
+    /// The same as [`PieceTable::map_file`].
+    #[cfg(feature = "lines")]
+    pub unsafe fn map_file_with_line_breaks(
+        path: impl AsRef<Path>,
+        config: LineBreakConfig,
+    ) -> io::Result<Self> {
+        // SAFETY: the caller guarantees that the file won't be modified.
+        let mut buffers = unsafe { map(path.as_ref())? };
+        buffers.line_break_config = config;
+        Ok(Self::from_buffers(buffers))
+    }
+}
+
+/// Map the file at `path` as the `original` buffer, after checking that it is
+/// valid UTF-8.
+///
+/// # Safety
+///
+/// See [`PieceTable::map_file`].
+unsafe fn map(path: &Path) -> io::Result<Buffers<'static>> {
+    let file = File::open(path)?;
+    // SAFETY: the caller guarantees that the file won't be modified.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    Buffers::from_mapped(map).map_err(|byte_idx| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid UTF-8 at byte {byte_idx}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("peace-table-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn map_and_edit() {
+        let path = temp_path("map_and_edit");
+        let contents = "lorem 🦀 ipsum\r\ndolor sit amet\n".repeat(5000);
+        std::fs::write(&path, &contents).unwrap();
+
+        let mut pt = unsafe { PieceTable::map_file(&path) }.unwrap();
+        assert_eq!(pt.text(), contents);
+        #[cfg(feature = "lines")]
+        assert_eq!(pt.len_lines(), 10001);
+        #[cfg(feature = "lines")]
+        assert_eq!(pt.line(4321).to_string(), "dolor sit amet");
+
+        #[cfg(feature = "lines")]
+        {
+            let config = LineBreakConfig::Lf;
+            let pt =
+                unsafe { PieceTable::map_file_with_line_breaks(&path, config) }
+                    .unwrap();
+            assert_eq!(pt.len_lines(), 10001);
+            assert_eq!(pt.line(0).to_string(), "lorem 🦀 ipsum\r");
+        }
+
+        pt.insert(6, "🐙 ");
+        pt.remove(0..6);
+        assert!(pt.text().starts_with("🐙 🦀 ipsum\r\n"));
+
+        drop(pt);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "lines")]
+    fn scanned_on_demand() {
+        let path = temp_path("scanned_on_demand");
+        let contents = "lorem 🦀 ipsum\r\ndolor sit amet\n".repeat(5000);
+        std::fs::write(&path, &contents).unwrap();
+
+        let pt = unsafe { PieceTable::map_file(&path) }.unwrap();
+        assert_eq!(pt.line(1).to_string(), "dolor sit amet");
+        assert_eq!(pt.char_to_utf16(17), 18);
+        let scanned = pt.tree.iter().filter(|p| p.scanned_metrics().is_some());
+        assert_eq!(scanned.count(), 1);
+
+        assert_eq!(pt.line(9999).to_string(), "dolor sit amet");
+        assert_eq!(pt.len_utf16(), contents.encode_utf16().count());
+        assert_eq!(pt.validate(), Ok(()));
+
+        drop(pt);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn empty_file() {
+        let path = temp_path("empty_file");
+        std::fs::write(&path, "").unwrap();
+
+        let pt = unsafe { PieceTable::map_file(&path) }.unwrap();
+        assert_eq!(pt.len_chars(), 0);
+
+        drop(pt);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let path = temp_path("invalid_utf8");
+        std::fs::write(&path, b"valid\n\xFF\xFE").unwrap();
+
+        let err = unsafe { PieceTable::map_file(&path) }.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "invalid UTF-8 at byte 6");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,39 +1,230 @@
-use crate::buffer::BufferType;
+use std::sync::OnceLock;
 
-#[derive(Debug)]
+use crate::buffer::{BufferType, Buffers};
+#[cfg(feature = "lines")]
+use crate::line;
+use crate::str_utils;
+use crate::tree::{Metrics, Size};
+
+#[derive(Debug, Clone)]
 pub(crate) struct Piece {
     /// Which [`Buffer`] is this piece referencing.
     pub(crate) buffer: BufferType,
     /// Start index in the buffer.
     pub(crate) start: usize,
 
-    /// The index of the first line break index in the buffer's `line_breaks`.
+    pub(crate) len_bytes: usize,
+    pub(crate) len_chars: usize,
+
+    /// The rest of the lengths of the piece, which are scanned from its text
+    /// only the first time they are needed (see [`Piece::scanned`]).
+    pub(crate) scanned: OnceLock<Scanned>,
+}
+
+/// The lengths of a piece which are found by scanning its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Scanned {
+    pub(crate) len_utf16: usize,
+    /// The index of the first line break in the buffer's line breaks (see
+    /// [`Buffers::line_breaks`]).
     #[cfg(feature = "lines")]
     pub(crate) first_line_break: Option<usize>,
+    /// The amount of line breaks that start inside the piece.
+    #[cfg(feature = "lines")]
+    pub(crate) len_line_breaks: usize,
+    /// The amount of LSP line breaks (see [`str_utils::lsp_line_breaks`])
+    /// that start inside the piece.
+    #[cfg(feature = "lines")]
+    pub(crate) len_lsp_line_breaks: usize,
+}
 
-    pub(crate) len_bytes: usize,
-    pub(crate) len_chars: usize,
+impl Scanned {
+    /// Scan the text in `byte_range` of `buffer`.
+    fn new(
+        buffers: &Buffers,
+        buffer: BufferType,
+        byte_range: std::ops::Range<usize>,
+    ) -> Self {
+        let text = buffers.text(buffer, byte_range.clone());
+        #[cfg(feature = "lines")]
+        let line_breaks = buffers.line_breaks_in(buffer, byte_range);
+
+        Self {
+            len_utf16: str_utils::count_utf16(text),
+            #[cfg(feature = "lines")]
+            first_line_break: (!line_breaks.is_empty())
+                .then_some(line_breaks.start),
+            #[cfg(feature = "lines")]
+            len_line_breaks: line_breaks.len(),
+            #[cfg(feature = "lines")]
+            len_lsp_line_breaks: str_utils::lsp_line_breaks(text).count(),
+        }
+    }
 }
 
 impl Piece {
+    /// The maximum length of a piece, in bytes. Longer texts are split into
+    /// several pieces, so that scanning a single piece (e.g., to convert a char
+    /// index to a byte index inside it) is bounded.
+    pub(crate) const MAX_LEN_BYTES: usize = 1 << 14;
+
+    /// Create a piece referencing `byte_range` in `buffer`.
+    ///
+    /// Runs in `O(N)` where `N` is the length of the range.
+    pub(crate) fn new(
+        buffers: &Buffers,
+        buffer: BufferType,
+        byte_range: std::ops::Range<usize>,
+    ) -> Self {
+        let text = buffers.text(buffer, byte_range.clone());
+        let scanned = Scanned::new(buffers, buffer, byte_range.clone());
+
+        Self {
+            buffer,
+            start: byte_range.start,
+            len_bytes: text.len(),
+            len_chars: str_utils::count_chars(text),
+            scanned: OnceLock::from(scanned),
+        }
+    }
+
+    /// Create a piece referencing `byte_range` in `buffer`, which has
+    /// `len_chars` chars, without scanning it.
+    pub(crate) fn unscanned(
+        buffer: BufferType,
+        byte_range: std::ops::Range<usize>,
+        len_chars: usize,
+    ) -> Self {
+        Self {
+            buffer,
+            start: byte_range.start,
+            len_bytes: byte_range.len(),
+            len_chars,
+            scanned: OnceLock::new(),
+        }
+    }
+
+    /// Create a piece referencing the chars in `char_range`, relative to the
+    /// start of this piece. It is scanned only if this piece was.
+    pub(crate) fn slice(
+        &self,
+        buffers: &Buffers,
+        char_range: std::ops::Range<usize>,
+    ) -> Self {
+        let text = self.text(buffers);
+        let start = str_utils::char_to_byte(text, char_range.start);
+        let end =
+            start + str_utils::char_to_byte(&text[start..], char_range.len());
+
+        let byte_range = self.start + start..self.start + end;
+        let mut piece =
+            Self::unscanned(self.buffer, byte_range.clone(), char_range.len());
+        if self.scanned.get().is_some() {
+            piece.scanned =
+                OnceLock::from(Scanned::new(buffers, self.buffer, byte_range));
+        }
+        piece
+    }
+
+    /// Forget the scanned lengths of the piece, after the line breaks of its
+    /// buffer were rescanned (see [`Buffers::rescan`]), so that they are
+    /// scanned again the next time they are needed.
+    #[cfg(feature = "lines")]
+    pub(crate) fn forget_scanned(&mut self) {
+        self.scanned = OnceLock::new();
+    }
+
+    /// The lengths of the piece which are found by scanning its text, which
+    /// is scanned now if it wasn't yet.
+    pub(crate) fn scanned(&self, buffers: &Buffers) -> &Scanned {
+        self.scanned.get_or_init(|| {
+            Scanned::new(buffers, self.buffer, self.byte_range())
+        })
+    }
+
+    /// The text this piece is referencing.
+    pub(crate) fn text<'a>(&self, buffers: &'a Buffers) -> &'a str {
+        buffers.text(self.buffer, self.byte_range())
+    }
+
+    /// The line breaks that start inside this piece.
+    #[cfg(feature = "lines")]
+    pub(crate) fn line_breaks<'a>(
+        &self,
+        buffers: &'a Buffers,
+    ) -> &'a [(usize, line::Break)] {
+        let scanned = self.scanned(buffers);
+        match scanned.first_line_break {
+            Some(first) => &buffers.line_breaks(self.buffer, self.start)
+                [first..first + scanned.len_line_breaks],
+            None => &[],
+        }
+    }
+
     pub(crate) fn byte_range(&self) -> std::ops::Range<usize> {
         self.start..self.start + self.len_bytes
     }
+
+    /// The lengths of the piece which are always known.
+    pub(crate) fn size(&self) -> Size {
+        Size { bytes: self.len_bytes, chars: self.len_chars }
+    }
+
+    /// All the lengths of the piece, scanning it if it wasn't yet.
+    pub(crate) fn metrics(&self, buffers: &Buffers) -> Metrics {
+        self.metrics_with(self.scanned(buffers))
+    }
+
+    /// All the lengths of the piece, if it was already scanned.
+    pub(crate) fn scanned_metrics(&self) -> Option<Metrics> {
+        self.scanned.get().map(|scanned| self.metrics_with(scanned))
+    }
+
+    fn metrics_with(&self, scanned: &Scanned) -> Metrics {
+        Metrics {
+            bytes: self.len_bytes,
+            chars: self.len_chars,
+            utf16: scanned.len_utf16,
+            #[cfg(feature = "lines")]
+            line_breaks: scanned.len_line_breaks,
+            #[cfg(feature = "lines")]
+            lsp_line_breaks: scanned.len_lsp_line_breaks,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{PieceTable, line};
+    #[cfg(feature = "lines")]
+    use crate::{PieceTable, buffer::BufferType, line};
 
     #[test]
     #[cfg(feature = "lines")]
     fn first_line_break() {
         let pt = PieceTable::new("012\r\n567");
-        let idx = pt.pieces[0].first_line_break.unwrap();
-        let &(lb_idx, lb_type) = &pt.buffers.original.line_breaks[idx];
+        let piece = pt.tree.piece(0);
+        let idx = piece.scanned(&pt.buffers).first_line_break.unwrap();
+        let line_breaks = pt.buffers.line_breaks(BufferType::Original, 0);
+        let &(lb_idx, lb_type) = &line_breaks[idx];
 
         assert_eq!(lb_type, line::Break::Crlf);
         assert_eq!(pt.buffers.original.content.as_bytes()[lb_idx], b'\r');
         assert_eq!(pt.buffers.original.content.as_bytes()[lb_idx + 1], b'\n');
     }
+
+    #[test]
+    #[cfg(feature = "lines")]
+    fn scanned_on_demand() {
+        let text = "lorem ipsum\n".repeat(10000);
+        let pt = PieceTable::new(&text);
+        assert!(pt.tree.len() > 4);
+        assert!(pt.tree.iter().all(|p| p.scanned_metrics().is_none()));
+
+        assert_eq!(pt.line(3).to_string(), "lorem ipsum");
+        let scanned = pt.tree.iter().filter(|p| p.scanned_metrics().is_some());
+        assert_eq!(scanned.count(), 1);
+
+        assert_eq!(pt.len_lines(), 10001);
+        assert!(pt.tree.iter().all(|p| p.scanned_metrics().is_some()));
+    }
 }
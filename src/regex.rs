@@ -0,0 +1,570 @@
+//! Searching for regular expressions in a table.
+//!
+//! The bytes of the table are streamed through the lazy DFAs of
+//! [`regex_automata::hybrid`] directly, so the text is never copied. Like in
+//! `regex_automata`, finding a match takes two passes: a forward search finds
+//! where it ends, and then a reverse search from its end finds where it starts
+//! (and the other way around when searching backward).
+
+use std::ops::Range;
+
+use regex_automata::Anchored;
+use regex_automata::hybrid::dfa::{Cache, DFA};
+use regex_automata::hybrid::{self, LazyStateID};
+use regex_automata::util::start;
+
+use crate::PieceTable;
+use crate::error::{self, Error};
+
+/// The error returned when a [`Regex`] cannot be built.
+pub use regex_automata::hybrid::BuildError as RegexBuildError;
+
+/// A compiled regular expression, for searching tables (see
+/// [`PieceTable::find_regex`]).
+///
+/// The syntax is the syntax of the `regex` crate, except for Unicode word
+/// boundaries, which are not supported (ASCII word boundaries, `(?-u:\b)`,
+/// are).
+#[derive(Debug)]
+pub struct Regex {
+    inner: hybrid::regex::Regex,
+}
+
+impl Regex {
+    /// Compile `pattern`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RegexBuildError`] if the pattern is invalid, or if it is
+    /// too big to compile.
+    pub fn new(pattern: &str) -> Result<Self, Box<RegexBuildError>> {
+        Ok(Self {
+            inner: hybrid::regex::Regex::new(pattern).map_err(Box::new)?,
+        })
+    }
+
+    /// Create the caches of the lazy DFAs of the regex, which every search
+    /// fills as it goes.
+    fn create_caches(&self) -> (Cache, Cache) {
+        (
+            self.inner.forward().create_cache(),
+            self.inner.reverse().create_cache(),
+        )
+    }
+}
+
+/// A match of a [`Regex`] in a table.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RegexMatch {
+    pub char_range: Range<usize>,
+    pub byte_range: Range<usize>,
+}
+
+/// A searcher of a regex in a table, which keeps the caches of its DFAs
+/// between searches.
+#[derive(Debug, Clone)]
+struct Searcher<'a, 'r> {
+    table: &'a PieceTable<'a>,
+    regex: &'r Regex,
+    forward_cache: Cache,
+    reverse_cache: Cache,
+}
+
+impl<'a, 'r> Searcher<'a, 'r> {
+    fn new(table: &'a PieceTable<'a>, regex: &'r Regex) -> Self {
+        let (forward_cache, reverse_cache) = regex.create_caches();
+        Self { table, regex, forward_cache, reverse_cache }
+    }
+
+    /// Find the first match which starts at (or after) `byte_idx`.
+    fn find(&mut self, byte_idx: usize) -> Option<Range<usize>> {
+        let len = self.table.len_bytes();
+        let forward = self.regex.inner.forward();
+        let end = scan_forward(
+            self.table,
+            forward,
+            &mut self.forward_cache,
+            byte_idx..len,
+            Anchored::No,
+        )?;
+        let reverse = self.regex.inner.reverse();
+        let start = scan_reverse(
+            self.table,
+            reverse,
+            &mut self.reverse_cache,
+            byte_idx..end,
+            Anchored::Yes,
+            false,
+        )?;
+        Some(start..end)
+    }
+
+    /// Find the match which starts last among the matches which end at (or
+    /// before) `byte_idx`. If `before` is true, the match must also start
+    /// before `byte_idx`.
+    fn rfind(&mut self, byte_idx: usize, before: bool) -> Option<Range<usize>> {
+        let reverse = self.regex.inner.reverse();
+        let start = scan_reverse(
+            self.table,
+            reverse,
+            &mut self.reverse_cache,
+            0..byte_idx,
+            Anchored::No,
+            before,
+        )?;
+        let forward = self.regex.inner.forward();
+        let end = scan_forward(
+            self.table,
+            forward,
+            &mut self.forward_cache,
+            start..byte_idx,
+            Anchored::Yes,
+        )?;
+        Some(start..end)
+    }
+
+    /// Whether `byte_idx` (which must be in bounds) is on a char boundary.
+    fn is_char_boundary(&self, byte_idx: usize) -> bool {
+        self.table.bytes_at(byte_idx).next().is_none_or(is_char_start)
+    }
+
+    fn to_match(&self, byte_range: Range<usize>) -> RegexMatch {
+        RegexMatch {
+            char_range: self.table.byte_to_char(byte_range.start)
+                ..self.table.byte_to_char(byte_range.end),
+            byte_range,
+        }
+    }
+}
+
+/// Whether `byte` is the first byte of a UTF-8 encoded char.
+const fn is_char_start(byte: u8) -> bool {
+    (byte as i8) >= -0x40
+}
+
+/// Run `dfa` forward over the bytes of `table` in `byte_range`, and return
+/// the end of the last match it found before it stopped matching.
+///
+/// The bytes around the range are the context of look-around assertions, and
+/// matches are reported one byte late, so the DFA also reads the byte after
+/// the range (or the end of the input).
+fn scan_forward(
+    table: &PieceTable,
+    dfa: &DFA,
+    cache: &mut Cache,
+    byte_range: Range<usize>,
+    anchored: Anchored,
+) -> Option<usize> {
+    let mut bytes = table.bytes_at(byte_range.start);
+    let look_behind = bytes.clone().prev();
+    let config =
+        start::Config::new().anchored(anchored).look_behind(look_behind);
+    let mut state = infallible(dfa.start_state(cache, &config));
+    let mut last = None;
+
+    for byte_idx in byte_range.clone() {
+        let Some(byte) = bytes.next() else { unreachable!("in bounds") };
+        state = infallible(dfa.next_state(cache, state, byte));
+        if state.is_match() {
+            last = Some(byte_idx);
+        } else if state.is_dead() {
+            return last;
+        }
+    }
+
+    state = infallible(match bytes.next() {
+        Some(byte) => dfa.next_state(cache, state, byte),
+        None => dfa.next_eoi_state(cache, state),
+    });
+    if state.is_match() {
+        last = Some(byte_range.end);
+    }
+    last
+}
+
+/// Run `dfa` (which must be a reverse DFA) backward over the bytes of `table`
+/// in `byte_range`, and return the start of the first match it found if the
+/// search is unanchored, or of the last one (i.e., the longest) if it is
+/// anchored (see [`scan_forward`]). If `skip_end` is true, a match which
+/// starts at the end of the range is ignored.
+fn scan_reverse(
+    table: &PieceTable,
+    dfa: &DFA,
+    cache: &mut Cache,
+    byte_range: Range<usize>,
+    anchored: Anchored,
+    skip_end: bool,
+) -> Option<usize> {
+    let mut bytes = table.bytes_at(byte_range.end);
+    let look_behind = bytes.clone().next();
+    let config =
+        start::Config::new().anchored(anchored).look_behind(look_behind);
+    let mut state = infallible(dfa.start_state(cache, &config));
+    let mut last = None;
+
+    for byte_idx in byte_range.clone().rev() {
+        let Some(byte) = bytes.prev() else { unreachable!("in bounds") };
+        state = infallible(dfa.next_state(cache, state, byte));
+        if state.is_match() && !(skip_end && byte_idx + 1 == byte_range.end) {
+            last = Some(byte_idx + 1);
+            if anchored == Anchored::No {
+                return last;
+            }
+        } else if state.is_dead() {
+            return last;
+        }
+    }
+
+    state = infallible(match bytes.prev() {
+        Some(byte) => dfa.next_state(cache, state, byte),
+        None => dfa.next_eoi_state(cache, state),
+    });
+    if state.is_match() && !(skip_end && byte_range.is_empty()) {
+        last = Some(byte_range.start);
+    }
+    last
+}
+
+/// Unwrap the result of a transition of a lazy DFA, which can only fail if
+/// the DFA has quit bytes (e.g., for Unicode word boundaries), or if its
+/// cache is configured to give up, neither of which [`Regex`] does.
+fn infallible<E: std::fmt::Debug>(
+    result: Result<LazyStateID, E>,
+) -> LazyStateID {
+    match result {
+        Ok(state) => state,
+        Err(err) => unreachable!("the lazy DFA failed: {err:?}"),
+    }
+}
+
+/// An iterator over the non-overlapping matches of a regex in a table, from a
+/// given position forward (see [`PieceTable::find_regex_iter`]).
+#[derive(Debug, Clone)]
+pub struct RegexMatches<'a, 'r> {
+    searcher: Searcher<'a, 'r>,
+    /// The byte index to search from, or [`None`] if the search is done.
+    byte_idx: Option<usize>,
+    /// The end of the last match.
+    last_end: Option<usize>,
+}
+
+impl Iterator for RegexMatches<'_, '_> {
+    type Item = RegexMatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let found = self.byte_idx.and_then(|i| self.searcher.find(i));
+            let Some(range) = found else {
+                self.byte_idx = None;
+                return None;
+            };
+
+            // Like the `regex` crate, skip empty matches right after the
+            // previous match, and empty matches inside a char.
+            if range.is_empty()
+                && (self.last_end == Some(range.end)
+                    || !self.searcher.is_char_boundary(range.end))
+            {
+                let mut bytes = self.searcher.table.bytes_at(range.end);
+                self.byte_idx = bytes.next().map(|_| {
+                    range.end
+                        + 1
+                        + bytes.take_while(|&b| !is_char_start(b)).count()
+                });
+                continue;
+            }
+
+            self.byte_idx = Some(range.end);
+            self.last_end = Some(range.end);
+            return Some(self.searcher.to_match(range));
+        }
+    }
+}
+
+/// An iterator over the non-overlapping matches of a regex in a table, from a
+/// given position backward (see [`PieceTable::rfind_regex_iter`]).
+#[derive(Debug, Clone)]
+pub struct RegexRMatches<'a, 'r> {
+    searcher: Searcher<'a, 'r>,
+    /// The byte index to search back from, or [`None`] if the search is done.
+    byte_idx: Option<usize>,
+    /// The start of the last match.
+    last_start: Option<usize>,
+}
+
+impl Iterator for RegexRMatches<'_, '_> {
+    type Item = RegexMatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let byte_idx = self.byte_idx?;
+            // Skip empty matches right before the previous match, by looking
+            // for matches which start before it.
+            let before = self.last_start == Some(byte_idx);
+            let Some(range) = self.searcher.rfind(byte_idx, before) else {
+                self.byte_idx = None;
+                return None;
+            };
+
+            // Skip empty matches inside a char.
+            if range.is_empty() && !self.searcher.is_char_boundary(range.start)
+            {
+                let mut bytes = self.searcher.table.bytes_at(range.start);
+                let mut start = range.start;
+                while let Some(byte) = bytes.prev() {
+                    start -= 1;
+                    if is_char_start(byte) {
+                        break;
+                    }
+                }
+                self.byte_idx = Some(start);
+                continue;
+            }
+
+            self.byte_idx = Some(range.start);
+            self.last_start = Some(range.start);
+            return Some(self.searcher.to_match(range));
+        }
+    }
+}
+
+impl PieceTable<'_> {
+    /// Returns the first match of `regex` which starts at (or after)
+    /// `char_idx`, or [`None`] if there is none. The text before `char_idx`
+    /// is still the context of look-around assertions (e.g., `^` does not
+    /// match at `char_idx` unless it is the start of the table).
+    ///
+    /// Runs in `O(N log P)` where `N` is the length of the searched text and
+    /// `P` is the amount of pieces, once the lazy DFAs of the search are built.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{PieceTable, Regex};
+    /// let mut pt = PieceTable::new("let x = 1;\nlet y = 23;");
+    /// pt.insert(19, "4");
+    /// let regex = Regex::new(r"[0-9]+").unwrap();
+    ///
+    /// let found = pt.find_regex(&regex, 9).unwrap();
+    /// assert_eq!(found.char_range, 19..22);
+    /// assert_eq!(pt.slice(found.char_range).to_string(), "423");
+    /// ```
+    pub fn find_regex(
+        &self,
+        regex: &Regex,
+        char_idx: usize,
+    ) -> Option<RegexMatch> {
+        self.find_regex_iter(regex, char_idx).next()
+    }
+
+    /// Returns the last match of `regex` which ends at (or before) `char_idx`,
+    /// or [`None`] if there is none (see [`PieceTable::rfind_regex_iter`]).
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{PieceTable, Regex};
+    /// let pt = PieceTable::new("fn main() {}\nfn helper() {}");
+    /// let regex = Regex::new(r"(?m)^fn \w+").unwrap();
+    ///
+    /// let found = pt.rfind_regex(&regex, pt.len_chars()).unwrap();
+    /// assert_eq!(found.char_range, 13..22);
+    ///
+    /// let found = pt.rfind_regex(&regex, 12).unwrap();
+    /// assert_eq!(pt.slice(found.char_range).to_string(), "fn main");
+    /// ```
+    pub fn rfind_regex(
+        &self,
+        regex: &Regex,
+        char_idx: usize,
+    ) -> Option<RegexMatch> {
+        self.rfind_regex_iter(regex, char_idx).next()
+    }
+
+    /// Returns an iterator over the non-overlapping matches of `regex` which
+    /// start at (or after) `char_idx`, like [`PieceTable::find_regex`].
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{PieceTable, Regex};
+    /// let pt = PieceTable::new("a1 b22 c333");
+    /// let regex = Regex::new(r"[a-z][0-9]+").unwrap();
+    ///
+    /// let found: Vec<_> =
+    ///     pt.find_regex_iter(&regex, 1).map(|m| m.char_range).collect();
+    /// assert_eq!(found, [3..6, 7..11]);
+    /// ```
+    pub fn find_regex_iter<'r>(
+        &self,
+        regex: &'r Regex,
+        char_idx: usize,
+    ) -> RegexMatches<'_, 'r> {
+        error::unwrap(self.try_find_regex_iter(regex, char_idx))
+    }
+
+    /// Like [`PieceTable::find_regex_iter`], but returns an [`Error`] instead
+    /// of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    pub fn try_find_regex_iter<'r>(
+        &self,
+        regex: &'r Regex,
+        char_idx: usize,
+    ) -> Result<RegexMatches<'_, 'r>, Error> {
+        Ok(RegexMatches {
+            byte_idx: Some(self.try_char_to_byte(char_idx)?),
+            searcher: Searcher::new(self, regex),
+            last_end: None,
+        })
+    }
+
+    /// Returns an iterator over the non-overlapping matches of `regex` which
+    /// end at (or before) `char_idx`, from the last one backward.
+    ///
+    /// The matches are found by searching backward, so every match is the one
+    /// which starts last (among the matches that end before the previous
+    /// one). Thus they might be different from the matches found by searching
+    /// forward, like in [`str::rmatch_indices`].
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the size of the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{PieceTable, Regex};
+    /// let pt = PieceTable::new("aaa");
+    /// let regex = Regex::new(r"aa").unwrap();
+    ///
+    /// let found: Vec<_> =
+    ///     pt.rfind_regex_iter(&regex, 3).map(|m| m.char_range).collect();
+    /// assert_eq!(found, [1..3]);
+    /// ```
+    pub fn rfind_regex_iter<'r>(
+        &self,
+        regex: &'r Regex,
+        char_idx: usize,
+    ) -> RegexRMatches<'_, 'r> {
+        error::unwrap(self.try_rfind_regex_iter(regex, char_idx))
+    }
+
+    /// Like [`PieceTable::rfind_regex_iter`], but returns an [`Error`] instead
+    /// of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the size of
+    /// the contents.
+    pub fn try_rfind_regex_iter<'r>(
+        &self,
+        regex: &'r Regex,
+        char_idx: usize,
+    ) -> Result<RegexRMatches<'_, 'r>, Error> {
+        Ok(RegexRMatches {
+            byte_idx: Some(self.try_char_to_byte(char_idx)?),
+            searcher: Searcher::new(self, regex),
+            last_start: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table with a piece for (almost) every char.
+    fn table(text: &str) -> PieceTable<'static> {
+        let mut pt = PieceTable::new("").into_owned();
+        for ch in text.chars().rev() {
+            pt.insert(0, ch.encode_utf8(&mut [0; 4]));
+        }
+        pt
+    }
+
+    fn char_ranges(
+        matches: impl Iterator<Item = RegexMatch>,
+    ) -> Vec<Range<usize>> {
+        matches.map(|m| m.char_range).collect()
+    }
+
+    #[test]
+    fn forward() {
+        let text = "fn main() {\n    let é = \"🦀 crab\";\n}\r\nabab aab\n";
+        let pt = table(text);
+
+        for pattern in [
+            r"\w+",
+            r"(?m)^\s*\S",
+            r"(?m)$",
+            r"a*",
+            r"",
+            r"(?-u:\b)",
+            r"ab|aab",
+            r"\p{Emoji}",
+            r"[^a]",
+            r"(?s).",
+            r"\n\z",
+        ] {
+            let regex = Regex::new(pattern).unwrap();
+            let mut cache = regex.inner.create_cache();
+
+            for char_idx in [0, 1, 5, 17, 18, text.chars().count()] {
+                let byte_idx = pt.char_to_byte(char_idx);
+                let input = regex_automata::Input::new(text).range(byte_idx..);
+                let expected: Vec<_> = regex
+                    .inner
+                    .find_iter(&mut cache, input)
+                    .map(|m| m.range())
+                    .collect();
+                let found: Vec<_> = pt
+                    .find_regex_iter(&regex, char_idx)
+                    .map(|m| m.byte_range)
+                    .collect();
+                assert_eq!(found, expected, "{pattern:?} at {char_idx}");
+            }
+        }
+    }
+
+    #[test]
+    fn backward() {
+        let pt = table("a1 b22 c333");
+        let regex = Regex::new(r"[a-z][0-9]+").unwrap();
+        let found = char_ranges(pt.rfind_regex_iter(&regex, 11));
+        assert_eq!(found, [7..11, 3..6, 0..2]);
+        let found = char_ranges(pt.rfind_regex_iter(&regex, 9));
+        assert_eq!(found, [7..9, 3..6, 0..2]);
+
+        let pt = table("é🦀");
+        let regex = Regex::new(r"").unwrap();
+        let found: Vec<_> =
+            pt.rfind_regex_iter(&regex, 2).map(|m| m.byte_range).collect();
+        assert_eq!(found, [6..6, 2..2, 0..0]);
+
+        let pt = table("baaa");
+        let regex = Regex::new(r"a*").unwrap();
+        let found = char_ranges(pt.rfind_regex_iter(&regex, 4));
+        assert_eq!(found, [4..4, 3..4, 2..3, 1..2, 0..0]);
+
+        let pt = table("one\ntwo\nthree");
+        let regex = Regex::new(r"(?m)^\w").unwrap();
+        let found = char_ranges(pt.rfind_regex_iter(&regex, 13));
+        assert_eq!(found, [8..9, 4..5, 0..1]);
+    }
+}
@@ -0,0 +1,390 @@
+//! Replacing all the matches of a needle as a single edit.
+
+use std::ops::Range;
+
+use crate::PieceTable;
+use crate::error::{self, Error};
+use crate::history::Change;
+use crate::piece::Piece;
+
+/// A cursor over the chars of a sequence of pieces, which copies (or skips)
+/// them piece by piece.
+struct PiecesCursor<'p> {
+    pieces: &'p [Piece],
+    /// The index of the piece the cursor is in.
+    idx: usize,
+    /// The char index of the cursor, relative to its piece.
+    offset: usize,
+}
+
+impl PiecesCursor<'_> {
+    /// Move the cursor `len_chars` chars forward, pushing the pieces (or the
+    /// parts of pieces) it passes over to `out`, if there is one.
+    fn advance(
+        &mut self,
+        table: &PieceTable,
+        mut len_chars: usize,
+        mut out: Option<&mut Vec<Piece>>,
+    ) {
+        while len_chars > 0 {
+            let piece = &self.pieces[self.idx];
+            let len = len_chars.min(piece.len_chars - self.offset);
+            if let Some(out) = out.as_deref_mut() {
+                out.push(if len == piece.len_chars {
+                    piece.clone()
+                } else {
+                    piece.slice(&table.buffers, self.offset..self.offset + len)
+                });
+            }
+
+            len_chars -= len;
+            self.offset += len;
+            if self.offset == piece.len_chars {
+                self.idx += 1;
+                self.offset = 0;
+            }
+        }
+    }
+}
+
+impl PieceTable<'_> {
+    /// Replace every non-overlapping match of `needle` (see
+    /// [`PieceTable::find_iter`]) with `replacement`, as a single step in the
+    /// history of the table. Returns the char ranges of the replacements in
+    /// the new contents.
+    ///
+    /// The replacement is appended to the table only once, and the pieces of
+    /// the table are rebuilt in a single pass, so this runs in `O(N + M log P)`
+    /// where `N` is the length of the table, `M` is the amount of matches and
+    /// `P` is the amount of pieces.
+    ///
+    /// Matches which split a CRLF sequence are rejected if the table's
+    /// [`CrlfPolicy`] is [`CrlfPolicy::Reject`], and are replaced as they are
+    /// otherwise, as snapping them would replace more than the match.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if a match splits a CRLF sequence (and the table's
+    /// [`CrlfPolicy`] rejects it).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("one fish, two fish");
+    /// pt.insert(10, "red fish, ");
+    /// assert_eq!(pt.replace_all("fish", "🐟"), [4..5, 11..12, 18..19]);
+    /// assert_eq!(pt.text(), "one 🐟, red 🐟, two 🐟");
+    ///
+    /// pt.undo();
+    /// assert_eq!(pt.text(), "one fish, red fish, two fish");
+    /// ```
+    ///
+    /// [`CrlfPolicy`]: crate::CrlfPolicy
+    /// [`CrlfPolicy::Reject`]: crate::CrlfPolicy::Reject
+    pub fn replace_all(
+        &mut self,
+        needle: &str,
+        replacement: &str,
+    ) -> Vec<Range<usize>> {
+        error::unwrap(self.try_replace_all(needle, replacement))
+    }
+
+    /// Like [`PieceTable::replace_all`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CrlfSplit`] if the start or the end of a match are
+    /// between the CR and the LF of a CRLF sequence (and the table's
+    /// [`CrlfPolicy`] rejects it).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::{CrlfPolicy, Error, PieceTable};
+    /// let mut pt = PieceTable::new("a\r\nb\nc");
+    /// assert_eq!(
+    ///     pt.try_replace_all("\n", "\r\n"),
+    ///     Err(Error::CrlfSplit { char_idx: 2 })
+    /// );
+    /// assert_eq!(pt.text(), "a\r\nb\nc");
+    ///
+    /// pt.set_crlf_policy(CrlfPolicy::Split);
+    /// assert_eq!(pt.try_replace_all("\n", "\r\n"), Ok(vec![2..4, 5..7]));
+    /// assert_eq!(pt.text(), "a\r\r\nb\r\nc");
+    /// ```
+    ///
+    /// [`CrlfPolicy`]: crate::CrlfPolicy
+    pub fn try_replace_all(
+        &mut self,
+        needle: &str,
+        replacement: &str,
+    ) -> Result<Vec<Range<usize>>, Error> {
+        let matches: Vec<_> = self.find_iter(needle).collect();
+        if matches.is_empty() {
+            return Ok(vec![]);
+        }
+        self.check_crlf_splits(&matches)?;
+
+        #[cfg(feature = "lines")]
+        for m in matches.iter().rev() {
+            self.record_replace(m.start, m.end, replacement);
+        }
+        let pieces = self.append(replacement);
+        Ok(self.replace_ranges(&matches, std::iter::repeat(pieces)))
+    }
+
+    /// Replace every non-overlapping match of `needle` (see
+    /// [`PieceTable::find_iter`]) with the text `replace` returns for its char
+    /// range, as a single step in the history of the table. Returns the char
+    /// ranges of the replacements in the new contents.
+    ///
+    /// `replace` is called for the matches in order, before the table is
+    /// changed. Like [`PieceTable::replace_all`], this runs in
+    /// `O(N + M log P)`, besides appending the replacements, and rejects
+    /// matches which split a CRLF sequence depending on the table's
+    /// [`CrlfPolicy`].
+    ///
+    /// # Panics
+    ///
+    /// Will panic if a match splits a CRLF sequence (and the table's
+    /// [`CrlfPolicy`] rejects it).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("[ ] eggs\n[ ] milk\n[ ] bread");
+    /// let mut n = 0;
+    /// pt.replace_with("[ ]", |_range| {
+    ///     n += 1;
+    ///     format!("{n}.")
+    /// });
+    /// assert_eq!(pt.text(), "1. eggs\n2. milk\n3. bread");
+    /// ```
+    ///
+    /// [`CrlfPolicy`]: crate::CrlfPolicy
+    pub fn replace_with<F, T>(
+        &mut self,
+        needle: &str,
+        replace: F,
+    ) -> Vec<Range<usize>>
+    where
+        F: FnMut(Range<usize>) -> T,
+        T: AsRef<str>,
+    {
+        error::unwrap(self.try_replace_with(needle, replace))
+    }
+
+    /// Like [`PieceTable::replace_with`], but returns an [`Error`] instead of
+    /// panicking (in which case the table is unchanged, and `replace` is not
+    /// called).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CrlfSplit`] if the start or the end of a match are
+    /// between the CR and the LF of a CRLF sequence (and the table's
+    /// [`CrlfPolicy`] rejects it).
+    ///
+    /// [`CrlfPolicy`]: crate::CrlfPolicy
+    pub fn try_replace_with<F, T>(
+        &mut self,
+        needle: &str,
+        replace: F,
+    ) -> Result<Vec<Range<usize>>, Error>
+    where
+        F: FnMut(Range<usize>) -> T,
+        T: AsRef<str>,
+    {
+        let matches: Vec<_> = self.find_iter(needle).collect();
+        if matches.is_empty() {
+            return Ok(vec![]);
+        }
+        self.check_crlf_splits(&matches)?;
+        let replacements: Vec<_> =
+            matches.iter().cloned().map(replace).collect();
+
+        #[cfg(feature = "lines")]
+        for (m, replacement) in matches.iter().zip(&replacements).rev() {
+            self.record_replace(m.start, m.end, replacement.as_ref());
+        }
+        let pieces: Vec<_> = replacements
+            .iter()
+            .map(|replacement| self.append(replacement.as_ref()))
+            .collect();
+        Ok(self.replace_ranges(&matches, pieces.into_iter()))
+    }
+
+    /// Replace the chars in every range of `ranges` (which must be sorted,
+    /// non-overlapping and in bounds) with the matching pieces of
+    /// `replacements`, as a single change. Returns the char ranges of the
+    /// replacements in the new contents.
+    pub(crate) fn replace_ranges(
+        &mut self,
+        ranges: &[Range<usize>],
+        replacements: impl Iterator<Item = Vec<Piece>>,
+    ) -> Vec<Range<usize>> {
+        #[cfg(feature = "contiguous-inserts")]
+        {
+            self.last_insert = None;
+        }
+
+        // The chars around the ranges are rebuilt too, so that a replacement
+        // which ends up next to a CR or an LF can be joined with it (see
+        // `PieceTable::fit_crlf_pieces`).
+        let splits_crlf =
+            |idx| matches!(self.chars_around(idx), (Some('\r'), Some('\n')));
+        let mut start = ranges[0].start.saturating_sub(1);
+        if splits_crlf(start) {
+            start -= 1;
+        }
+        let mut end = (ranges[ranges.len() - 1].end + 1).min(self.len_chars());
+        if splits_crlf(end) {
+            end += 1;
+        }
+
+        let removed =
+            if start < end { self.remove_range(start, end) } else { vec![] };
+        let mut cursor = PiecesCursor { pieces: &removed, idx: 0, offset: 0 };
+        let mut inserted = vec![];
+        let mut new_ranges = vec![];
+        // The char index of the cursor in the old contents, and in the new.
+        let (mut old_idx, mut new_idx) = (start, start);
+
+        for (range, pieces) in ranges.iter().zip(replacements) {
+            cursor.advance(self, range.start - old_idx, Some(&mut inserted));
+            cursor.advance(self, range.len(), None);
+            new_idx += range.start - old_idx;
+            old_idx = range.end;
+
+            let len_chars: usize = pieces.iter().map(|p| p.len_chars).sum();
+            new_ranges.push(new_idx..new_idx + len_chars);
+            new_idx += len_chars;
+            inserted.extend(pieces);
+        }
+        cursor.advance(self, end - old_idx, Some(&mut inserted));
+
+        #[cfg(feature = "lines")]
+        let inserted = self.fit_crlf_pieces(inserted);
+        if !inserted.is_empty() {
+            self.insert_pieces(start, inserted.clone());
+        }
+        self.history.record(Change { char_idx: start, removed, inserted });
+
+        new_ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CrlfPolicy;
+
+    #[test]
+    fn crlf_sequences() {
+        let mut pt = PieceTable::new("a\r\nb\rXc\r\n\r\nX\n");
+        pt.set_crlf_policy(CrlfPolicy::Split);
+        pt.insert(9, "X");
+        let initial = pt.text();
+
+        let replacements = [
+            ("X", "\n"),
+            ("\n", "\r"),
+            ("\r", "\r\n"),
+            ("\r\n", "\n\r"),
+            ("b", ""),
+            ("\n", ""),
+            ("", "\n"),
+            ("c", "\r\r"),
+        ];
+        for (needle, replacement) in replacements {
+            let text = pt.text();
+            let expected = text.replace(needle, replacement);
+            let ranges = pt.replace_all(needle, replacement);
+
+            assert_eq!(pt.text(), expected, "{needle:?} -> {replacement:?}");
+            assert_eq!(ranges.len(), text.matches(needle).count());
+            for range in ranges {
+                assert_eq!(pt.slice(range).to_string(), replacement);
+            }
+            assert_eq!(pt.validate(), Ok(()));
+        }
+
+        let replaced = pt.text();
+        for _ in replacements {
+            pt.undo();
+            assert_eq!(pt.validate(), Ok(()));
+        }
+        assert_eq!(pt.text(), initial);
+        while pt.redo().is_some() {
+            assert_eq!(pt.validate(), Ok(()));
+        }
+        assert_eq!(pt.text(), replaced);
+    }
+
+    #[test]
+    fn reject_crlf_splits() {
+        let mut pt = PieceTable::new("a\r\nb\r\n");
+        pt.insert(0, "\n");
+        assert_eq!(
+            pt.try_replace_all("\r", ""),
+            Err(Error::CrlfSplit { char_idx: 3 })
+        );
+        let mut called = false;
+        assert_eq!(
+            pt.try_replace_with("\nb", |_range| {
+                called = true;
+                ""
+            }),
+            Err(Error::CrlfSplit { char_idx: 3 })
+        );
+        assert!(!called);
+        assert_eq!(pt.text(), "\na\r\nb\r\n");
+        assert_eq!(pt.undo(), Some(vec![0]));
+        assert!(!pt.can_undo());
+
+        // Matches which contain whole sequences are not rejected.
+        assert_eq!(pt.replace_all("\r\n", "\n"), [1..2, 3..4]);
+        assert_eq!(pt.text(), "a\nb\n");
+    }
+
+    #[test]
+    fn replace_with() {
+        let mut pt = PieceTable::new("x");
+        for i in 0..200 {
+            pt.insert((i * 31) % pt.len_chars(), ["ab", "é", "x"][i % 3]);
+        }
+        let text = pt.text();
+
+        let mut calls = vec![];
+        let ranges = pt.replace_with("x", |range| {
+            calls.push(range);
+            "🦀".repeat(calls.len() % 3)
+        });
+
+        let mut n = 0;
+        let expected: String = text
+            .split('x')
+            .enumerate()
+            .map(|(i, part)| {
+                if i == 0 {
+                    return part.to_owned();
+                }
+                n += 1;
+                "🦀".repeat(n % 3) + part
+            })
+            .collect();
+        assert_eq!(pt.text(), expected);
+        assert_eq!(calls.len(), ranges.len());
+        assert_eq!(pt.validate(), Ok(()));
+
+        pt.undo();
+        assert_eq!(pt.text(), text);
+        assert_eq!(
+            calls,
+            pt.find_iter("x").collect::<Vec<_>>(),
+            "`replace` is called with the matches in the old contents"
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! Searching for substrings in a table.
+//!
+//! Every chunk of the table (see [`PieceTable::iter`]) is searched as a whole
+//! with [`memchr::memmem`], which skips ahead to the rarest byte of the needle.
+//! Only at the boundaries of chunks are bytes read one by one, by a
+//! Knuth–Morris–Pratt matcher which carries a partial match over to the next
+//! chunk. So matches which span several pieces are found without copying the
+//! text, in a single pass and using memory proportional to the needle alone.
+
+use std::ops::Range;
+
+use memchr::memmem;
+
+use crate::PieceTable;
+use crate::iter::Chunks;
+use crate::str_utils;
+
+/// A Knuth–Morris–Pratt matcher of a needle, read from its start or (for
+/// searching backward) from its end.
+#[derive(Debug, Clone)]
+struct Matcher<'n> {
+    needle: &'n [u8],
+    reversed: bool,
+    /// The length of the longest proper prefix of the first `i + 1` bytes
+    /// (in reading order) which is also their suffix, for every `i`.
+    failure: Vec<usize>,
+    /// The amount of needle bytes matched by the last bytes that were read.
+    matched: usize,
+}
+
+impl<'n> Matcher<'n> {
+    fn new(needle: &'n str, reversed: bool) -> Self {
+        let mut matcher = Self {
+            needle: needle.as_bytes(),
+            reversed,
+            failure: vec![0; needle.len()],
+            matched: 0,
+        };
+
+        let mut len = 0;
+        for i in 1..needle.len() {
+            while len > 0 && matcher.byte(i) != matcher.byte(len) {
+                len = matcher.failure[len - 1];
+            }
+            if matcher.byte(i) == matcher.byte(len) {
+                len += 1;
+            }
+            matcher.failure[i] = len;
+        }
+        matcher
+    }
+
+    /// The `i`-th byte of the needle, in reading order.
+    fn byte(&self, i: usize) -> u8 {
+        if self.reversed {
+            self.needle[self.needle.len() - 1 - i]
+        } else {
+            self.needle[i]
+        }
+    }
+
+    /// Read `byte`, and return whether the whole needle was matched. Matches
+    /// never overlap, so reading starts over after a match.
+    fn read(&mut self, byte: u8) -> bool {
+        while self.matched > 0 && self.byte(self.matched) != byte {
+            self.matched = self.failure[self.matched - 1];
+        }
+        if self.byte(self.matched) == byte {
+            self.matched += 1;
+        }
+        if self.matched == self.needle.len() {
+            self.matched = 0;
+            return true;
+        }
+        false
+    }
+}
+
+/// An iterator over the char ranges of the non-overlapping matches of a needle
+/// in a table, from its start (see [`PieceTable::find_iter`]).
+#[derive(Debug, Clone)]
+pub struct Matches<'a, 'n> {
+    chunks: Chunks<'a>,
+    /// The chunk being searched, which is searched from `byte_idx` on.
+    chunk: &'a str,
+    byte_idx: usize,
+    finder: memmem::Finder<'n>,
+    /// The part of the needle matched by the end of the previous chunks.
+    matcher: Matcher<'n>,
+    needle_chars: usize,
+    /// The amount of chars which start before `byte_idx` in the chunk.
+    char_idx: usize,
+    /// The amount of chars in the table, for matching an empty needle.
+    len_chars: usize,
+}
+
+impl Matches<'_, '_> {
+    /// Find the next match which ends in the current chunk, and return the
+    /// index of its end in the chunk.
+    fn find_in_chunk(&mut self) -> Option<usize> {
+        let bytes = self.chunk.as_bytes();
+        let mut idx = self.byte_idx;
+
+        // A partial match is carried over only to the start of a chunk, and
+        // is read byte by byte only as long as it starts before the chunk.
+        debug_assert!(self.matcher.matched == 0 || idx == 0);
+        while self.matcher.matched > idx && idx < bytes.len() {
+            idx += 1;
+            if self.matcher.read(bytes[idx - 1]) {
+                return Some(idx);
+            }
+        }
+        if self.matcher.matched > idx {
+            return None;
+        }
+
+        let start = idx - self.matcher.matched;
+        self.matcher.matched = 0;
+        if let Some(found) = self.finder.find(&bytes[start..]) {
+            return Some(start + found + self.finder.needle().len());
+        }
+
+        // Only the last bytes can start a match which ends in the next chunks.
+        let tail = bytes.len().saturating_sub(self.finder.needle().len() - 1);
+        for &byte in &bytes[tail.max(start)..] {
+            self.matcher.read(byte);
+        }
+        None
+    }
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Like `str::matches`, an empty needle matches at every char boundary.
+        if self.finder.needle().is_empty() {
+            if self.char_idx > self.len_chars {
+                return None;
+            }
+            self.char_idx += 1;
+            return Some(self.char_idx - 1..self.char_idx - 1);
+        }
+
+        loop {
+            if let Some(end) = self.find_in_chunk() {
+                self.char_idx +=
+                    str_utils::count_chars(&self.chunk[self.byte_idx..end]);
+                self.byte_idx = end;
+                return Some(self.char_idx - self.needle_chars..self.char_idx);
+            }
+
+            self.char_idx +=
+                str_utils::count_chars(&self.chunk[self.byte_idx..]);
+            self.chunk = self.chunks.next()?;
+            self.byte_idx = 0;
+        }
+    }
+}
+
+/// An iterator over the char ranges of the non-overlapping matches of a needle
+/// in a table, from its end (see [`PieceTable::rfind_iter`]).
+#[derive(Debug, Clone)]
+pub struct RMatches<'a, 'n> {
+    chunks: Chunks<'a>,
+    /// The chunk being searched, which is searched from `byte_idx` backward.
+    chunk: &'a str,
+    byte_idx: usize,
+    finder: memmem::FinderRev<'n>,
+    /// The part of the needle matched by the start of the next chunks.
+    matcher: Matcher<'n>,
+    needle_chars: usize,
+    /// The amount of chars which start before `byte_idx` in the chunk.
+    char_idx: usize,
+    /// Whether the empty needle was already matched at the start of the table.
+    exhausted: bool,
+}
+
+impl RMatches<'_, '_> {
+    /// Find the previous match which starts in the current chunk, and return
+    /// the index of its start in the chunk.
+    fn rfind_in_chunk(&mut self) -> Option<usize> {
+        let bytes = self.chunk.as_bytes();
+        let mut idx = self.byte_idx;
+
+        // Like in `Matches::find_in_chunk`, but mirrored.
+        debug_assert!(self.matcher.matched == 0 || idx == bytes.len());
+        while self.matcher.matched > bytes.len() - idx && idx > 0 {
+            idx -= 1;
+            if self.matcher.read(bytes[idx]) {
+                return Some(idx);
+            }
+        }
+        if self.matcher.matched > bytes.len() - idx {
+            return None;
+        }
+
+        let end = idx + self.matcher.matched;
+        self.matcher.matched = 0;
+        if let Some(found) = self.finder.rfind(&bytes[..end]) {
+            return Some(found);
+        }
+
+        let head = (self.finder.needle().len() - 1).min(end);
+        for &byte in bytes[..head].iter().rev() {
+            self.matcher.read(byte);
+        }
+        None
+    }
+}
+
+impl Iterator for RMatches<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finder.needle().is_empty() {
+            if self.exhausted {
+                return None;
+            }
+            let char_idx = self.char_idx;
+            match char_idx.checked_sub(1) {
+                Some(prev) => self.char_idx = prev,
+                None => self.exhausted = true,
+            }
+            return Some(char_idx..char_idx);
+        }
+
+        loop {
+            if let Some(start) = self.rfind_in_chunk() {
+                self.char_idx -=
+                    str_utils::count_chars(&self.chunk[start..self.byte_idx]);
+                self.byte_idx = start;
+                return Some(self.char_idx..self.char_idx + self.needle_chars);
+            }
+
+            self.char_idx -=
+                str_utils::count_chars(&self.chunk[..self.byte_idx]);
+            self.chunk = self.chunks.next_back()?;
+            self.byte_idx = self.chunk.len();
+        }
+    }
+}
+
+impl PieceTable<'_> {
+    /// Returns the char range of the first match of `needle` in the table, or
+    /// [`None`] if there is none.
+    ///
+    /// Runs in `O(N + M)` where `N` is the length of the table up to the end of
+    /// the match, and `M` is the length of `needle`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("🦀 crab, 🐙 octopus");
+    /// pt.insert(2, "c");
+    /// assert_eq!(pt.find("🐙"), Some(9..10));
+    /// assert_eq!(pt.find("ccrab"), Some(2..7));
+    /// assert_eq!(pt.find("fish"), None);
+    /// ```
+    pub fn find(&self, needle: &str) -> Option<Range<usize>> {
+        self.find_iter(needle).next()
+    }
+
+    /// Returns the char range of the last match of `needle` in the table, or
+    /// [`None`] if there is none.
+    ///
+    /// Runs in `O(N + M)` where `N` is the length of the table from the start
+    /// of the match, and `M` is the length of `needle`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("to be, or not to be");
+    /// assert_eq!(pt.rfind("be"), Some(17..19));
+    /// ```
+    pub fn rfind(&self, needle: &str) -> Option<Range<usize>> {
+        self.rfind_iter(needle).next()
+    }
+
+    /// Returns an iterator over the char ranges of the non-overlapping matches
+    /// of `needle` in the table, from its start.
+    ///
+    /// Iterating over all the matches runs in `O(N + M)` where `N` is the
+    /// length of the table, and `M` is the length of `needle`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("aaaa");
+    /// pt.insert(2, "ä");
+    /// assert_eq!(pt.find_iter("aa").collect::<Vec<_>>(), [0..2, 3..5]);
+    /// ```
+    pub fn find_iter<'n>(&self, needle: &'n str) -> Matches<'_, 'n> {
+        Matches {
+            chunks: self.iter(),
+            chunk: "",
+            byte_idx: 0,
+            finder: memmem::Finder::new(needle),
+            matcher: Matcher::new(needle, false),
+            needle_chars: needle.chars().count(),
+            char_idx: 0,
+            len_chars: self.len_chars(),
+        }
+    }
+
+    /// Returns an iterator over the char ranges of the non-overlapping matches
+    /// of `needle` in the table, from its end.
+    ///
+    /// Iterating over all the matches runs in `O(N + M)` where `N` is the
+    /// length of the table, and `M` is the length of `needle`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("aaaaa");
+    /// assert_eq!(pt.rfind_iter("aa").collect::<Vec<_>>(), [3..5, 1..3]);
+    /// ```
+    pub fn rfind_iter<'n>(&self, needle: &'n str) -> RMatches<'_, 'n> {
+        RMatches {
+            chunks: self.iter(),
+            chunk: "",
+            byte_idx: 0,
+            finder: memmem::FinderRev::new(needle),
+            matcher: Matcher::new(needle, true),
+            needle_chars: needle.chars().count(),
+            char_idx: self.len_chars(),
+            exhausted: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The char ranges of the matches of `needle` in `text`, as found by
+    /// `str::match_indices` (or `str::rmatch_indices`).
+    fn expected(text: &str, needle: &str, rev: bool) -> Vec<Range<usize>> {
+        let to_range = |(idx, _m): (usize, &str)| {
+            let start = text[..idx].chars().count();
+            start..start + needle.chars().count()
+        };
+        if rev {
+            text.rmatch_indices(needle).map(to_range).collect()
+        } else {
+            text.match_indices(needle).map(to_range).collect()
+        }
+    }
+
+    #[test]
+    fn across_pieces() {
+        let mut pt = PieceTable::new("abaab");
+        for (i, text) in ["ab", "a", "🦀b", "", "aba", "é", "ba"]
+            .into_iter()
+            .cycle()
+            .take(40)
+            .enumerate()
+        {
+            pt.insert((i * 7) % (pt.len_chars() + 1), text);
+        }
+        let text = pt.text();
+
+        for needle in ["", "a", "ab", "aba", "abab", "🦀ba", "bé", "aaa", &text]
+        {
+            let found: Vec<_> = pt.find_iter(needle).collect();
+            assert_eq!(found, expected(&text, needle, false), "{needle:?}");
+            assert_eq!(pt.find(needle), found.first().cloned());
+
+            let found: Vec<_> = pt.rfind_iter(needle).collect();
+            assert_eq!(found, expected(&text, needle, true), "{needle:?}");
+            assert_eq!(pt.rfind(needle), found.first().cloned());
+        }
+        assert_eq!(pt.find("abc"), None);
+        assert_eq!(pt.rfind(&(text + "a")), None);
+    }
+
+    #[test]
+    fn periodic_across_chunks() {
+        // Inserting at the start every time makes every char a piece of its
+        // own, so every match spans several chunks.
+        let mut pt = PieceTable::new("");
+        for i in 0..200 {
+            pt.insert(0, if i % 7 == 3 || i % 11 == 0 { "b" } else { "a" });
+        }
+        let text = pt.text();
+        assert_eq!(pt.iter().count(), 200);
+
+        for needle in ["aab", "aaaa", "baaaaab", "abaaa", &text[50..150]] {
+            let found: Vec<_> = pt.find_iter(needle).collect();
+            assert_eq!(found, expected(&text, needle, false), "{needle:?}");
+            let found: Vec<_> = pt.rfind_iter(needle).collect();
+            assert_eq!(found, expected(&text, needle, true), "{needle:?}");
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let pt = PieceTable::new("");
+        let mut matches = pt.find_iter("");
+        assert_eq!((matches.next(), matches.next()), (Some(0..0), None));
+        let mut matches = pt.rfind_iter("");
+        assert_eq!((matches.next(), matches.next()), (Some(0..0), None));
+        assert_eq!(pt.find("a"), None);
+        assert_eq!(pt.rfind("a"), None);
+    }
+}
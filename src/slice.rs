@@ -1,52 +1,607 @@
-use std::ops::Not;
+use std::cmp::Ordering;
+use std::ops::Range;
 
 use crate::PieceTable;
+use crate::error::{self, Error};
+#[cfg(feature = "lines")]
+use crate::iter::Lines;
+use crate::iter::{Bytes, Chars, Chunks};
 
-/// `(piece_idx, byte_idx)`.
-type Position = (usize, usize);
-
-#[derive(Debug)]
+/// An immutable view into a range of a [`PieceTable`].
+///
+/// All the indexes a slice takes and returns are relative to its start.
+#[derive(Debug, Clone)]
 pub struct Slice<'a> {
-    /// The position of the start piece and byte index in it, inclusive.
-    start: Position,
-    /// The position of the end piece and byte index in it, exclusive.
-    end: Position,
     table: &'a PieceTable<'a>,
+    /// The char range of the slice in the table.
+    char_range: Range<usize>,
+    /// The byte range of the slice in the table.
+    byte_range: Range<usize>,
+    /// The index of the table's line which contains the start of the slice.
+    #[cfg(feature = "lines")]
+    first_line: usize,
 }
 
 impl<'a> Slice<'a> {
-    pub(crate) const fn new(
-        start: Position,
-        end: Position,
-        table: &'a PieceTable,
-    ) -> Self {
-        Self { start, end, table }
+    /// Create a slice of the chars in `char_range` (which must be in bounds)
+    /// of `table`.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    pub(crate) fn new(table: &'a PieceTable, char_range: Range<usize>) -> Self {
+        let start = table.char_to_byte(char_range.start);
+        let end = table.char_to_byte(char_range.end);
+
+        Self {
+            table,
+            #[cfg(feature = "lines")]
+            first_line: table.char_to_line(char_range.start),
+            char_range,
+            byte_range: start..end,
+        }
+    }
+
+    /// Returns a [`Slice`] of the chars in `char_range` of this slice.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the end of the range is larger than the length of the
+    /// slice, or if its start is larger than its end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("lorem ipsum dolor");
+    /// let slice = pt.slice(6..);
+    /// assert_eq!(slice.slice(..5), "ipsum");
+    /// ```
+    pub fn slice<R>(&self, char_range: R) -> Self
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        error::unwrap(self.try_slice(char_range))
+    }
+
+    /// Like [`Slice::slice`], but returns an [`Error`] instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidRange`] if the start of the range is larger than
+    /// its end, and [`Error::OutOfBounds`] if its end is larger than the length
+    /// of the slice.
+    pub fn try_slice<R>(&self, char_range: R) -> Result<Self, Error>
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        let (start, end) =
+            PieceTable::simplify_range_bounds(char_range, self.len_chars())?;
+        error::check_range(start, end, self.len_chars())?;
+
+        let offset = self.char_range.start;
+        Ok(Self::new(self.table, offset + start..offset + end))
+    }
+
+    /// Total number of chars in the slice.
+    ///
+    /// Runs in `O(1)`.
+    pub const fn len_chars(&self) -> usize {
+        self.char_range.end - self.char_range.start
+    }
+
+    /// Total number of bytes in the slice.
+    ///
+    /// Runs in `O(1)`.
+    pub const fn len_bytes(&self) -> usize {
+        self.byte_range.end - self.byte_range.start
+    }
+
+    /// Total number of lines in the slice, i.e., the amount of line breaks in
+    /// it plus one.
+    ///
+    /// Runs in `O(log N)` where `N` is the amount of pieces.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\ntwo\nthree");
+    /// assert_eq!(pt.slice(2..9).len_lines(), 3);
+    /// assert_eq!(pt.slice(4..7).len_lines(), 1);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn len_lines(&self) -> usize {
+        self.table.char_to_line(self.char_range.end) - self.first_line + 1
+    }
+
+    /// Returns the byte index of the char at `char_idx`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the length of the slice.
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        assert!(char_idx <= self.len_chars(), "index out of bounds");
+        let byte_idx =
+            self.table.char_to_byte(self.char_range.start + char_idx);
+        byte_idx - self.byte_range.start
+    }
+
+    /// Like [`Slice::char_to_byte`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the length
+    /// of the slice.
+    pub fn try_char_to_byte(&self, char_idx: usize) -> Result<usize, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.char_to_byte(char_idx))
+    }
+
+    /// Returns the index of the char which contains the byte at `byte_idx`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `byte_idx` is larger than the length of the slice.
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        assert!(byte_idx <= self.len_bytes(), "index out of bounds");
+        let char_idx =
+            self.table.byte_to_char(self.byte_range.start + byte_idx);
+        char_idx - self.char_range.start
+    }
+
+    /// Like [`Slice::byte_to_char`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `byte_idx` is larger than the length
+    /// of the slice.
+    pub fn try_byte_to_char(&self, byte_idx: usize) -> Result<usize, Error> {
+        error::check_idx(byte_idx, self.len_bytes())?;
+        Ok(self.byte_to_char(byte_idx))
+    }
+
+    /// Returns the index of the line that contains the char at `char_idx`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `char_idx` is larger than the length of the slice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\ntwo\nthree");
+    /// let slice = pt.slice(2..);
+    /// assert_eq!(slice.char_to_line(1), 0);
+    /// assert_eq!(slice.char_to_line(2), 1);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        assert!(char_idx <= self.len_chars(), "index out of bounds");
+        let line_idx =
+            self.table.char_to_line(self.char_range.start + char_idx);
+        line_idx - self.first_line
+    }
+
+    /// Like [`Slice::char_to_line`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the length
+    /// of the slice.
+    #[cfg(feature = "lines")]
+    pub fn try_char_to_line(&self, char_idx: usize) -> Result<usize, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.char_to_line(char_idx))
+    }
+
+    /// Returns the char index of the start of the `line_idx`-th line. Like
+    /// [`PieceTable::line_to_char`], `line_idx` can be one past the last line.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is larger than the amount of lines.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\ntwo\nthree");
+    /// let slice = pt.slice(2..);
+    /// assert_eq!(slice.line_to_char(0), 0);
+    /// assert_eq!(slice.line_to_char(1), 2);
+    /// assert_eq!(slice.line_to_char(3), 11);
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn line_to_char(&self, line_idx: usize) -> usize {
+        let Ok(char_idx) = self.try_line_to_char(line_idx) else {
+            panic!("line index out of bounds");
+        };
+        char_idx
+    }
+
+    /// Like [`Slice::line_to_char`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `line_idx` is larger than the amount
+    /// of lines.
+    #[cfg(feature = "lines")]
+    pub fn try_line_to_char(&self, line_idx: usize) -> Result<usize, Error> {
+        if !self.has_line(line_idx) {
+            error::check_idx(line_idx, self.len_lines())?;
+            return Ok(self.len_chars());
+        }
+
+        let char_idx = self.table.line_to_char(self.first_line + line_idx);
+        Ok(char_idx.max(self.char_range.start) - self.char_range.start)
+    }
+
+    /// Returns a [`Slice`] containing the `line_idx`-th line, **without** the
+    /// line break sequence.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `line_idx` is out of bounds (i.e., there is no such line).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("one\ntwo\nthree");
+    /// let slice = pt.slice(2..10);
+    /// assert_eq!(slice.line(0), "e");
+    /// assert_eq!(slice.line(2), "th");
+    /// ```
+    #[cfg(feature = "lines")]
+    pub fn line(&self, line_idx: usize) -> Self {
+        assert!(self.has_line(line_idx), "line index out of bounds");
+
+        let line_idx = self.first_line + line_idx;
+        let start =
+            self.table.line_to_char(line_idx).max(self.char_range.start);
+        let end = self.table.line_end(line_idx).min(self.char_range.end);
+        Self::new(self.table, start..end.max(start))
+    }
+
+    /// Like [`Slice::line`], but returns an [`Error`] instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if there is no such line.
+    #[cfg(feature = "lines")]
+    pub fn try_line(&self, line_idx: usize) -> Result<Self, Error> {
+        if !self.has_line(line_idx) {
+            return Err(Error::OutOfBounds {
+                idx: line_idx,
+                len: self.len_lines(),
+            });
+        }
+        Ok(self.line(line_idx))
+    }
+
+    /// Returns an iterator over all the `&str` chunks in the slice.
+    pub fn iter(&self) -> Chunks<'a> {
+        Chunks::new(self.table, self.byte_range.clone(), self.byte_range.start)
+    }
+
+    /// Like [`PieceTable::chunks_at`], limited to the slice.
+    pub fn chunks_at(&self, char_idx: usize) -> Chunks<'a> {
+        let byte_idx = self.byte_range.start + self.char_to_byte(char_idx);
+        Chunks::new(self.table, self.byte_range.clone(), byte_idx)
+    }
+
+    /// Like [`Slice::chunks_at`], but returns an [`Error`] instead of
+    /// panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the length
+    /// of the slice.
+    pub fn try_chunks_at(&self, char_idx: usize) -> Result<Chunks<'a>, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.chunks_at(char_idx))
+    }
+
+    /// Like [`PieceTable::chars_at`], limited to the slice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let pt = PieceTable::new("(a(b)c)");
+    /// let slice = pt.slice(2..5);
+    ///
+    /// let mut chars = slice.chars_at(1);
+    /// assert_eq!(chars.prev(), Some('('));
+    /// assert_eq!(chars.prev(), None);
+    /// assert_eq!(chars.collect::<String>(), "(b)");
+    /// ```
+    pub fn chars_at(&self, char_idx: usize) -> Chars<'a> {
+        let byte_idx = self.byte_range.start + self.char_to_byte(char_idx);
+        Chars::new(self.table, self.byte_range.clone(), byte_idx)
+    }
+
+    /// Like [`Slice::chars_at`], but returns an [`Error`] instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `char_idx` is larger than the length
+    /// of the slice.
+    pub fn try_chars_at(&self, char_idx: usize) -> Result<Chars<'a>, Error> {
+        error::check_idx(char_idx, self.len_chars())?;
+        Ok(self.chars_at(char_idx))
+    }
+
+    /// Like [`PieceTable::bytes_at`], limited to the slice.
+    pub fn bytes_at(&self, byte_idx: usize) -> Bytes<'a> {
+        assert!(byte_idx <= self.len_bytes(), "index out of bounds");
+        let byte_idx = self.byte_range.start + byte_idx;
+        Bytes::new(self.table, self.byte_range.clone(), byte_idx)
+    }
+
+    /// Like [`Slice::bytes_at`], but returns an [`Error`] instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `byte_idx` is larger than the length
+    /// of the slice.
+    pub fn try_bytes_at(&self, byte_idx: usize) -> Result<Bytes<'a>, Error> {
+        error::check_idx(byte_idx, self.len_bytes())?;
+        Ok(self.bytes_at(byte_idx))
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &str> {
-        let pieces = dbg!(&self.table.pieces[self.start.0..=self.end.0]);
-        let buffers = &self.table.buffers;
+    /// Like [`PieceTable::lines_at`], limited to the slice.
+    #[cfg(feature = "lines")]
+    pub fn lines_at(&self, line_idx: usize) -> Lines<'a> {
+        let Ok(lines) = self.try_lines_at(line_idx) else {
+            panic!("line index out of bounds");
+        };
+        lines
+    }
 
-        pieces.iter().enumerate().filter_map(move |(i, piece)| {
-            let range = piece.byte_range();
-            let range = if i == 0 && i == pieces.len() - 1 {
-                range.start + self.start.1..range.start + self.end.1
-            } else if i == 0 {
-                range.start + self.start.1..range.end
-            } else if i == pieces.len() - 1 {
-                piece.start..range.start + self.end.1
-            } else {
-                range
-            };
+    /// Like [`Slice::lines_at`], but returns an [`Error`] instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutOfBounds`] if `line_idx` is larger than the amount
+    /// of lines.
+    #[cfg(feature = "lines")]
+    pub fn try_lines_at(&self, line_idx: usize) -> Result<Lines<'a>, Error> {
+        // There is a line before `line_idx`, so it is at most the amount of
+        // lines.
+        if !self.has_line(line_idx.saturating_sub(1)) {
+            error::check_idx(line_idx, self.len_lines())?;
+        }
+        Ok(Lines::new(self.clone(), line_idx))
+    }
 
-            let s = &buffers[piece.buffer][range];
-            s.is_empty().not().then_some(s)
-        })
+    /// Whether there is a `line_idx`-th line in the slice.
+    ///
+    /// Unlike comparing with [`Slice::len_lines`], this scans the text only up
+    /// to the line (if it wasn't scanned yet).
+    #[cfg(feature = "lines")]
+    pub(crate) fn has_line(&self, line_idx: usize) -> bool {
+        line_idx == 0
+            || self
+                .table
+                .find_line_break(self.first_line + line_idx - 1)
+                .is_some_and(|(position, ty)| {
+                    position.chars + ty.len_chars() <= self.char_range.end
+                })
     }
 }
 
-impl<'a> std::fmt::Display for Slice<'a> {
+impl std::fmt::Display for Slice<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.iter().try_for_each(|s| write!(f, "{s}"))
     }
 }
+
+/// Compare the concatenations of two sequences of chunks, byte by byte.
+fn cmp_chunks<'a, 'b>(
+    mut a: impl Iterator<Item = &'a [u8]>,
+    mut b: impl Iterator<Item = &'b [u8]>,
+) -> Ordering {
+    let (mut a_chunk, mut b_chunk): (&[u8], &[u8]) = (&[], &[]);
+    loop {
+        if a_chunk.is_empty() {
+            a_chunk = a.next().unwrap_or_default();
+        }
+        if b_chunk.is_empty() {
+            b_chunk = b.next().unwrap_or_default();
+        }
+        if a_chunk.is_empty() || b_chunk.is_empty() {
+            return a_chunk.len().cmp(&b_chunk.len());
+        }
+
+        let len = a_chunk.len().min(b_chunk.len());
+        match a_chunk[..len].cmp(&b_chunk[..len]) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+        a_chunk = &a_chunk[len..];
+        b_chunk = &b_chunk[len..];
+    }
+}
+
+impl PartialEq for Slice<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len_bytes() == other.len_bytes()
+            && self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Slice<'_> {}
+
+impl PartialOrd for Slice<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Slices are ordered lexicographically by their bytes, like [`str`]s.
+impl Ord for Slice<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let a = self.iter().map(str::as_bytes);
+        let b = other.iter().map(str::as_bytes);
+        cmp_chunks(a, b)
+    }
+}
+
+impl PartialEq<str> for Slice<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.len_bytes() == other.len()
+            && cmp_chunks(
+                self.iter().map(str::as_bytes),
+                std::iter::once(other.as_bytes()),
+            ) == Ordering::Equal
+    }
+}
+
+impl PartialEq<&str> for Slice<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl PartialEq<Slice<'_>> for str {
+    fn eq(&self, other: &Slice<'_>) -> bool {
+        other == self
+    }
+}
+
+impl PartialEq<Slice<'_>> for &str {
+    fn eq(&self, other: &Slice<'_>) -> bool {
+        other == *self
+    }
+}
+
+/// The hash of a slice only depends on its contents (and not on the way they
+/// are split into chunks), so equal slices have equal hashes.
+impl std::hash::Hash for Slice<'_> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // Hashers may hash the same bytes differently when they are split
+        // between calls to `write`, so the bytes are written in fixed blocks.
+        const BLOCK_LEN: usize = 256;
+        let mut block = [0; BLOCK_LEN];
+        let mut len = 0;
+
+        for mut chunk in self.iter().map(str::as_bytes) {
+            while !chunk.is_empty() {
+                let n = chunk.len().min(BLOCK_LEN - len);
+                block[len..len + n].copy_from_slice(&chunk[..n]);
+                len += n;
+                chunk = &chunk[n..];
+
+                if len == BLOCK_LEN {
+                    state.write(&block);
+                    len = 0;
+                }
+            }
+        }
+
+        state.write(&block[..len]);
+        state.write_u8(0xff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::{BuildHasher, RandomState};
+
+    fn table(expected: &mut String) -> PieceTable<'static> {
+        let mut pt = PieceTable::from(expected.clone());
+        for i in 0..300 {
+            let idx = (i * 7919) % (pt.len_chars() + 1);
+            let byte_idx = pt.char_to_byte(idx);
+            let text = ["a", "🦀", "\n", "ö\nx"][i % 4];
+            pt.insert(idx, text);
+            expected.insert_str(byte_idx, text);
+        }
+        pt
+    }
+
+    #[test]
+    fn nested_slices() {
+        let mut expected = "lorem ipsum\n".repeat(20);
+        let pt = table(&mut expected);
+        let chars = expected.chars().collect::<Vec<_>>();
+
+        for i in (0..chars.len()).step_by(13) {
+            let end = (i + i % 97).min(chars.len());
+            let slice = pt.slice(i..end);
+            let text = chars[i..end].iter().collect::<String>();
+            assert_eq!(slice, text.as_str());
+            assert_eq!(slice.len_chars(), end - i);
+            assert_eq!(slice.len_bytes(), text.len());
+            assert!(slice.chars_at(0).eq(text.chars()));
+            assert!(slice.chars_at(0).rev().eq(text.chars().rev()));
+            let mut chars = slice.chars_at(slice.len_chars());
+            assert!(std::iter::from_fn(|| chars.prev()).eq(text.chars().rev()));
+            assert!(slice.bytes_at(0).eq(text.bytes()));
+
+            let inner = slice.slice(1.min(slice.len_chars())..);
+            assert_eq!(
+                inner,
+                text.chars().skip(1).collect::<String>().as_str()
+            );
+
+            #[cfg(feature = "lines")]
+            {
+                let lines = text.split('\n').collect::<Vec<_>>();
+                assert_eq!(slice.len_lines(), lines.len());
+                assert!(
+                    slice
+                        .lines_at(0)
+                        .map(|l| l.to_string())
+                        .eq(lines.iter().copied())
+                );
+                for (line_idx, line) in lines.iter().enumerate() {
+                    assert_eq!(slice.line(line_idx), *line);
+                    let char_idx = slice.line_to_char(line_idx);
+                    assert_eq!(slice.char_to_line(char_idx), line_idx);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compare_and_hash() {
+        let initial = "abcabc";
+        let mut pt = PieceTable::new(initial);
+        pt.insert(4, "");
+        pt.remove(4..5);
+        pt.insert(4, "b");
+        pt.insert(1, "x");
+        pt.remove(1..2);
+
+        let (a, b) = (pt.slice(0..3), pt.slice(3..6));
+        assert_eq!(a, b);
+        assert_eq!(a, "abc");
+        assert_eq!("abc", b);
+        assert_ne!(a, "abcd");
+        assert_ne!(pt.slice(0..2), b);
+
+        let state = RandomState::new();
+        assert_eq!(state.hash_one(&a), state.hash_one(&b));
+
+        let long = "🦀ab".repeat(1000);
+        let mut pt = PieceTable::new(&long);
+        pt.insert(500, "🦀ab");
+        pt.remove(500..503);
+        let other = PieceTable::new(&long);
+        assert_eq!(
+            state.hash_one(pt.slice(..)),
+            state.hash_one(other.slice(..))
+        );
+
+        assert!(pt.slice(0..4) > pt.slice(1..4));
+        assert!(pt.slice(1..3) < pt.slice(1..4));
+        assert_eq!(pt.slice(..).cmp(&other.slice(..)), Ordering::Equal);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::PieceTable;
+
+/// A read-only view of a [`PieceTable`], as it was when
+/// [`PieceTable::snapshot`] was called.
+///
+/// A snapshot dereferences to a [`PieceTable`], so all of its non-mutating
+/// methods (e.g., [`PieceTable::iter`] and [`PieceTable::slice`]) can be used
+/// on it. The history of the table is not a part of the snapshot.
+#[derive(Debug)]
+pub struct Snapshot<'b> {
+    table: PieceTable<'b>,
+}
+
+impl<'b> Snapshot<'b> {
+    pub(crate) const fn new(table: PieceTable<'b>) -> Self {
+        Self { table }
+    }
+}
+
+impl<'b> std::ops::Deref for Snapshot<'b> {
+    type Target = PieceTable<'b>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.table
+    }
+}
+
+impl Clone for Snapshot<'_> {
+    fn clone(&self) -> Self {
+        self.table.snapshot()
+    }
+}
+
+impl std::fmt::Display for Snapshot<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.table.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Snapshot<'static>>();
+        assert_send_sync::<PieceTable<'static>>();
+    }
+
+    #[test]
+    fn unaffected_by_later_edits() {
+        let initial = "lorem ipsum dolor sit amet\n".repeat(2000);
+        let mut pt = PieceTable::new(&initial);
+        let mut snapshots = vec![];
+
+        for i in 0..2000 {
+            if i % 100 == 0 {
+                snapshots.push((pt.snapshot(), pt.text()));
+            }
+
+            let idx = (i * 7919) % (pt.len_chars() + 1);
+            pt.insert(idx, &"ab\ncd".repeat(i % 50));
+            let start = (i * 104729) % pt.len_chars();
+            pt.remove(start..(start + i % 40).min(pt.len_chars()));
+        }
+
+        std::thread::scope(|s| {
+            for (snapshot, text) in &snapshots {
+                s.spawn(move || {
+                    assert_eq!(&snapshot.text(), text);
+                    assert_eq!(snapshot.len_chars(), text.len());
+                    assert_eq!(
+                        snapshot.slice(10..20).to_string(),
+                        text[10..20]
+                    );
+                    #[cfg(feature = "lines")]
+                    assert_eq!(
+                        snapshot.line(3).to_string(),
+                        text.split('\n').nth(3).unwrap()
+                    );
+                });
+            }
+        });
+    }
+}
@@ -1,14 +1,61 @@
 pub(crate) use str_indices::chars::count as count_chars;
+pub(crate) use str_indices::chars::from_byte_idx as byte_to_char;
 pub(crate) use str_indices::chars::to_byte_idx as char_to_byte;
+pub(crate) use str_indices::utf16::count as count_utf16;
+pub(crate) use str_indices::utf16::from_byte_idx as byte_to_utf16;
+pub(crate) use str_indices::utf16::to_byte_idx as utf16_to_byte;
 
+#[cfg(feature = "lines")]
 use crate::line;
 
-/// Insert the indexes of the line breaks in `text` into `v`. `base_idx` will be
-/// added to every index.
+/// Split `text` into consecutive byte ranges of at most `max_len` bytes each
+/// (`max_len` must be at least 4). Ranges never end inside a char, nor
+/// between the CR and the LF of a CRLF sequence.
+///
+/// `text` is expected to be valid UTF-8. If it isn't, a range may end inside
+/// an invalid sequence (which checking the range finds).
+pub(crate) fn chunk_ranges(
+    text: &[u8],
+    max_len: usize,
+) -> impl Iterator<Item = std::ops::Range<usize>> {
+    let mut start = 0;
+
+    std::iter::from_fn(move || {
+        if start == text.len() {
+            return None;
+        }
+
+        let max_end = text.len().min(start + max_len);
+        let mut end = max_end;
+        while end < text.len() && !is_char_start(text[end]) && end > start {
+            end -= 1;
+        }
+        if end == start {
+            end = max_end; // not valid UTF-8
+        }
+        if end < text.len() && text[end - 1] == 0x0D && text[end] == 0x0A {
+            end -= 1;
+        }
+
+        let range = start..end;
+        start = end;
+        Some(range)
+    })
+}
+
+/// Whether `byte` is the first byte of a UTF-8 encoded char.
+const fn is_char_start(byte: u8) -> bool {
+    (byte as i8) >= -0x40
+}
+
+/// Insert the indexes of the line breaks in `text` (according to `config`)
+/// into `v`, and return their amount. `base_idx` will be added to every index.
+#[cfg(feature = "lines")]
 pub(crate) fn line_breaks(
     text: &str,
     v: &mut Vec<(usize, line::Break)>,
     base_idx: usize,
+    config: line::LineBreakConfig,
 ) -> usize {
     let mut bytes = text
         .as_bytes()
@@ -17,34 +64,31 @@ pub(crate) fn line_breaks(
         .map(|(idx, byte)| (idx + base_idx, byte))
         .peekable();
 
-    let mut line_breaks = 0;
+    #[cfg(feature = "unicode-line-breaks")]
+    let unicode = config == line::LineBreakConfig::Unicode;
+    #[cfg(not(feature = "unicode-line-breaks"))]
+    let unicode = false;
+    let len = v.len();
 
     while let Some((idx, byte)) = bytes.next() {
-        line_breaks += 1;
-
         match byte {
             0x0A => v.push((idx, line::Break::Lf)),
-            0x0D => {
+            0x0D if config != line::LineBreakConfig::Lf => {
                 if bytes.next_if(|&(_idx, &byte)| byte == 0x0A).is_some() {
                     v.push((idx, line::Break::Crlf));
-                } else {
-                    #[cfg(feature = "unicode-line-breaks")]
+                } else if unicode {
                     v.push((idx, line::Break::Cr));
                 }
             }
-            #[cfg(feature = "unicode-line-breaks")]
-            0x0B => v.push((idx, line::Break::Vt)),
-            #[cfg(feature = "unicode-line-breaks")]
-            0x0C => v.push((idx, line::Break::Ff)),
+            0x0B if unicode => v.push((idx, line::Break::Vt)),
+            0x0C if unicode => v.push((idx, line::Break::Ff)),
             // Nel is part of a two-byte UTF-8 sequence, hence there is no need
             // to peek, because if the next byte is not Nel's second byte, it
             // cannot start a new char anyway (thus irrelevant).
-            #[cfg(feature = "unicode-line-breaks")]
-            0xC2 if let Some((_idx, 0x85)) = bytes.next() => {
+            0xC2 if unicode && let Some((_idx, 0x85)) = bytes.next() => {
                 v.push((idx, line::Break::Nel));
             }
-            #[cfg(feature = "unicode-line-breaks")]
-            0xE2 => {
+            0xE2 if unicode => {
                 let n1 = bytes.next().map(|(_idx, byte)| byte);
                 let n2 = bytes.next().map(|(_idx, byte)| byte);
                 if n1.is_some_and(|&b| b == 0x80) {
@@ -55,14 +99,41 @@ pub(crate) fn line_breaks(
                     }
                 }
             }
-            _ => line_breaks -= 1,
+            _ => {}
         }
     }
 
-    line_breaks
+    v.len() - len
+}
+
+/// The line breaks in `text` as LSP defines them, which are LF, CRLF and CR
+/// whatever the [`LineBreakConfig`] is, as the byte index and the length of
+/// each one.
+///
+/// A CR at the end of `text` is a line break of its own, which is right as
+/// long as a CRLF sequence is never split between pieces (see
+/// [`PieceTable::fit_crlf`]).
+///
+/// [`LineBreakConfig`]: line::LineBreakConfig
+/// [`PieceTable::fit_crlf`]: crate::PieceTable::fit_crlf
+#[cfg(feature = "lines")]
+pub(crate) fn lsp_line_breaks(
+    text: &str,
+) -> impl Iterator<Item = (usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut start = 0;
+
+    std::iter::from_fn(move || {
+        let idx = start
+            + bytes[start..].iter().position(|&b| b == 0x0A || b == 0x0D)?;
+        let len = if bytes[idx..].starts_with(b"\r\n") { 2 } else { 1 };
+        start = idx + len;
+        Some((idx, len))
+    })
 }
 
 #[cfg(test)]
+#[cfg(feature = "lines")]
 mod tests {
     use super::*;
 
@@ -70,8 +141,27 @@ mod tests {
     fn count_lines() {
         let mut v = vec![];
         let text = "My name is:\nNot 123, but it is\r\nNot 321 either.";
-        line_breaks(text, &mut v, 0);
+        line_breaks(text, &mut v, 0, line::LineBreakConfig::default());
         dbg!(&v);
         assert_eq!(v.len(), 2)
     }
+
+    #[test]
+    fn line_break_configs() {
+        use line::LineBreakConfig;
+
+        let text = "a\nb\r\nc\rd\u{b}e\u{85}f\u{2028}g\u{2029}";
+        let count = |config| line_breaks(text, &mut vec![], 0, config);
+        assert_eq!(count(LineBreakConfig::Lf), 2);
+        assert_eq!(count(LineBreakConfig::LfCrlf), 2);
+        #[cfg(feature = "unicode-line-breaks")]
+        assert_eq!(count(LineBreakConfig::Unicode), 7);
+
+        let mut v = vec![];
+        line_breaks(text, &mut v, 0, LineBreakConfig::Lf);
+        assert_eq!(v, [(1, line::Break::Lf), (4, line::Break::Lf)]);
+
+        let lsp = lsp_line_breaks(text).collect::<Vec<_>>();
+        assert_eq!(lsp, [(1, 1), (3, 2), (6, 1)]);
+    }
 }
@@ -0,0 +1,856 @@
+//! A persistent weight-balanced tree of [`Piece`]s, ordered by their position
+//! in the document.
+//!
+//! Every node caches the [`Size`] of its subtree, so looking up the piece
+//! which contains a given char (or byte) is `O(log n)` in the amount of
+//! pieces, and so are insertions and removals of pieces. The rest of the
+//! [`Metrics`] of a subtree are cached once all its pieces were scanned (see
+//! [`Piece::scanned`]), so looking up a line break scans the pieces before it
+//! the first time, and is `O(log n)` from then on.
+//!
+//! Nodes are shared (through [`Arc`]s) between a tree and its clones, so
+//! cloning a tree is `O(1)`. An edit copies the nodes on the path to the piece
+//! it changes, but only the nodes that are shared, so a tree which is not
+//! shared is edited in place.
+//!
+//! The tree is balanced by weight (the amount of pieces in each subtree)
+//! rather than by color: the nodes keep the weight anyway, to turn the index
+//! of a piece into a path, and restoring the balance needs only the rotations
+//! on the path that was edited. So, unlike a red-black tree, no node needs to
+//! point at its parent, which a node that is shared between trees can't do.
+
+use std::cmp::Ordering;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::sync::{Arc, OnceLock};
+
+use crate::buffer::Buffers;
+use crate::piece::Piece;
+
+/// The lengths of a piece, or of a sequence of pieces, which are known without
+/// scanning its text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Size {
+    pub(crate) bytes: usize,
+    pub(crate) chars: usize,
+}
+
+impl Add for Size {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self { bytes: self.bytes + rhs.bytes, chars: self.chars + rhs.chars }
+    }
+}
+
+impl AddAssign for Size {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// The lengths of a piece, or of a sequence of pieces, in every unit the table
+/// can be indexed by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Metrics {
+    pub(crate) bytes: usize,
+    pub(crate) chars: usize,
+    /// The amount of UTF-16 code units the text would take.
+    pub(crate) utf16: usize,
+    #[cfg(feature = "lines")]
+    pub(crate) line_breaks: usize,
+    /// The amount of line breaks as LSP defines them, whatever the table's
+    /// line breaks are (see [`str_utils::lsp_line_breaks`]).
+    ///
+    /// [`str_utils::lsp_line_breaks`]: crate::str_utils::lsp_line_breaks
+    #[cfg(feature = "lines")]
+    pub(crate) lsp_line_breaks: usize,
+}
+
+impl Add for Metrics {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            bytes: self.bytes + rhs.bytes,
+            chars: self.chars + rhs.chars,
+            utf16: self.utf16 + rhs.utf16,
+            #[cfg(feature = "lines")]
+            line_breaks: self.line_breaks + rhs.line_breaks,
+            #[cfg(feature = "lines")]
+            lsp_line_breaks: self.lsp_line_breaks + rhs.lsp_line_breaks,
+        }
+    }
+}
+
+impl Sub for Metrics {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self {
+            bytes: self.bytes - rhs.bytes,
+            chars: self.chars - rhs.chars,
+            utf16: self.utf16 - rhs.utf16,
+            #[cfg(feature = "lines")]
+            line_breaks: self.line_breaks - rhs.line_breaks,
+            #[cfg(feature = "lines")]
+            lsp_line_breaks: self.lsp_line_breaks - rhs.lsp_line_breaks,
+        }
+    }
+}
+
+impl AddAssign for Metrics {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Metrics {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+type Link = Option<Arc<Node>>;
+
+#[derive(Debug, Clone)]
+struct Node {
+    piece: Piece,
+    /// The sizes of all the pieces in the subtree.
+    size: Size,
+    /// The lengths of all the pieces in the subtree, once they were all
+    /// scanned.
+    total: OnceLock<Metrics>,
+    /// The amount of pieces in the subtree.
+    len: usize,
+    left: Link,
+    right: Link,
+}
+
+/// A handle to a piece in a [`Tree`], which is the index of the piece in the
+/// document.
+///
+/// A handle keeps pointing at the same piece as long as no piece is inserted
+/// or removed before it.
+pub(crate) type NodeIdx = usize;
+
+/// What [`Tree::search_scanned`] found.
+enum Found {
+    /// The node which contains the index, and the lengths before it.
+    Node(Metrics, NodeIdx),
+    /// The index is the length of the whole tree: the last node, and the
+    /// lengths before it.
+    End(Metrics, NodeIdx),
+    None,
+}
+
+/// The balance parameters of the tree: a subtree may weigh at most `DELTA`
+/// times its sibling, and a double rotation is used when the inner grandchild
+/// weighs at least `GAMMA` times the outer one. These are the parameters which
+/// were proven correct by Hirai and Yamamoto.
+const DELTA: usize = 3;
+const GAMMA: usize = 2;
+
+fn size(link: &Link) -> Size {
+    link.as_ref().map_or_else(Size::default, |node| node.size)
+}
+
+/// The lengths of the subtree of `link`, if all its pieces were scanned.
+fn scanned_total(link: &Link) -> Option<Metrics> {
+    link.as_ref()
+        .map_or(Some(Metrics::default()), |node| node.total.get().copied())
+}
+
+/// The lengths of the subtree of `link`, scanning the pieces which weren't yet.
+fn total(link: &Link, buffers: &Buffers) -> Metrics {
+    let Some(node) = link else { return Metrics::default() };
+    *node.total.get_or_init(|| {
+        total(&node.left, buffers)
+            + node.piece.metrics(buffers)
+            + total(&node.right, buffers)
+    })
+}
+
+fn len(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.len)
+}
+
+fn weight(link: &Link) -> usize {
+    len(link) + 1
+}
+
+/// The node of `link`, which must not be empty, copied if it is shared.
+fn node_mut(link: &mut Link) -> &mut Node {
+    let Some(node) = link else { unreachable!("the node exists") };
+    Arc::make_mut(node)
+}
+
+impl Node {
+    fn new(piece: Piece) -> Self {
+        Self {
+            size: piece.size(),
+            total: piece
+                .scanned_metrics()
+                .map_or_else(OnceLock::new, OnceLock::from),
+            piece,
+            len: 1,
+            left: None,
+            right: None,
+        }
+    }
+
+    /// Recompute the lengths of the subtree from the node's children.
+    fn update(&mut self) {
+        self.size = size(&self.left) + self.piece.size() + size(&self.right);
+        self.total = match (
+            scanned_total(&self.left),
+            self.piece.scanned_metrics(),
+            scanned_total(&self.right),
+        ) {
+            (Some(left), Some(piece), Some(right)) => {
+                OnceLock::from(left + piece + right)
+            }
+            _ => OnceLock::new(),
+        };
+        self.len = len(&self.left) + 1 + len(&self.right);
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Tree {
+    root: Link,
+}
+
+impl Tree {
+    pub(crate) fn new() -> Self {
+        Self { root: None }
+    }
+
+    /// The sizes of all the pieces in the tree together.
+    ///
+    /// Runs in `O(1)`.
+    pub(crate) fn size(&self) -> Size {
+        size(&self.root)
+    }
+
+    /// The lengths of all the pieces in the tree together.
+    ///
+    /// Runs in `O(1)` once all the pieces were scanned, and scans the ones
+    /// which weren't yet otherwise.
+    pub(crate) fn total(&self, buffers: &Buffers) -> Metrics {
+        total(&self.root, buffers)
+    }
+
+    /// The amount of pieces in the tree.
+    pub(crate) fn len(&self) -> usize {
+        len(&self.root)
+    }
+
+    pub(crate) fn piece(&self, mut node: NodeIdx) -> &Piece {
+        let mut curr = self.root.as_deref();
+        while let Some(n) = curr {
+            let left = len(&n.left);
+            match node.cmp(&left) {
+                Ordering::Less => curr = n.left.as_deref(),
+                Ordering::Equal => return &n.piece,
+                Ordering::Greater => {
+                    node -= left + 1;
+                    curr = n.right.as_deref();
+                }
+            }
+        }
+        unreachable!("the node is in the tree")
+    }
+
+    #[cfg(test)]
+    pub(crate) fn last(&self) -> Option<NodeIdx> {
+        self.len().checked_sub(1)
+    }
+
+    /// The node which comes right after `node` in the document.
+    pub(crate) fn next(&self, node: NodeIdx) -> Option<NodeIdx> {
+        (node + 1 < self.len()).then_some(node + 1)
+    }
+
+    /// The node which comes right before `node` in the document.
+    pub(crate) fn prev(&self, node: NodeIdx) -> Option<NodeIdx> {
+        node.checked_sub(1)
+    }
+
+    /// Iterate over the pieces in document order.
+    pub(crate) fn iter(&self) -> Iter<'_> {
+        let mut iter = Iter { stack: vec![] };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    /// Find the node that contains the char at `char_idx`, and the sizes of all
+    /// the pieces before it.
+    ///
+    /// If `char_idx` is the length of the document, the last node is returned.
+    pub(crate) fn node_at_char(
+        &self,
+        char_idx: usize,
+    ) -> Option<(Size, NodeIdx)> {
+        self.search(char_idx, |s| s.chars)
+    }
+
+    /// Find the node that contains the byte at `byte_idx`, and the sizes of all
+    /// the pieces before it.
+    ///
+    /// If `byte_idx` is the length of the document, the last node is returned.
+    pub(crate) fn node_at_byte(
+        &self,
+        byte_idx: usize,
+    ) -> Option<(Size, NodeIdx)> {
+        self.search(byte_idx, |s| s.bytes)
+    }
+
+    /// Find the node that contains the UTF-16 code unit at `utf16_idx`, and the
+    /// lengths of all the pieces before it.
+    ///
+    /// If `utf16_idx` is the length of the document, the last node is
+    /// returned.
+    pub(crate) fn node_at_utf16(
+        &self,
+        utf16_idx: usize,
+        buffers: &Buffers,
+    ) -> Option<(Metrics, NodeIdx)> {
+        match self.search_scanned(utf16_idx, |m| m.utf16, buffers) {
+            Found::Node(before, node) | Found::End(before, node) => {
+                Some((before, node))
+            }
+            Found::None => None,
+        }
+    }
+
+    /// Find the node that contains the `line_break_idx`-th line break (i.e., in
+    /// which that line break starts), and the lengths of all the pieces before
+    /// it.
+    #[cfg(feature = "lines")]
+    pub(crate) fn node_at_line_break(
+        &self,
+        line_break_idx: usize,
+        buffers: &Buffers,
+    ) -> Option<(Metrics, NodeIdx)> {
+        match self.search_scanned(line_break_idx, |m| m.line_breaks, buffers) {
+            Found::Node(before, node) => Some((before, node)),
+            Found::End(..) | Found::None => None,
+        }
+    }
+
+    /// Like [`Tree::node_at_line_break`], but for the line breaks as LSP
+    /// defines them (see [`Metrics::lsp_line_breaks`]).
+    #[cfg(feature = "lines")]
+    pub(crate) fn node_at_lsp_line_break(
+        &self,
+        line_break_idx: usize,
+        buffers: &Buffers,
+    ) -> Option<(Metrics, NodeIdx)> {
+        let dim = |m: &Metrics| m.lsp_line_breaks;
+        match self.search_scanned(line_break_idx, dim, buffers) {
+            Found::Node(before, node) => Some((before, node)),
+            Found::End(..) | Found::None => None,
+        }
+    }
+
+    /// The lengths of all the pieces before `node`, scanning the ones which
+    /// weren't yet.
+    pub(crate) fn metrics_before(
+        &self,
+        mut node: NodeIdx,
+        buffers: &Buffers,
+    ) -> Metrics {
+        let mut curr = self.root.as_deref();
+        let mut before = Metrics::default();
+        while let Some(n) = curr {
+            let left = len(&n.left);
+            match node.cmp(&left) {
+                Ordering::Less => curr = n.left.as_deref(),
+                Ordering::Equal => return before + total(&n.left, buffers),
+                Ordering::Greater => {
+                    node -= left + 1;
+                    before +=
+                        total(&n.left, buffers) + n.piece.metrics(buffers);
+                    curr = n.right.as_deref();
+                }
+            }
+        }
+        unreachable!("the node is in the tree")
+    }
+
+    /// Descend the tree looking for the node which contains `idx`, as measured
+    /// by `dim`.
+    ///
+    /// A node contains the indexes in `start..start + len`, where `start` is
+    /// the sum of all the nodes before it. If `idx` equals the length of the
+    /// whole tree, the last node is returned.
+    fn search(
+        &self,
+        mut idx: usize,
+        dim: impl Fn(&Size) -> usize,
+    ) -> Option<(Size, NodeIdx)> {
+        let mut curr = self.root.as_deref();
+        // The sizes, and the amount, of the pieces before the subtree of
+        // `curr`.
+        let mut before = Size::default();
+        let mut before_len = 0;
+
+        while let Some(node) = curr {
+            let left_size = size(&node.left);
+            let left = dim(&left_size);
+            let piece = node.piece.size();
+            let found = || (before + left_size, before_len + len(&node.left));
+
+            if idx < left {
+                curr = node.left.as_deref();
+            } else if idx < left + dim(&piece) {
+                return Some(found());
+            } else if node.right.is_some() {
+                idx -= left + dim(&piece);
+                before += left_size + piece;
+                before_len += len(&node.left) + 1;
+                curr = node.right.as_deref();
+            } else if idx == left + dim(&piece) {
+                return Some(found());
+            } else {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    /// Like [`Tree::search`], but by lengths which are only known once the
+    /// pieces were scanned.
+    ///
+    /// The pieces are visited in document order, skipping the subtrees which
+    /// were all scanned and end before `idx`, and scanning the pieces which
+    /// weren't yet. So the first search scans the pieces up to the node which
+    /// is found, and caches the lengths of the subtrees it went through.
+    fn search_scanned(
+        &self,
+        idx: usize,
+        dim: impl Fn(&Metrics) -> usize + Copy,
+        buffers: &Buffers,
+    ) -> Found {
+        /// The state of the walk: the index left to find, and the lengths and
+        /// the amount of the pieces which were walked past.
+        struct Walk<'a, 'b, D> {
+            idx: usize,
+            before: Metrics,
+            before_len: usize,
+            dim: D,
+            buffers: &'a Buffers<'b>,
+        }
+
+        impl<D: Fn(&Metrics) -> usize> Walk<'_, '_, D> {
+            /// Walk the subtree of `link`, returning the node which contains
+            /// the index if it is in the subtree.
+            fn walk(&mut self, link: &Link) -> Option<(Metrics, NodeIdx)> {
+                let node = link.as_deref()?;
+                if let Some(total) = node.total.get()
+                    && self.idx >= (self.dim)(total)
+                {
+                    self.idx -= (self.dim)(total);
+                    self.before += *total;
+                    self.before_len += node.len;
+                    return None;
+                }
+
+                if let Some(found) = self.walk(&node.left) {
+                    return Some(found);
+                }
+                let piece = node.piece.metrics(self.buffers);
+                if self.idx < (self.dim)(&piece) {
+                    return Some((self.before, self.before_len));
+                }
+                self.idx -= (self.dim)(&piece);
+                self.before += piece;
+                self.before_len += 1;
+                if let Some(found) = self.walk(&node.right) {
+                    return Some(found);
+                }
+
+                // Both children were walked to their end, so they were
+                // scanned.
+                if let (Some(left), Some(right)) =
+                    (scanned_total(&node.left), scanned_total(&node.right))
+                {
+                    let _ = node.total.set(left + piece + right);
+                }
+                None
+            }
+        }
+
+        let mut walk = Walk {
+            idx,
+            before: Metrics::default(),
+            before_len: 0,
+            dim,
+            buffers,
+        };
+        if let Some((before, node)) = walk.walk(&self.root) {
+            return Found::Node(before, node);
+        }
+
+        match self.len().checked_sub(1) {
+            Some(last) if walk.idx == 0 => {
+                let piece = self.piece(last).metrics(buffers);
+                Found::End(walk.before - piece, last)
+            }
+            _ => Found::None,
+        }
+    }
+
+    /// Recompute the lengths cached in the tree (the total of every subtree)
+    /// from its pieces, and return the first cached lengths which are wrong
+    /// along with the recomputed ones.
+    ///
+    /// The pieces which weren't scanned yet are scanned (and so are the totals
+    /// which weren't cached yet).
+    ///
+    /// Runs in `O(n)` where `n` is the amount of pieces.
+    pub(crate) fn check_metrics(
+        &self,
+        buffers: &Buffers,
+    ) -> Option<(Metrics, Metrics)> {
+        fn sum(
+            link: &Link,
+            buffers: &Buffers,
+        ) -> Result<Metrics, (Metrics, Metrics)> {
+            let Some(node) = link else { return Ok(Metrics::default()) };
+            let total = sum(&node.left, buffers)?
+                + node.piece.metrics(buffers)
+                + sum(&node.right, buffers)?;
+            // The sizes are cached even if the rest of the lengths aren't.
+            let cached = Metrics {
+                bytes: node.size.bytes,
+                chars: node.size.chars,
+                ..node.total.get().copied().unwrap_or(total)
+            };
+            if cached != total {
+                return Err((cached, total));
+            }
+            let _ = node.total.set(total);
+            Ok(total)
+        }
+
+        sum(&self.root, buffers).err()
+    }
+
+    /// Insert `piece` as the last piece in the document.
+    pub(crate) fn push_back(&mut self, piece: Piece) -> NodeIdx {
+        self.insert(self.len(), piece)
+    }
+
+    /// Insert `piece` at `node` (which may be the amount of pieces), so it is
+    /// right before the piece which was at `node`. Returns `node`.
+    pub(crate) fn insert(&mut self, node: NodeIdx, piece: Piece) -> NodeIdx {
+        fn insert(link: &mut Link, idx: usize, piece: Piece) {
+            if link.is_none() {
+                *link = Some(Arc::new(Node::new(piece)));
+                return;
+            }
+
+            let node = node_mut(link);
+            let left = len(&node.left);
+            if idx <= left {
+                insert(&mut node.left, idx, piece);
+            } else {
+                insert(&mut node.right, idx - left - 1, piece);
+            }
+            node.update();
+            balance(link);
+        }
+
+        debug_assert!(node <= self.len());
+        insert(&mut self.root, node, piece);
+        node
+    }
+
+    /// Insert `piece` right after `node` in the document.
+    pub(crate) fn insert_after(
+        &mut self,
+        node: NodeIdx,
+        piece: Piece,
+    ) -> NodeIdx {
+        self.insert(node + 1, piece)
+    }
+
+    /// Replace the piece of `node` with `piece`, returning the old piece.
+    pub(crate) fn replace(&mut self, node: NodeIdx, piece: Piece) -> Piece {
+        fn replace(link: &mut Link, idx: usize, piece: Piece) -> Piece {
+            let node = node_mut(link);
+            let left = len(&node.left);
+            let old = match idx.cmp(&left) {
+                Ordering::Less => replace(&mut node.left, idx, piece),
+                Ordering::Equal => std::mem::replace(&mut node.piece, piece),
+                Ordering::Greater => {
+                    replace(&mut node.right, idx - left - 1, piece)
+                }
+            };
+            node.update();
+            old
+        }
+
+        replace(&mut self.root, node, piece)
+    }
+
+    /// Remove `node` from the tree, returning its piece. The handles of the
+    /// pieces after it are shifted back by one.
+    pub(crate) fn remove(&mut self, node: NodeIdx) -> Piece {
+        fn remove(link: &mut Link, idx: usize) -> Piece {
+            let node = node_mut(link);
+            let left = len(&node.left);
+            let piece = match idx.cmp(&left) {
+                Ordering::Less => remove(&mut node.left, idx),
+                Ordering::Greater => remove(&mut node.right, idx - left - 1),
+                Ordering::Equal if node.right.is_none() => {
+                    let piece = node.piece.clone();
+                    *link = node.left.take();
+                    return piece;
+                }
+                // The successor of the node takes its place.
+                Ordering::Equal => {
+                    let successor = remove(&mut node.right, 0);
+                    std::mem::replace(&mut node.piece, successor)
+                }
+            };
+            node.update();
+            balance(link);
+            piece
+        }
+
+        debug_assert!(node < self.len());
+        remove(&mut self.root, node)
+    }
+}
+
+/// Restore the balance of the subtree of `link` after a single piece was
+/// inserted to (or removed from) one of its children.
+fn balance(link: &mut Link) {
+    let Some(node) = link else { return };
+    let (left, right) = (weight(&node.left), weight(&node.right));
+
+    if right > DELTA * left {
+        let Some(child) = &node.right else { unreachable!() };
+        if weight(&child.left) >= GAMMA * weight(&child.right) {
+            rotate_right(&mut node_mut(link).right);
+        }
+        rotate_left(link);
+    } else if left > DELTA * right {
+        let Some(child) = &node.left else { unreachable!() };
+        if weight(&child.right) >= GAMMA * weight(&child.left) {
+            rotate_left(&mut node_mut(link).left);
+        }
+        rotate_right(link);
+    }
+}
+
+/// Rotate the node of `link` down to the left, making its right child take its
+/// place.
+fn rotate_left(link: &mut Link) {
+    let Some(mut x) = link.take() else { unreachable!() };
+    let x_node = Arc::make_mut(&mut x);
+    let Some(mut y) = x_node.right.take() else { unreachable!() };
+    let y_node = Arc::make_mut(&mut y);
+
+    x_node.right = y_node.left.take();
+    x_node.update();
+    y_node.left = Some(x);
+    y_node.update();
+    *link = Some(y);
+}
+
+/// Rotate the node of `link` down to the right, making its left child take its
+/// place.
+fn rotate_right(link: &mut Link) {
+    let Some(mut x) = link.take() else { unreachable!() };
+    let x_node = Arc::make_mut(&mut x);
+    let Some(mut y) = x_node.left.take() else { unreachable!() };
+    let y_node = Arc::make_mut(&mut y);
+
+    x_node.left = y_node.right.take();
+    x_node.update();
+    y_node.right = Some(x);
+    y_node.update();
+    *link = Some(y);
+}
+
+impl std::fmt::Debug for Tree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over the pieces of a [`Tree`], in document order.
+pub(crate) struct Iter<'a> {
+    /// The nodes whose pieces (and right subtrees) were not visited yet, with
+    /// the next one on top.
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iter<'a> {
+    fn push_left(&mut self, mut node: Option<&'a Node>) {
+        while let Some(n) = node {
+            self.stack.push(n);
+            node = n.left.as_deref();
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Piece;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(node.right.as_deref());
+        Some(&node.piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{BufferType, Original};
+    use crate::piece::Scanned;
+
+    fn piece(len: usize) -> Piece {
+        Piece {
+            buffer: BufferType::Add,
+            start: 0,
+            len_bytes: len * 2,
+            len_chars: len,
+            scanned: OnceLock::from(Scanned {
+                len_utf16: len + len / 4,
+                #[cfg(feature = "lines")]
+                first_line_break: None,
+                #[cfg(feature = "lines")]
+                len_line_breaks: len % 3,
+                #[cfg(feature = "lines")]
+                len_lsp_line_breaks: len % 5,
+            }),
+        }
+    }
+
+    /// Assert the balance and the cached lengths of the subtree, and return
+    /// its lengths.
+    fn check(link: &Link) -> Metrics {
+        let Some(node) = link else { return Metrics::default() };
+        let (left, right) = (weight(&node.left), weight(&node.right));
+        assert!(left <= DELTA * right && right <= DELTA * left);
+        assert_eq!(node.len, len(&node.left) + 1 + len(&node.right));
+
+        let total = check(&node.left)
+            + node.piece.scanned_metrics().unwrap()
+            + check(&node.right);
+        assert_eq!(Some(&total), node.total.get());
+        assert_eq!(node.size, Size { bytes: total.bytes, chars: total.chars });
+        total
+    }
+
+    fn check_tree(tree: &Tree) {
+        let buffers = Buffers::from_original(Original::Borrowed(""));
+        check(&tree.root);
+        assert_eq!(tree.check_metrics(&buffers), None);
+        assert_eq!(tree.iter().count(), tree.len());
+    }
+
+    fn lens(tree: &Tree) -> Vec<usize> {
+        tree.iter().map(|p| p.len_chars).collect()
+    }
+
+    #[test]
+    fn random_operations() {
+        let mut tree = Tree::new();
+        // The expected `len_chars` of the pieces, in order.
+        let mut expected: Vec<usize> = vec![];
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut rand = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize % n
+        };
+
+        for step in 0..3000 {
+            if expected.is_empty() || rand(3) != 0 {
+                let len = rand(20) + 1;
+                let idx = rand(expected.len() + 1);
+                let node = match rand(3) {
+                    0 if idx == expected.len() => tree.push_back(piece(len)),
+                    1 if idx > 0 => tree.insert_after(idx - 1, piece(len)),
+                    _ => tree.insert(idx, piece(len)),
+                };
+                assert_eq!(node, idx);
+                expected.insert(idx, len);
+            } else if rand(4) == 0 {
+                let idx = rand(expected.len());
+                let len = rand(20) + 1;
+                let old = tree.replace(idx, piece(len));
+                assert_eq!(
+                    old.len_chars,
+                    std::mem::replace(&mut expected[idx], len)
+                );
+            } else {
+                let idx = rand(expected.len());
+                assert_eq!(tree.remove(idx).len_chars, expected.remove(idx));
+            }
+
+            if step % 50 == 0 {
+                check_tree(&tree);
+                assert_eq!(lens(&tree), expected);
+            }
+        }
+
+        check_tree(&tree);
+        for (idx, len) in expected.iter().enumerate() {
+            assert_eq!(tree.piece(idx).len_chars, *len);
+        }
+        assert_eq!(tree.last(), expected.len().checked_sub(1));
+        assert_eq!(tree.next(expected.len() - 1), None);
+
+        let mut offset = 0;
+        for (idx, len) in expected.iter().enumerate() {
+            for i in offset..offset + len {
+                let (before, found) = tree.node_at_char(i).unwrap();
+                assert_eq!(found, idx);
+                assert_eq!(before.chars, offset);
+            }
+            offset += len;
+        }
+        assert!(tree.node_at_char(offset).is_some());
+        assert!(tree.node_at_char(offset + 1).is_none());
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut tree = Tree::new();
+        for len in 1..=100 {
+            tree.push_back(piece(len));
+        }
+        let expected = lens(&tree);
+
+        let mut clones = vec![];
+        for i in 0..100 {
+            clones.push((tree.clone(), lens(&tree)));
+            tree.insert(i * 7 % tree.len(), piece(i + 1));
+            tree.remove(i * 13 % tree.len());
+            tree.replace(i * 3 % tree.len(), piece(i + 2));
+        }
+
+        check_tree(&tree);
+        for (clone, lens_then) in &clones {
+            check_tree(clone);
+            assert_eq!(&lens(clone), lens_then);
+        }
+        assert_eq!(clones[0].1, expected);
+
+        // Editing a clone doesn't affect the tree it was cloned from.
+        let lens_now = lens(&tree);
+        let (mut clone, _) = clones.pop().unwrap();
+        clone.remove(0);
+        clone.push_back(piece(1));
+        assert_eq!(lens(&tree), lens_now);
+    }
+}
@@ -0,0 +1,250 @@
+//! Checking the lengths a table caches against its text.
+
+use crate::PieceTable;
+use crate::str_utils;
+use crate::tree::Metrics;
+
+/// A unit the lengths of a table are measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Bytes,
+    Chars,
+    Utf16,
+    LineBreaks,
+    LspLineBreaks,
+}
+
+/// A length (or line break) which the table caches, but which does not match
+/// its text (see [`PieceTable::validate`]).
+///
+/// Pieces are the parts of the text the table is made of, indexed in the order
+/// of the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Inconsistency {
+    /// The cached length of a piece is not the length of its text.
+    Piece {
+        piece_idx: usize,
+        metric: Metric,
+        cached: usize,
+        actual: usize,
+    },
+    /// The line breaks of a piece (found through the index of its first line
+    /// break in its buffer) are not the line breaks of its text. `byte_idx` is
+    /// the index in the table of the first line break which differs.
+    PieceLineBreaks { piece_idx: usize, byte_idx: usize },
+    /// A piece ends with a CR and the next one starts with an LF, so they are
+    /// not counted as a single CRLF sequence. `byte_idx` is the index of the
+    /// CR in the table.
+    SplitCrlf { byte_idx: usize },
+    /// A length cached in the tree of pieces is not the sum of the lengths of
+    /// the pieces.
+    Tree {
+        metric: Metric,
+        cached: usize,
+        actual: usize,
+    },
+}
+
+impl PieceTable<'_> {
+    /// Recompute the lengths of the table (in bytes, chars, UTF-16 code units
+    /// and line breaks) and the positions of its line breaks from its text,
+    /// and return every cached value which does not match.
+    ///
+    /// This is meant for debugging and testing, as the cached values are
+    /// always expected to match.
+    ///
+    /// Runs in `O(N)` where `N` is the length of the table.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`Inconsistency`]s that were found, if there are any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use peace_table::PieceTable;
+    /// let mut pt = PieceTable::new("one\r\ntwo\nthree");
+    /// pt.remove(2..6);
+    /// pt.insert(4, "\r\n");
+    /// assert_eq!(pt.validate(), Ok(()));
+    /// ```
+    pub fn validate(&self) -> Result<(), Vec<Inconsistency>> {
+        let mut inconsistencies = vec![];
+        // The position of the start of the current piece.
+        let mut before = Metrics::default();
+        #[cfg(feature = "lines")]
+        let mut ends_with_cr = false;
+
+        for (piece_idx, piece) in self.tree.iter().enumerate() {
+            let text = piece.text(&self.buffers);
+            #[cfg(feature = "lines")]
+            let mut line_breaks = vec![];
+
+            let actual = Metrics {
+                bytes: text.len(),
+                chars: str_utils::count_chars(text),
+                utf16: str_utils::count_utf16(text),
+                #[cfg(feature = "lines")]
+                line_breaks: str_utils::line_breaks(
+                    text,
+                    &mut line_breaks,
+                    piece.start,
+                    self.buffers.line_break_config,
+                ),
+                #[cfg(feature = "lines")]
+                lsp_line_breaks: str_utils::lsp_line_breaks(text).count(),
+            };
+            let cached = piece.metrics(&self.buffers);
+            inconsistencies.extend(mismatches(cached, actual).map(
+                |(metric, cached, actual)| Inconsistency::Piece {
+                    piece_idx,
+                    metric,
+                    cached,
+                    actual,
+                },
+            ));
+
+            #[cfg(feature = "lines")]
+            {
+                let scanned = piece.scanned(&self.buffers);
+                let cached = match scanned.first_line_break {
+                    Some(first) => self
+                        .buffers
+                        .line_breaks(piece.buffer, piece.start)
+                        .get(first..first + scanned.len_line_breaks),
+                    None if scanned.len_line_breaks == 0 => Some(&[][..]),
+                    None => None,
+                };
+                let differs = match cached {
+                    Some(cached) if cached == line_breaks => None,
+                    Some(cached) => {
+                        let same = cached
+                            .iter()
+                            .zip(&line_breaks)
+                            .take_while(|(cached, actual)| cached == actual)
+                            .count();
+                        [cached.get(same), line_breaks.get(same)]
+                            .into_iter()
+                            .flatten()
+                            .map(|&(idx, _ty)| idx)
+                            .min()
+                    }
+                    None => Some(piece.start),
+                };
+                if let Some(idx) = differs {
+                    inconsistencies.push(Inconsistency::PieceLineBreaks {
+                        piece_idx,
+                        byte_idx: before.bytes
+                            + idx.saturating_sub(piece.start),
+                    });
+                }
+
+                if ends_with_cr && text.starts_with('\n') {
+                    inconsistencies.push(Inconsistency::SplitCrlf {
+                        byte_idx: before.bytes - 1,
+                    });
+                }
+                if !text.is_empty() {
+                    ends_with_cr = text.ends_with('\r');
+                }
+            }
+
+            before += cached;
+        }
+
+        if let Some((cached, actual)) = self.tree.check_metrics(&self.buffers) {
+            inconsistencies.extend(mismatches(cached, actual).map(
+                |(metric, cached, actual)| Inconsistency::Tree {
+                    metric,
+                    cached,
+                    actual,
+                },
+            ));
+        }
+
+        if inconsistencies.is_empty() { Ok(()) } else { Err(inconsistencies) }
+    }
+}
+
+/// The metrics in which `cached` and `actual` differ, with their values.
+fn mismatches(
+    cached: Metrics,
+    actual: Metrics,
+) -> impl Iterator<Item = (Metric, usize, usize)> {
+    [
+        (Metric::Bytes, cached.bytes, actual.bytes),
+        (Metric::Chars, cached.chars, actual.chars),
+        (Metric::Utf16, cached.utf16, actual.utf16),
+        #[cfg(feature = "lines")]
+        (Metric::LineBreaks, cached.line_breaks, actual.line_breaks),
+        #[cfg(feature = "lines")]
+        (
+            Metric::LspLineBreaks,
+            cached.lsp_line_breaks,
+            actual.lsp_line_breaks,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_metric, cached, actual)| cached != actual)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "lines")]
+    use std::sync::OnceLock;
+
+    use super::*;
+    #[cfg(feature = "lines")]
+    use crate::piece::Scanned;
+
+    #[test]
+    fn random_edits() {
+        let mut pt = PieceTable::new("a\r\nb\rc\nd\u{2028}é🦀\n");
+        pt.set_crlf_policy(crate::CrlfPolicy::Split);
+
+        for i in 0..500 {
+            let len = pt.len_chars();
+            let start = (i * 7919) % (len + 1);
+            if i % 3 == 0 {
+                pt.remove(start..(start + i % 7).min(len));
+            } else {
+                pt.insert(start, ["\r", "\n", "x\r\n", "é\ry", "🦀"][i % 5]);
+            }
+            assert_eq!(pt.validate(), Ok(()));
+        }
+
+        while pt.undo().is_some() {
+            assert_eq!(pt.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn corrupted_pieces() {
+        let mut pt = PieceTable::new("ab\ncd");
+        pt.insert(5, "\nef");
+        let node = pt.tree.last().unwrap();
+        let mut piece = pt.tree.piece(node).clone();
+        piece.len_chars += 1;
+        #[cfg(feature = "lines")]
+        {
+            let scanned = *piece.scanned(&pt.buffers);
+            piece.scanned =
+                OnceLock::from(Scanned { first_line_break: None, ..scanned });
+        }
+        pt.tree.replace(node, piece);
+
+        let inconsistencies = pt.validate().unwrap_err();
+        assert!(inconsistencies.contains(&Inconsistency::Piece {
+            piece_idx: 1,
+            metric: Metric::Chars,
+            cached: 4,
+            actual: 3,
+        }));
+        #[cfg(feature = "lines")]
+        assert!(inconsistencies.contains(&Inconsistency::PieceLineBreaks {
+            piece_idx: 1,
+            byte_idx: 5,
+        }));
+    }
+}
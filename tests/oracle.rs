@@ -0,0 +1,413 @@
+//! Differential tests, which apply random edits to both a [`PieceTable`] and a
+//! [`String`], and compare them after every edit.
+//!
+//! The tests run with any combination of the crate's features, checking the
+//! line related methods only when `lines` is enabled.
+
+use peace_table::{ChangeSet, CrlfPolicy, Error, PieceTable};
+#[cfg(feature = "lines")]
+use peace_table::{LineBreak, LineBreakConfig};
+use proptest::prelude::*;
+use proptest::sample::{Index, select};
+
+/// The fragments that texts are made of: plain, multi-byte and 4-byte chars,
+/// and every kind of line break.
+const FRAGMENTS: &[&str] = &[
+    "a", "xyz", "é", "⑤", "🦀", "\n", "\r", "\r\n", "\u{000B}", "\u{000C}",
+    "\u{0085}", "\u{2028}", "\u{2029}",
+];
+
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(Index, String),
+    Remove(Index, usize),
+    ReplaceAll(&'static str, String),
+    /// Edits made at once with a [`ChangeSet`], from pairs of indexes which
+    /// are sorted into ranges (skipping the ranges which overlap).
+    Apply(Vec<(Index, Index, String)>),
+    Undo,
+    Redo,
+}
+
+fn text() -> impl Strategy<Value = String> {
+    prop::collection::vec(select(FRAGMENTS), 0..6).prop_map(|v| v.concat())
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        6 => (any::<Index>(), text()).prop_map(|(i, t)| Op::Insert(i, t)),
+        3 => (any::<Index>(), 0..8_usize).prop_map(|(i, n)| Op::Remove(i, n)),
+        1 => (select(FRAGMENTS), text())
+            .prop_map(|(needle, r)| Op::ReplaceAll(needle, r)),
+        1 => prop::collection::vec((any::<Index>(), any::<Index>(), text()), 0..4)
+            .prop_map(Op::Apply),
+        1 => Just(Op::Undo),
+        1 => Just(Op::Redo),
+    ]
+}
+
+/// The expected text, along with the texts it can undo and redo to.
+struct Oracle {
+    states: Vec<String>,
+    current: usize,
+}
+
+impl Oracle {
+    fn text(&self) -> &str {
+        &self.states[self.current]
+    }
+
+    fn record(&mut self, text: String) {
+        self.states.truncate(self.current + 1);
+        self.states.push(text);
+        self.current += 1;
+    }
+
+    fn char_to_byte(&self, char_idx: usize) -> usize {
+        self.text()
+            .char_indices()
+            .map(|(idx, _ch)| idx)
+            .nth(char_idx)
+            .unwrap_or(self.text().len())
+    }
+
+    /// Whether `char_idx` is between the CR and the LF of a CRLF sequence.
+    fn splits_crlf(&self, char_idx: usize) -> bool {
+        let byte_idx = self.char_to_byte(char_idx);
+        let bytes = self.text().as_bytes();
+        byte_idx > 0
+            && bytes[byte_idx - 1] == b'\r'
+            && bytes.get(byte_idx) == Some(&b'\n')
+    }
+}
+
+/// Split `text` into its lines, each with its line break (the last line has
+/// none), according to `config`.
+#[cfg(feature = "lines")]
+fn lines(text: &str, config: LineBreakConfig) -> Vec<(&str, Option<&str>)> {
+    let mut lines = vec![];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((idx, ch)) = chars.next() {
+        let len = match ch {
+            '\r' if config != LineBreakConfig::Lf
+                && chars.peek().is_some_and(|&(_idx, ch)| ch == '\n') =>
+            {
+                chars.next();
+                2
+            }
+            '\n' => 1,
+            '\u{000B}' | '\u{000C}' | '\r' | '\u{0085}' | '\u{2028}'
+            | '\u{2029}'
+                if is_unicode(config) =>
+            {
+                ch.len_utf8()
+            }
+            _ => continue,
+        };
+        lines.push((&text[start..idx], Some(&text[idx..idx + len])));
+        start = idx + len;
+    }
+
+    lines.push((&text[start..], None));
+    lines
+}
+
+#[cfg(all(feature = "lines", feature = "unicode-line-breaks"))]
+fn is_unicode(config: LineBreakConfig) -> bool {
+    config == LineBreakConfig::Unicode
+}
+
+#[cfg(all(feature = "lines", not(feature = "unicode-line-breaks")))]
+fn is_unicode(_config: LineBreakConfig) -> bool {
+    false
+}
+
+fn check(pt: &PieceTable, expected: &str) -> Result<(), TestCaseError> {
+    prop_assert_eq!(pt.text(), expected);
+    prop_assert_eq!(pt.validate(), Ok(()));
+
+    prop_assert_eq!(pt.len_bytes(), expected.len());
+    prop_assert_eq!(pt.len_chars(), expected.chars().count());
+    prop_assert_eq!(pt.len_utf16(), expected.encode_utf16().count());
+
+    prop_assert_eq!(pt.iter().collect::<String>(), expected);
+    prop_assert_eq!(pt.iter().rev().collect::<Vec<_>>().concat(), {
+        let chunks: Vec<_> = pt.iter().collect();
+        chunks.into_iter().rev().collect::<String>()
+    });
+    prop_assert!(pt.chars_at(0).eq(expected.chars()));
+    prop_assert!(pt.bytes_at(0).eq(expected.bytes()));
+
+    for needle in ["\r\n", "aa", "é🦀", "\n\r"] {
+        let to_range = |(idx, _m): (usize, &str)| {
+            let start = expected[..idx].chars().count();
+            start..start + needle.chars().count()
+        };
+        prop_assert!(
+            pt.find_iter(needle)
+                .eq(expected.match_indices(needle).map(to_range))
+        );
+        prop_assert!(
+            pt.rfind_iter(needle)
+                .eq(expected.rmatch_indices(needle).map(to_range))
+        );
+    }
+
+    let mut chars = pt.chars_at(pt.len_chars());
+    prop_assert!(
+        std::iter::from_fn(|| chars.prev()).eq(expected.chars().rev())
+    );
+
+    for (char_idx, (byte_idx, _ch)) in expected.char_indices().enumerate() {
+        prop_assert_eq!(pt.char_to_byte(char_idx), byte_idx);
+        prop_assert_eq!(pt.byte_to_char(byte_idx), char_idx);
+        prop_assert!(pt.chars_at(char_idx).eq(expected[byte_idx..].chars()));
+    }
+
+    #[cfg(feature = "lines")]
+    {
+        let lines = lines(expected, pt.line_break_config());
+        prop_assert_eq!(pt.len_lines(), lines.len());
+
+        let mut line_start = 0;
+        for (line_idx, &(line, ending)) in lines.iter().enumerate() {
+            prop_assert_eq!(pt.line(line_idx).to_string(), line);
+            prop_assert_eq!(
+                pt.line_with_ending(line_idx).to_string(),
+                format!("{line}{}", ending.unwrap_or_default())
+            );
+            prop_assert_eq!(
+                pt.line_ending(line_idx).as_ref().map(LineBreak::as_str),
+                ending
+            );
+            prop_assert_eq!(pt.line_to_byte(line_idx), line_start);
+            prop_assert_eq!(pt.byte_to_line(line_start), line_idx);
+            line_start += line.len() + ending.map_or(0, str::len);
+        }
+
+        let expected_lines: Vec<_> = lines.iter().map(|l| l.0).collect();
+        let actual: Vec<_> = pt.lines_at(0).map(|l| l.to_string()).collect();
+        prop_assert_eq!(&actual, &expected_lines);
+        let actual: Vec<_> =
+            pt.lines_at(0).rev().map(|l| l.to_string()).collect();
+        prop_assert!(actual.iter().eq(expected_lines.iter().rev()));
+    }
+
+    Ok(())
+}
+
+fn run(
+    initial: &str,
+    ops: Vec<Op>,
+    policy: CrlfPolicy,
+    config: Config,
+) -> Result<(), TestCaseError> {
+    let mut pt = new_table(initial, config);
+    pt.set_crlf_policy(policy);
+    let mut oracle = Oracle { states: vec![initial.to_owned()], current: 0 };
+    check(&pt, oracle.text())?;
+
+    for op in ops {
+        let len = oracle.text().chars().count();
+        match op {
+            Op::Insert(idx, text) => {
+                let char_idx = idx.index(len + 1);
+                let result = pt.try_insert(char_idx, &text);
+                if policy == CrlfPolicy::Reject
+                    && !text.is_empty()
+                    && oracle.splits_crlf(char_idx)
+                {
+                    prop_assert_eq!(result, Err(Error::CrlfSplit { char_idx }));
+                    continue;
+                }
+                prop_assert_eq!(result, Ok(()));
+                if !text.is_empty() {
+                    let byte_idx = oracle.char_to_byte(char_idx);
+                    let mut new = oracle.text().to_owned();
+                    new.insert_str(byte_idx, &text);
+                    oracle.record(new);
+                }
+            }
+            Op::Remove(idx, n) => {
+                let start = idx.index(len + 1);
+                let end = (start + n).min(len);
+                let result = pt.try_remove(start..end);
+                if policy == CrlfPolicy::Reject
+                    && start < end
+                    && (oracle.splits_crlf(start) || oracle.splits_crlf(end))
+                {
+                    let char_idx =
+                        if oracle.splits_crlf(start) { start } else { end };
+                    prop_assert_eq!(result, Err(Error::CrlfSplit { char_idx }));
+                    continue;
+                }
+                prop_assert_eq!(result, Ok(()));
+                if start < end {
+                    let range =
+                        oracle.char_to_byte(start)..oracle.char_to_byte(end);
+                    let mut new = oracle.text().to_owned();
+                    new.replace_range(range, "");
+                    oracle.record(new);
+                }
+            }
+            Op::ReplaceAll(needle, replacement) => {
+                let result = pt.try_replace_all(needle, &replacement);
+                let text = oracle.text();
+                let split = text.match_indices(needle).find_map(|(idx, m)| {
+                    let start = text[..idx].chars().count();
+                    let end = start + m.chars().count();
+                    if oracle.splits_crlf(start) {
+                        Some(start)
+                    } else if start < end && oracle.splits_crlf(end) {
+                        Some(end)
+                    } else {
+                        None
+                    }
+                });
+                if let (CrlfPolicy::Reject, Some(char_idx)) = (policy, split) {
+                    prop_assert_eq!(result, Err(Error::CrlfSplit { char_idx }));
+                    continue;
+                }
+
+                let ranges = result.unwrap();
+                let count = oracle.text().matches(needle).count();
+                prop_assert_eq!(ranges.len(), count);
+                if count > 0 {
+                    oracle.record(oracle.text().replace(needle, &replacement));
+                }
+            }
+            Op::Apply(edits) => {
+                let mut ranges: Vec<_> = edits
+                    .into_iter()
+                    .map(|(a, b, text)| {
+                        let (a, b) = (a.index(len + 1), b.index(len + 1));
+                        (a.min(b)..a.max(b), text)
+                    })
+                    .collect();
+                ranges.sort_by_key(|(range, _text)| range.start);
+                let mut prev_end = 0;
+                ranges.retain(|(range, _text)| {
+                    let disjoint = range.start >= prev_end;
+                    prev_end = prev_end.max(range.end);
+                    disjoint
+                });
+                let changes =
+                    ChangeSet::from_edits(len, ranges.iter().cloned()).unwrap();
+
+                // Adjacent edits are a single edit of the change set.
+                let mut merged: Vec<(std::ops::Range<usize>, String)> = vec![];
+                for (range, text) in ranges {
+                    match merged.last_mut() {
+                        _ if range.is_empty() && text.is_empty() => {}
+                        Some((last, last_text)) if last.end == range.start => {
+                            last.end = range.end;
+                            last_text.push_str(&text);
+                        }
+                        _ => merged.push((range, text)),
+                    }
+                }
+                let ranges = merged;
+
+                let result = pt.try_apply(&changes);
+                let split = ranges.iter().find_map(|(range, _text)| {
+                    if oracle.splits_crlf(range.start) {
+                        Some(range.start)
+                    } else if !range.is_empty() && oracle.splits_crlf(range.end)
+                    {
+                        Some(range.end)
+                    } else {
+                        None
+                    }
+                });
+                if let (CrlfPolicy::Reject, Some(char_idx)) = (policy, split) {
+                    prop_assert_eq!(result, Err(Error::CrlfSplit { char_idx }));
+                    continue;
+                }
+                prop_assert_eq!(result, Ok(()));
+
+                let mut new = oracle.text().to_owned();
+                for (range, text) in ranges.iter().rev() {
+                    let range = oracle.char_to_byte(range.start)
+                        ..oracle.char_to_byte(range.end);
+                    new.replace_range(range, text);
+                }
+                if !changes.is_identity() {
+                    oracle.record(new);
+                }
+            }
+            Op::Undo => {
+                let undone = pt.undo().is_some();
+                prop_assert_eq!(undone, oracle.current > 0);
+                oracle.current -= usize::from(undone);
+            }
+            Op::Redo => {
+                let redone = pt.redo().is_some();
+                prop_assert_eq!(
+                    redone,
+                    oracle.current + 1 < oracle.states.len()
+                );
+                oracle.current += usize::from(redone);
+            }
+        }
+        check(&pt, oracle.text())?;
+    }
+
+    Ok(())
+}
+
+/// The line breaks to configure the tables with, if the `lines` feature is
+/// enabled.
+#[cfg(feature = "lines")]
+type Config = LineBreakConfig;
+#[cfg(not(feature = "lines"))]
+type Config = ();
+
+#[cfg(feature = "lines")]
+fn config() -> impl Strategy<Value = Config> {
+    prop::sample::select(vec![
+        LineBreakConfig::default(),
+        LineBreakConfig::Lf,
+        LineBreakConfig::LfCrlf,
+        #[cfg(feature = "unicode-line-breaks")]
+        LineBreakConfig::Unicode,
+    ])
+}
+
+#[cfg(not(feature = "lines"))]
+fn config() -> impl Strategy<Value = Config> {
+    Just(())
+}
+
+#[cfg(feature = "lines")]
+fn new_table(initial: &str, config: Config) -> PieceTable<'_> {
+    PieceTable::new_with_line_breaks(initial, config)
+}
+
+#[cfg(not(feature = "lines"))]
+fn new_table(initial: &str, _config: Config) -> PieceTable<'_> {
+    PieceTable::new(initial)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn split_crlf(
+        initial in text(),
+        ops in prop::collection::vec(op(), 0..40),
+        config in config(),
+    ) {
+        run(&initial, ops, CrlfPolicy::Split, config)?;
+    }
+
+    #[test]
+    fn reject_crlf(
+        initial in text(),
+        ops in prop::collection::vec(op(), 0..40),
+        config in config(),
+    ) {
+        run(&initial, ops, CrlfPolicy::Reject, config)?;
+    }
+}